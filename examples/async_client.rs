@@ -0,0 +1,62 @@
+// Sends an HTTP request to a random ALIVE member of a Serverset every second, picking
+// from the latest membership each time so that backends are re-resolved as they come and
+// go. Configured by DISCO_-prefixed environment variables, e.g.
+//
+//   DISCO_ZK_HOSTS=localhost DISCO_SERVERSET_ZNODE=/discovery/web \
+//       cargo run --example async_client --features async
+
+use discotech::{AsyncServerset, DiscoConfig, ServiceEndpoint};
+
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+
+// Sends a bare HTTP/1.0 GET for `path`, returning the status line of the response.
+async fn get(endpoint: &ServiceEndpoint, path: &str) -> io::Result<String> {
+  let mut stream = TcpStream::connect(endpoint.addr()).await?;
+  let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, endpoint.host);
+  stream.write_all(request.as_bytes()).await?;
+  let mut response = Vec::new();
+  stream.read_to_end(&mut response).await?;
+  let response = String::from_utf8_lossy(&response);
+  Ok(response.lines().next().unwrap_or("").to_string())
+}
+
+
+#[tokio::main]
+async fn main() {
+  let config = DiscoConfig::from_env().expect("Invalid DISCO_ configuration");
+  let serverset = AsyncServerset::new(config).await
+    .expect("Could not connect to ZooKeeper");
+  match serverset.wait_for_members(1, Duration::from_secs(30)).await {
+    Ok(members) => println!("Found {} members", members),
+    Err(reason) => println!("{}; requests will fail until a member joins", reason),
+  }
+
+  // Logs the membership whenever it changes, alongside the requests.
+  let mut changes = serverset.changes();
+  tokio::spawn(async move {
+    while changes.changed().await.is_ok() {
+      let mut endpoints: Vec<String> = changes.borrow().values()
+        .map(|member| member.serviceEndpoint.addr())
+        .collect();
+      endpoints.sort();
+      println!("Members changed: {:?}", endpoints);
+    }
+  });
+
+  let mut ticks = time::interval(Duration::from_secs(1));
+  loop {
+    ticks.tick().await;
+    match serverset.serverset().pick_random() {
+      Some(endpoint) => match get(&endpoint, "/").await {
+        Ok(status) => println!("{}: {}", endpoint.addr(), status),
+        Err(reason) => println!("{}: {}", endpoint.addr(), reason),
+      },
+      None => println!("No ALIVE members to send to"),
+    }
+  }
+}
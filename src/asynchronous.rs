@@ -0,0 +1,266 @@
+use config::DiscoConfig;
+use error::DiscoError;
+use serverset::{Serverset, ServersetMember, ServiceEndpoint, WatchHandle, ZnodeEvent};
+
+use futures::{FutureExt, Stream};
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::{self, JoinHandle};
+use tokio::time::{self, Sleep};
+
+
+// The membership of a Serverset, yielded once on creation and again after every change.
+// Dropping the stream stops the watch loop it started.
+pub struct MemberStream {
+  members_rx: UnboundedReceiver<Vec<ServersetMember>>,
+  _watch: WatchHandle,
+}
+
+impl Stream for MemberStream {
+  type Item = Vec<ServersetMember>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+    self.get_mut().members_rx.poll_recv(cx)
+  }
+}
+
+
+impl Serverset {
+  // Starts watching the Serverset like watch, returning a stream of its membership.
+  pub fn watch_async(self: &Arc<Self>) -> MemberStream {
+    let (members_tx, members_rx) = unbounded_channel();
+    let events = self.events();
+    let mut published = self.members();
+    let _ = members_tx.send(published.values().cloned().collect());
+    // Holds only a weak reference, so the Serverset can still be dropped; the forwarding
+    // thread then exits once its event channel closes.
+    let serverset = Arc::downgrade(self);
+    thread::spawn(move || for _ in events.iter() {
+      let members = match serverset.upgrade() {
+        Some(serverset) => serverset.members(),
+        None => return,
+      };
+      // A pass publishes its members at once and then sends an event per change, so one
+      // snapshot covers every event of the pass.
+      if Arc::ptr_eq(&members, &published) {
+        continue
+      }
+      published = members;
+      if members_tx.send(published.values().cloned().collect()).is_err() {
+        return
+      }
+    });
+    MemberStream{
+      members_rx: members_rx,
+      _watch: self.watch(),
+    }
+  }
+
+  // Like pick_random, which never blocks.
+  pub fn pick_random_async(&self) -> future::Ready<Option<ServiceEndpoint>> {
+    future::ready(self.pick_random())
+  }
+
+  // Like announce, run on tokio's blocking thread pool. Must be called from within a
+  // tokio runtime.
+  pub fn announce_async(self: &Arc<Self>, endpoint: ServiceEndpoint,
+      additional: HashMap<String, ServiceEndpoint>)
+      -> impl Future<Output = Result<String, DiscoError>> {
+    let serverset = self.clone();
+    task::spawn_blocking(move || serverset.announce(endpoint, additional))
+      .map(|announced| match announced {
+        Ok(announced) => announced,
+        Err(reason) => Err(DiscoError::TaskFailed(reason.to_string())),
+      })
+  }
+}
+
+
+// A Serverset for async services, kept up to date by a tokio task rather than a watch
+// loop's own thread. Each pass still uses the blocking ZooKeeper client, so it runs on
+// tokio's blocking thread pool, and nothing here blocks the executor. The task wakes for
+// watch notifications as they arrive and polls every zookeeper_poll_ms, and needs tokio's
+// time driver. Dropping the AsyncServerset stops it.
+pub struct AsyncServerset {
+  serverset: Arc<Serverset>,
+  members_rx: watch::Receiver<Arc<HashMap<String, ServersetMember>>>,
+  sync_task: JoinHandle<()>,
+}
+impl AsyncServerset {
+  // Connects like Serverset::new, on tokio's blocking thread pool, and starts syncing.
+  // Must be awaited within a tokio runtime.
+  pub fn new(discoConfig: DiscoConfig)
+      -> impl Future<Output = Result<AsyncServerset, DiscoError>> {
+    task::spawn_blocking(move || Serverset::new(discoConfig))
+      .map(|connected| match connected {
+        Ok(connected) => connected.map(|serverset| {
+          AsyncServerset::from_serverset(Arc::new(serverset))
+        }),
+        Err(reason) => Err(DiscoError::TaskFailed(reason.to_string())),
+      })
+  }
+
+  // Starts syncing an existing Serverset, e.g. one over a DiscoveryBackend, which must
+  // not also be watched: the task takes the session's watch notifications, leaving a
+  // later watch loop only its polls. Must be called within a tokio runtime.
+  pub fn from_serverset(serverset: Arc<Serverset>) -> AsyncServerset {
+    let (members_tx, members_rx) = watch::channel(serverset.members());
+    let events_rx = serverset.take_events().map(|events| {
+      let (events_tx, events_rx) = unbounded_channel();
+      thread::spawn(move || forward_events(events, events_tx));
+      events_rx
+    });
+    let sync_task = task::spawn(SyncLoop{
+      serverset: serverset.clone(),
+      members_tx: members_tx,
+      events_rx: events_rx,
+      pending_events: Vec::new(),
+      state: SyncState::Due,
+    });
+    AsyncServerset{
+      serverset: serverset,
+      members_rx: members_rx,
+      sync_task: sync_task,
+    }
+  }
+
+  // The underlying Serverset, for pickers, registration and everything else that does
+  // not block.
+  pub fn serverset(&self) -> &Arc<Serverset> {
+    &self.serverset
+  }
+
+  // Returns the membership as of the latest pass.
+  pub fn members(&self) -> Arc<HashMap<String, ServersetMember>> {
+    self.members_rx.borrow().clone()
+  }
+
+  // Returns a receiver that is marked changed whenever a pass changes the membership,
+  // for re-resolving backends as they come and go.
+  pub fn changes(&self) -> watch::Receiver<Arc<HashMap<String, ServersetMember>>> {
+    self.members_rx.clone()
+  }
+
+  // Like Serverset::wait_for_members, waiting on tokio's blocking thread pool.
+  pub fn wait_for_members(&self, min_members: usize, timeout: Duration)
+      -> impl Future<Output = Result<usize, DiscoError>> {
+    let serverset = self.serverset.clone();
+    task::spawn_blocking(move || serverset.wait_for_members(min_members, timeout))
+      .map(|waited| match waited {
+        Ok(waited) => waited.map_err(DiscoError::from),
+        Err(reason) => Err(DiscoError::TaskFailed(reason.to_string())),
+      })
+  }
+}
+
+impl Drop for AsyncServerset {
+  fn drop(&mut self) {
+    self.sync_task.abort();
+  }
+}
+
+
+// Hands the session's watch notifications to a SyncLoop as they arrive, until either end
+// goes away.
+fn forward_events(events: Receiver<ZnodeEvent>, events_tx: UnboundedSender<ZnodeEvent>) {
+  for event in events.iter() {
+    if events_tx.send(event).is_err() {
+      return
+    }
+  }
+}
+
+
+// Runs Serverset::poll_once on the blocking thread pool, sleeping for as long as it says
+// between passes or until a watch notification arrives, and publishes the members after
+// each.
+struct SyncLoop {
+  serverset: Arc<Serverset>,
+  members_tx: watch::Sender<Arc<HashMap<String, ServersetMember>>>,
+  // None once the notifications stop, or if there never were any.
+  events_rx: Option<UnboundedReceiver<ZnodeEvent>>,
+  // Notifications received since the last pass, for the next to handle.
+  pending_events: Vec<ZnodeEvent>,
+  state: SyncState,
+}
+
+impl SyncLoop {
+  // Moves the notifications received so far into pending_events, registering to be woken
+  // for the next.
+  fn receive_events(&mut self, cx: &mut Context) {
+    let mut closed = false;
+    if let Some(ref mut events_rx) = self.events_rx {
+      loop {
+        match events_rx.poll_recv(cx) {
+          Poll::Ready(Some(event)) => self.pending_events.push(event),
+          Poll::Ready(None) => {
+            closed = true;
+            break
+          },
+          Poll::Pending => break,
+        }
+      }
+    }
+    if closed {
+      self.events_rx = None;
+    }
+  }
+}
+
+enum SyncState {
+  Due,
+  Syncing(JoinHandle<Duration>),
+  Sleeping(Pin<Box<Sleep>>),
+}
+
+impl Future for SyncLoop {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+    let sync_loop = self.get_mut();
+    loop {
+      // A notification cuts the sleep short; one arriving mid-pass waits for the next.
+      if let SyncState::Sleeping(_) = sync_loop.state {
+        sync_loop.receive_events(cx);
+        if !sync_loop.pending_events.is_empty() {
+          sync_loop.state = SyncState::Due;
+        }
+      }
+      let next_state = match sync_loop.state {
+        SyncState::Due => {
+          let serverset = sync_loop.serverset.clone();
+          let events = mem::replace(&mut sync_loop.pending_events, Vec::new());
+          SyncState::Syncing(task::spawn_blocking(move || serverset.poll_once(events)))
+        },
+        SyncState::Syncing(ref mut pass) => match Pin::new(pass).poll(cx) {
+          Poll::Pending => return Poll::Pending,
+          // The pass panicked, or the runtime is shutting down.
+          Poll::Ready(Err(_)) => return Poll::Ready(()),
+          Poll::Ready(Ok(delay)) => {
+            let members = sync_loop.serverset.members();
+            // A pass that changed nothing leaves the members as they were.
+            let changed = !Arc::ptr_eq(&members, &*sync_loop.members_tx.borrow());
+            if changed && sync_loop.members_tx.send(members).is_err() {
+              return Poll::Ready(())
+            }
+            SyncState::Sleeping(Box::pin(time::sleep(delay)))
+          },
+        },
+        SyncState::Sleeping(ref mut sleep) => match sleep.as_mut().poll(cx) {
+          Poll::Pending => return Poll::Pending,
+          Poll::Ready(()) => SyncState::Due,
+        },
+      };
+      sync_loop.state = next_state;
+    }
+  }
+}
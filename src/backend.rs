@@ -0,0 +1,123 @@
+use error::DiscoError;
+
+use serde_json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::time::SystemTime;
+
+
+// A source of Serverset members other than ZooKeeper, such as a file, a fixed fleet, or
+// an in-memory list in tests. A Serverset created with Serverset::with_backend lists its
+// members from the backend on every reconciliation pass, decoding each with its codec,
+// and otherwise behaves as one watching ZooKeeper: it drives the same members map,
+// listeners, events and pickers. ZooKeeper itself stays built in, as reading it keeps
+// per-member versions and watches this interface has no use for.
+pub trait DiscoveryBackend: Send + Sync {
+  // Returns the name, e.g. member_0000000001, and encoded data of every current member.
+  // Failing leaves the members as they were until the next pass.
+  fn list_members(&self) -> Result<Vec<(String, Vec<u8>)>, DiscoError>;
+
+  // Returns a channel that receives a message whenever the members may have changed, so
+  // that they are listed again without waiting for the next poll. Called once each time
+  // the Serverset is watched. Backends that cannot tell return None and are polled every
+  // zookeeper_poll_ms.
+  fn changes(&self) -> Option<Receiver<()>> {
+    None
+  }
+}
+
+
+// What a FileBackend found when it last looked at its file.
+enum FileState {
+  Unread,
+  Missing,
+  // The file's modification time and the members it listed.
+  Read(SystemTime, Vec<(String, Vec<u8>)>),
+}
+
+
+// Members read from a JSON file, e.g. for local development or air-gapped test
+// environments. The file holds an object mapping member names to members in the format
+// announced in ZooKeeper, as in test/fixtures/file_backend.json:
+//
+//   {"member_0000000001": {"serviceEndpoint": {"host": "10.0.0.1", "port": 9090},
+//       "additionalEndpoints": {}, "status": "ALIVE"}}
+//
+// Each poll checks the file's modification time and re-reads it only if that changed.
+// Members that cannot be decoded are reported and skipped, and a missing file holds no
+// members.
+pub struct FileBackend {
+  path: PathBuf,
+  last_read: Mutex<FileState>,
+}
+impl FileBackend {
+  pub fn new(path: &Path) -> FileBackend {
+    FileBackend{
+      path: path.to_path_buf(),
+      last_read: Mutex::new(FileState::Unread),
+    }
+  }
+
+  // Lists the member entries in the file's contents. The members are kept as JSON
+  // values, so that a malformed member fails only its own decode, not the whole file.
+  fn parse(&self, contents: &str) -> Result<Vec<(String, Vec<u8>)>, DiscoError> {
+    let parsed: Result<BTreeMap<String, serde_json::Value>, _> =
+        serde_json::from_str(contents);
+    let members = match parsed {
+      Ok(members) => members,
+      Err(reason) => return Err(self.error(reason)),
+    };
+    let mut listed = Vec::new();
+    for (member_znode, member) in members {
+      match serde_json::to_vec(&member) {
+        Ok(member_data) => listed.push((member_znode, member_data)),
+        Err(reason) => return Err(self.error(reason)),
+      }
+    }
+    Ok(listed)
+  }
+
+  fn error<E: fmt::Display>(&self, reason: E) -> DiscoError {
+    DiscoError::Backend(format!("{}: {}", self.path.display(), reason))
+  }
+}
+
+impl DiscoveryBackend for FileBackend {
+  fn list_members(&self) -> Result<Vec<(String, Vec<u8>)>, DiscoError> {
+    let mut last_read = self.last_read.lock().unwrap();
+    let mut file = match File::open(&self.path) {
+      Ok(file) => file,
+      Err(ref reason) if reason.kind() == ErrorKind::NotFound => {
+        match *last_read {
+          FileState::Missing => (),
+          _ => warn!("Member file {} does not exist; it holds no members",
+              self.path.display()),
+        }
+        *last_read = FileState::Missing;
+        return Ok(Vec::new())
+      },
+      Err(reason) => return Err(self.error(reason)),
+    };
+    let modified = match file.metadata().and_then(|metadata| metadata.modified()) {
+      Ok(modified) => modified,
+      Err(reason) => return Err(self.error(reason)),
+    };
+    match *last_read {
+      FileState::Read(last_modified, ref listed) if last_modified == modified =>
+          return Ok(listed.clone()),
+      _ => (),
+    }
+    let mut contents = String::new();
+    if let Err(reason) = file.read_to_string(&mut contents) {
+      return Err(self.error(reason))
+    }
+    let listed = try!(self.parse(&contents));
+    *last_read = FileState::Read(modified, listed.clone());
+    Ok(listed)
+  }
+}
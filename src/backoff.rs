@@ -0,0 +1,103 @@
+use rand;
+use rand::Rng;
+use std::cmp;
+use std::time::{Duration, Instant};
+
+
+// How long to wait between retries of a failing operation: `initial` after the first
+// failure, growing by `multiplier` with each further one up to `max`, and varied either
+// way by up to `jitter`, a fraction of the delay, so that clients do not retry in step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffPolicy {
+  pub initial: Duration,
+  pub multiplier: f64,
+  pub max: Duration,
+  pub jitter: f64,
+}
+impl BackoffPolicy {
+  // Returns the delay, before jitter, after the given number of consecutive failures.
+  pub fn delay(&self, failures: u32) -> Duration {
+    if failures == 0 {
+      return Duration::from_millis(0)
+    }
+    let initial_ms = duration_ms(self.initial) as f64;
+    let max_ms = duration_ms(self.max);
+    let delay_ms = initial_ms * self.multiplier.powi(cmp::min(failures - 1, 64) as i32);
+    if delay_ms >= max_ms as f64 {
+      return self.max
+    }
+    Duration::from_millis(delay_ms as u64)
+  }
+}
+
+
+// How far a retried operation has backed off, as reported by Serverset::poll_backoff.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffState {
+  pub consecutive_failures: u32,
+  // The jittered delay chosen after the latest failure, or zero after a success.
+  pub delay: Duration,
+}
+
+
+// Tracks the consecutive failures of an operation and when it is next due.
+pub struct Backoff {
+  policy: BackoffPolicy,
+  state: BackoffState,
+  retry_at: Option<Instant>,
+}
+impl Backoff {
+  pub fn new(policy: BackoffPolicy) -> Backoff {
+    Backoff{
+      policy: policy,
+      state: BackoffState{
+        consecutive_failures: 0,
+        delay: Duration::from_millis(0),
+      },
+      retry_at: None,
+    }
+  }
+
+  // Records a failure, returning how long to wait before retrying.
+  pub fn fail(&mut self) -> Duration {
+    self.state.consecutive_failures = self.state.consecutive_failures.saturating_add(1);
+    let delay = self.policy.delay(self.state.consecutive_failures);
+    self.state.delay = jittered(delay, self.policy.jitter);
+    self.retry_at = Some(Instant::now() + self.state.delay);
+    self.state.delay
+  }
+
+  pub fn succeed(&mut self) {
+    self.state.consecutive_failures = 0;
+    self.state.delay = Duration::from_millis(0);
+    self.retry_at = None;
+  }
+
+  // Returns true unless the delay chosen after the latest failure has yet to pass.
+  pub fn is_due(&self) -> bool {
+    match self.retry_at {
+      Some(retry_at) => Instant::now() >= retry_at,
+      None => true,
+    }
+  }
+
+  pub fn state(&self) -> BackoffState {
+    self.state
+  }
+}
+
+
+// Varies `delay` either way by a random amount of up to `jitter`, a fraction of it, e.g.
+// so that clients started together do not keep polling in step.
+pub fn jittered(delay: Duration, jitter: f64) -> Duration {
+  if jitter <= 0.0 {
+    return delay
+  }
+  let spread = rand::thread_rng().gen_range(-jitter, jitter);
+  Duration::from_millis((duration_ms(delay) as f64 * (1.0 + spread)) as u64)
+}
+
+
+fn duration_ms(duration: Duration) -> u64 {
+  duration.as_secs() * 1000 + (duration.subsec_nanos() / 1000000) as u64
+}
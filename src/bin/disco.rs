@@ -0,0 +1,310 @@
+// Lists, watches and announces the members of a Serverset from the command line, using
+// only the library's public API. Run with no arguments for usage.
+
+extern crate discotech;
+extern crate serde_json;
+
+use discotech::{DiscoConfig, DiscoError, MemberStatus, Serverset, ServersetEvent,
+    ServersetMember, ServiceEndpoint, ZkServers};
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io::{self, BufRead};
+use std::process;
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+// The Serverset was read and has members, or the command ran to completion.
+const EXIT_OK: i32 = 0;
+// The command line or configuration was invalid.
+const EXIT_USAGE: i32 = 1;
+// ZooKeeper could not be reached, or refused us, before the timeout.
+const EXIT_UNREACHABLE: i32 = 2;
+// ZooKeeper was reached, but the Serverset has no members or its ZNode does not exist.
+const EXIT_EMPTY: i32 = 3;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+const USAGE: &'static str = "\
+Usage: disco <command> --zk <host:port,...> --znode <path> [options]
+
+Commands:
+  list       Print a table of the members, or JSON with --json
+  dump       Print the members as JSON, keyed by ZNode name
+  watch      Print members as they join (+), change (~) and leave (-)
+  announce   Register a member and hold it until stdin closes or Enter is pressed
+
+Options:
+  --zk <servers>          ZooKeeper servers, e.g. zk1:2181,zk2:2181
+  --znode <path>          The Serverset ZNode, e.g. /services/foo/prod
+  --chroot <path>         A chroot to connect under
+  --timeout-secs <secs>   How long to wait to reach ZooKeeper (default 10)
+  --json                  With list, print JSON rather than a table
+  --host <host>           With announce, the member's host
+  --port <port>           With announce, the member's port
+  --status <status>       With announce, the member's status (default ALIVE)
+
+Exit codes: 0 on success, 1 on bad usage, 2 if ZooKeeper could not be reached, and 3
+if the Serverset was read but is empty or missing.";
+
+
+struct Options {
+  command: String,
+  flags: HashMap<String, String>,
+  json: bool,
+}
+impl Options {
+  fn parse(args: Vec<String>) -> Result<Options, String> {
+    let mut args = args.into_iter();
+    let command = match args.next() {
+      Some(command) => command,
+      None => return Err("No command given".to_string()),
+    };
+    let mut flags = HashMap::new();
+    let mut json = false;
+    while let Some(arg) = args.next() {
+      if arg == "--json" {
+        json = true;
+        continue
+      }
+      if !arg.starts_with("--") {
+        return Err(format!("Unexpected argument: {}", arg))
+      }
+      match args.next() {
+        Some(value) => flags.insert(arg[2..].to_string(), value),
+        None => return Err(format!("Missing value for {}", arg)),
+      };
+    }
+    Ok(Options{
+      command: command,
+      flags: flags,
+      json: json,
+    })
+  }
+
+  fn required(&self, flag: &str) -> Result<&str, String> {
+    match self.flags.get(flag) {
+      Some(value) => Ok(value.as_str()),
+      None => Err(format!("Missing required option --{}", flag)),
+    }
+  }
+
+  fn timeout(&self) -> Result<Duration, String> {
+    match self.flags.get("timeout-secs") {
+      Some(secs) => secs.parse()
+        .map(Duration::from_secs)
+        .map_err(|_| format!("Invalid --timeout-secs: {}", secs)),
+      None => Ok(Duration::from_secs(DEFAULT_TIMEOUT_SECS)),
+    }
+  }
+
+  fn config(&self) -> Result<DiscoConfig, String> {
+    // Validated by build, which reports a malformed server list.
+    let servers = ZkServers::ConnectString(try!(self.required("zk")).to_string());
+    let mut builder = DiscoConfig::builder()
+      .zookeeper_servers(servers)
+      .serverset_znode(try!(self.required("znode")))
+      // An operator wants to see every member, not just those a client would use.
+      .status_filter(vec!["all".to_string()]);
+    if let Some(chroot) = self.flags.get("chroot") {
+      builder = builder.zookeeper_chroot(chroot);
+    }
+    builder.build().map_err(|reason| reason.to_string())
+  }
+}
+
+
+fn main() {
+  let options = match Options::parse(env::args().skip(1).collect()) {
+    Ok(options) => options,
+    Err(reason) => usage(&reason),
+  };
+  let config = match options.config() {
+    Ok(config) => config,
+    Err(reason) => usage(&reason),
+  };
+  let timeout = match options.timeout() {
+    Ok(timeout) => timeout,
+    Err(reason) => usage(&reason),
+  };
+  let serverset = match Serverset::new(config) {
+    Ok(serverset) => Arc::new(serverset),
+    Err(reason) => fail(EXIT_USAGE, &reason.to_string()),
+  };
+  let exit_code = match options.command.as_str() {
+    "list" if options.json => dump(&serverset, timeout),
+    "list" => list(&serverset, timeout),
+    "dump" => dump(&serverset, timeout),
+    "watch" => watch(serverset, timeout),
+    "announce" => announce(&serverset, &options, timeout),
+    command => usage(&format!("Unknown command: {}", command)),
+  };
+  process::exit(exit_code);
+}
+
+
+fn usage(reason: &str) -> ! {
+  fail(EXIT_USAGE, &format!("{}\n\n{}", reason, USAGE))
+}
+
+
+fn fail(exit_code: i32, reason: &str) -> ! {
+  eprintln!("disco: {}", reason);
+  process::exit(exit_code)
+}
+
+
+// Calls `call` with the Serverset, retrying retryable errors, as the session connects
+// lazily. Each call is made on a thread of its own and abandoned at `deadline`, as
+// discotech_zookeeper blocks a request for as long as it cannot reach the ensemble, so
+// that an unreachable ensemble exits with EXIT_UNREACHABLE rather than hanging.
+fn until_deadline<T, F>(serverset: &Arc<Serverset>, deadline: Instant, call: F)
+    -> Result<T, DiscoError>
+    where T: Send + 'static, F: Fn(&Serverset) -> Result<T, DiscoError> + Send + 'static {
+  let (results_tx, results_rx) = channel();
+  let serverset = serverset.clone();
+  thread::spawn(move || loop {
+    let result = call(&serverset);
+    let retry = match result {
+      Err(ref reason) => reason.is_retryable() && Instant::now() < deadline,
+      Ok(_) => false,
+    };
+    if !retry {
+      let _ = results_tx.send(result);
+      return
+    }
+    thread::sleep(Duration::from_millis(100));
+  });
+  let now = Instant::now();
+  let remaining = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+  match results_rx.recv_timeout(remaining) {
+    Ok(result) => result,
+    Err(_) => fail(EXIT_UNREACHABLE, "Timed out reaching ZooKeeper"),
+  }
+}
+
+
+// Reads the members, retrying until ZooKeeper is reached or the timeout passes. Exits if
+// the Serverset cannot be read at all.
+fn read_members(serverset: &Arc<Serverset>, timeout: Duration)
+    -> Vec<(String, ServersetMember)> {
+  let deadline = Instant::now() + timeout;
+  match until_deadline(serverset, deadline, |serverset| serverset.refresh()) {
+    Ok(_) => (),
+    Err(DiscoError::MissingZnode(znode)) =>
+        fail(EXIT_EMPTY, &format!("Serverset ZNode {} does not exist", znode)),
+    Err(reason) => fail(EXIT_UNREACHABLE, &reason.to_string()),
+  }
+  let mut members = serverset.snapshot();
+  members.sort_by(|a, b| a.0.cmp(&b.0));
+  members
+}
+
+
+fn exit_code(members: &[(String, ServersetMember)]) -> i32 {
+  if members.is_empty() { EXIT_EMPTY } else { EXIT_OK }
+}
+
+
+fn list(serverset: &Arc<Serverset>, timeout: Duration) -> i32 {
+  let members = read_members(serverset, timeout);
+  println!("{:<20} {:<40} {:<6} {:<10} {}", "ZNODE", "HOST", "PORT", "STATUS",
+      "ADDITIONAL");
+  for &(ref member_znode, ref member) in members.iter() {
+    let mut additional: Vec<String> = member.additionalEndpoints.iter()
+      .map(|(name, endpoint)| format!("{}={}", name, endpoint.addr()))
+      .collect();
+    additional.sort();
+    println!("{:<20} {:<40} {:<6} {:<10} {}", member_znode, member.serviceEndpoint.host,
+        member.serviceEndpoint.port, member.status, additional.join(","));
+  }
+  exit_code(&members)
+}
+
+
+fn dump(serverset: &Arc<Serverset>, timeout: Duration) -> i32 {
+  let members = read_members(serverset, timeout);
+  let by_znode: BTreeMap<&String, &ServersetMember> = members.iter()
+    .map(|&(ref member_znode, ref member)| (member_znode, member))
+    .collect();
+  match serde_json::to_string_pretty(&by_znode) {
+    Ok(json) => println!("{}", json),
+    Err(reason) => fail(EXIT_USAGE, &reason.to_string()),
+  }
+  exit_code(&members)
+}
+
+
+// Prints the current members as joins, then every change until killed.
+fn watch(serverset: Arc<Serverset>, timeout: Duration) -> i32 {
+  for (member_znode, member) in read_members(&serverset, timeout) {
+    println!("+ {} {}", member_znode, describe(&member));
+  }
+  let events = serverset.events();
+  let _watch = serverset.watch();
+  for event in events.iter() {
+    match event {
+      ServersetEvent::MemberAdded(member_znode, member) =>
+          println!("+ {} {}", member_znode, describe(&member)),
+      ServersetEvent::MemberChanged(member_znode, member) =>
+          println!("~ {} {}", member_znode, describe(&member)),
+      ServersetEvent::MemberRemoved(member_znode) => println!("- {}", member_znode),
+      ServersetEvent::Reregistered(_) | ServersetEvent::SyncCompleted => (),
+    }
+  }
+  EXIT_OK
+}
+
+
+fn describe(member: &ServersetMember) -> String {
+  format!("{} {}", member.serviceEndpoint.addr(), member.status)
+}
+
+
+// Registers the member and holds it until stdin is closed or a line is read, then
+// deregisters it. If the process is killed instead, e.g. with Ctrl-C, the member lasts
+// until ZooKeeper expires the session.
+fn announce(serverset: &Arc<Serverset>, options: &Options, timeout: Duration) -> i32 {
+  let host = match options.required("host") {
+    Ok(host) => host,
+    Err(reason) => usage(&reason),
+  };
+  let port = match options.required("port").map(|port| port.parse()) {
+    Ok(Ok(port)) => port,
+    Ok(Err(_)) => usage("Invalid --port"),
+    Err(reason) => usage(&reason),
+  };
+  let endpoint = match ServiceEndpoint::new(host, port) {
+    Ok(endpoint) => endpoint,
+    Err(reason) => usage(&reason.to_string()),
+  };
+  let status = match options.flags.get("status") {
+    Some(status) => MemberStatus::from(status.as_str()),
+    None => MemberStatus::Alive,
+  };
+  let member = ServersetMember{
+    serviceEndpoint: endpoint,
+    additionalEndpoints: HashMap::new(),
+    status: status,
+    shard: None,
+    weight: None,
+    extra_fields: HashMap::new(),
+  };
+  let deadline = Instant::now() + timeout;
+  let registered = until_deadline(serverset, deadline,
+      move |serverset| serverset.register(member.clone()));
+  let handle = match registered {
+    Ok(handle) => handle,
+    Err(reason) => fail(EXIT_UNREACHABLE, &reason.to_string()),
+  };
+  println!("Registered {}; press Enter or close stdin to deregister", handle.znode());
+  let mut line = String::new();
+  let _ = io::stdin().lock().read_line(&mut line);
+  match handle.deregister() {
+    Ok(()) => EXIT_OK,
+    Err(reason) => fail(EXIT_UNREACHABLE, &reason.to_string()),
+  }
+}
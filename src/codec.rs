@@ -0,0 +1,162 @@
+use serverset::{ServersetMember, ServiceEndpoint};
+use status::MemberStatus;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::string::FromUtf8Error;
+
+
+#[derive(Debug)]
+pub enum CodecError {
+  Utf8(FromUtf8Error),
+  Json(serde_json::Error),
+  // The payload was readable but did not describe a member.
+  Malformed(String),
+}
+
+impl fmt::Display for CodecError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CodecError::Utf8(ref reason) =>
+          write!(f, "Could not parse node string: {}", reason),
+      CodecError::Json(ref reason) => write!(f, "Could not parse node JSON: {}", reason),
+      CodecError::Malformed(ref reason) => write!(f, "Malformed member data: {}", reason),
+    }
+  }
+}
+
+impl Error for CodecError {
+  fn description(&self) -> &str {
+    match *self {
+      CodecError::Utf8(_) => "could not parse node string",
+      CodecError::Json(_) => "could not parse node JSON",
+      CodecError::Malformed(_) => "malformed member data",
+    }
+  }
+}
+
+impl From<FromUtf8Error> for CodecError {
+  fn from(reason: FromUtf8Error) -> CodecError {
+    CodecError::Utf8(reason)
+  }
+}
+
+impl From<serde_json::Error> for CodecError {
+  fn from(reason: serde_json::Error) -> CodecError {
+    CodecError::Json(reason)
+  }
+}
+
+
+// Converts between members and the bytes stored in their ZNodes. Serversets whose
+// members are not stored as Finagle/Aurora JSON, or are decoded into a type other than
+// ServersetMember, can be read by passing another implementation to
+// Serverset::with_codec; decode failures surface as DiscoError::UndecodableMember.
+pub trait MemberCodec<M = ServersetMember>: Send + Sync {
+  fn decode(&self, bytes: &[u8]) -> Result<M, CodecError>;
+  fn encode(&self, member: &M) -> Result<Vec<u8>, CodecError>;
+}
+
+
+// The Finagle/Aurora JSON format, and the default codec.
+pub struct JsonCodec {
+  default_status: MemberStatus,
+}
+impl JsonCodec {
+  // Members whose JSON omits a status are given `default_status`.
+  pub fn new(default_status: MemberStatus) -> JsonCodec {
+    JsonCodec{
+      default_status: default_status,
+    }
+  }
+}
+
+impl MemberCodec for JsonCodec {
+  fn decode(&self, bytes: &[u8]) -> Result<ServersetMember, CodecError> {
+    let member_json = try!(String::from_utf8(bytes.to_vec()));
+    Ok(try!(ServersetMember::from_json(&member_json, &self.default_status)))
+  }
+
+  fn encode(&self, member: &ServersetMember) -> Result<Vec<u8>, CodecError> {
+    Ok(try!(serde_json::to_string(member)).into_bytes())
+  }
+}
+
+
+// A bare `host:port` payload, as written by simple announcers. Such members are always
+// ALIVE and have no additional endpoints.
+pub struct HostPortCodec;
+
+impl MemberCodec for HostPortCodec {
+  fn decode(&self, bytes: &[u8]) -> Result<ServersetMember, CodecError> {
+    let host_port = try!(String::from_utf8(bytes.to_vec()));
+    let host_port = host_port.trim();
+    let (host, port) = match host_port.rfind(':') {
+      Some(idx) => (&host_port[..idx], &host_port[idx + 1..]),
+      None => return Err(CodecError::Malformed(format!("no port in {}", host_port))),
+    };
+    let port = match port.parse() {
+      Ok(port) => port,
+      Err(_) => return Err(CodecError::Malformed(format!("bad port in {}", host_port))),
+    };
+    Ok(ServersetMember{
+      serviceEndpoint: ServiceEndpoint{
+        host: host.trim_matches(|c| c == '[' || c == ']').to_string(),
+        port: port,
+      },
+      additionalEndpoints: HashMap::new(),
+      status: MemberStatus::Alive,
+      shard: None,
+      weight: None,
+      extra_fields: HashMap::new(),
+    })
+  }
+
+  fn encode(&self, member: &ServersetMember) -> Result<Vec<u8>, CodecError> {
+    let endpoint = &member.serviceEndpoint;
+    // IPv6 literals are bracketed so the port separator stays unambiguous.
+    let host_port = if endpoint.host.contains(':') {
+      format!("[{}]:{}", endpoint.host, endpoint.port)
+    } else {
+      format!("{}:{}", endpoint.host, endpoint.port)
+    };
+    Ok(host_port.into_bytes())
+  }
+}
+
+
+// Member JSON decoded straight into a type of the caller's with serde, e.g. one that
+// also names the datacenter and build a member announces, for a Serverset<M>. Fields
+// the type does not have are ignored unless it denies them.
+pub struct TypedJsonCodec<M> {
+  member_type: PhantomData<fn() -> M>,
+}
+impl<M> TypedJsonCodec<M> {
+  pub fn new() -> TypedJsonCodec<M> {
+    TypedJsonCodec{
+      member_type: PhantomData,
+    }
+  }
+}
+
+impl<M> Default for TypedJsonCodec<M> {
+  fn default() -> TypedJsonCodec<M> {
+    TypedJsonCodec::new()
+  }
+}
+
+impl<M> MemberCodec<M> for TypedJsonCodec<M> where M: Serialize + DeserializeOwned {
+  fn decode(&self, bytes: &[u8]) -> Result<M, CodecError> {
+    let member_json = try!(String::from_utf8(bytes.to_vec()));
+    Ok(try!(serde_json::from_str(&member_json)))
+  }
+
+  fn encode(&self, member: &M) -> Result<Vec<u8>, CodecError> {
+    Ok(try!(serde_json::to_string(member)).into_bytes())
+  }
+}
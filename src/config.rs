@@ -1,26 +1,783 @@
-extern crate rustc_serialize;
-
 use std::io::prelude::*;
 use std::io;
+use std::env;
+#[cfg(feature = "toml")]
+use std::collections::HashSet;
+use std::fmt::Display;
 use std::fs::File;
+#[cfg(feature = "toml")]
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use backoff::BackoffPolicy;
+use error::ConfigError;
+use metrics::Metrics;
+use serverset::ServiceEndpoint;
+
+use serde_json;
+#[cfg(feature = "toml")]
+use toml;
+
+
+// The port ZooKeeper servers listen on unless told otherwise.
+const DEFAULT_ZOOKEEPER_PORT: u16 = 2181;
+
+// How retries back off unless configured.
+const DEFAULT_BACKOFF_INITIAL_MS: u64 = 100;
+const DEFAULT_BACKOFF_MULTIPLIER: f64 = 2.0;
+const DEFAULT_BACKOFF_MAX_MS: u64 = 30000;
+const DEFAULT_BACKOFF_JITTER: f64 = 0.2;
+
+fn default_zookeeper_port() -> u16 {
+  DEFAULT_ZOOKEEPER_PORT
+}
+
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ZkServer {
+  pub host: String,
+  pub port: u16,
+}
+
+
+// The servers of a ZooKeeper ensemble, either listed out or as a connect string such as
+// "zk1:2181,zk2:2181,zk3:2181".
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ZkServers {
+  List(Vec<ZkServer>),
+  ConnectString(String),
+}
+impl ZkServers {
+  pub fn servers(&self) -> Result<Vec<ZkServer>, ConfigError> {
+    match *self {
+      ZkServers::List(ref servers) => Ok(servers.clone()),
+      ZkServers::ConnectString(ref connect_string) => parse_servers(connect_string),
+    }
+  }
+}
+
+
+// Parses a comma-separated list of host[:port] entries, defaulting missing ports to
+// 2181 and ignoring surrounding whitespace.
+pub fn parse_servers(connect_string: &str) -> Result<Vec<ZkServer>, ConfigError> {
+  parse_servers_with_port(connect_string, DEFAULT_ZOOKEEPER_PORT)
+}
+
+// Parses a server list like parse_servers, defaulting missing ports to `default_port`.
+pub fn parse_servers_with_port(connect_string: &str, default_port: u16)
+    -> Result<Vec<ZkServer>, ConfigError> {
+  let mut servers = Vec::new();
+  for entry in connect_string.split(',').map(|entry| entry.trim()) {
+    let invalid = || ConfigError::InvalidServer(entry.to_string());
+    let (host, port) = match entry.rfind(':') {
+      Some(idx) => match entry[idx + 1..].trim().parse() {
+        Ok(port) => (entry[..idx].trim(), port),
+        Err(_) => return Err(invalid()),
+      },
+      None => (entry, default_port),
+    };
+    if host.is_empty() || host.contains(|c: char| c == '/' || c.is_whitespace()) {
+      return Err(invalid())
+    }
+    servers.push(ZkServer{
+      host: host.to_string(),
+      port: port,
+    });
+  }
+  Ok(servers)
+}
+
 
-use rustc_serialize::json;
+// The ACL given to the ZNodes created by Serverset::register.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationAcl {
+  // Anyone may do anything, matching ZooKeeper's OPEN_ACL_UNSAFE.
+  Open,
+  // Only the authenticated identity may do anything, matching CREATOR_ALL_ACL.
+  CreatorAll,
+}
 
 
-#[derive(Debug, RustcDecodable, RustcEncodable)]
+// New code should build its configuration with DiscoConfig::builder(), which fills in
+// defaults and validates the result. The fields stay public for existing callers.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct DiscoConfig {
+  // A single ZooKeeper server, used unless zookeeper_servers is set.
+  #[serde(default)]
   pub zookeeper_host: String,
+  #[serde(default = "default_zookeeper_port")]
   pub zookeeper_port: u16,
+  // Every server in the ensemble, so the client can fail over between them.
+  pub zookeeper_servers: Option<ZkServers>,
+  // A path, such as "/apps/prod", that every ZNode path, serverset_znode included, is
+  // relative to. It is appended to the connect string, and need not exist.
+  pub zookeeper_chroot: Option<String>,
   pub zookeeper_poll_ms: u32,
   pub zookeeper_timeout_secs: u64,
+  // How often to poll while ZooKeeper watches are driving updates. Defaults to
+  // zookeeper_poll_ms.
+  pub zookeeper_fallback_poll_ms: Option<u32>,
+  // The fraction of each poll interval varied at random, so that a fleet started at once
+  // does not poll ZooKeeper in lockstep, e.g. 0.2 for up to 20% either way. Defaults to
+  // 0, polling at exactly the interval.
+  pub poll_jitter: Option<f64>,
+  // How many member ZNodes a reconciliation pass reads at once. Defaults to 8. A member
+  // whose read has not finished within zookeeper_timeout_secs of it starting is given
+  // up on until the next pass.
+  pub zookeeper_fetch_concurrency: Option<usize>,
+  // How many more times a member ZNode that could not be read or decoded is read again
+  // within a pass, as a read racing a write may succeed straight away. Defaults to 2.
+  pub member_read_retries: Option<usize>,
+  // How much of a member's data that could not be decoded is quoted in the error, in
+  // bytes. Defaults to 512.
+  pub member_payload_log_bytes: Option<usize>,
+  // How reconnection attempts, and polls after failed reconciliation passes, back off:
+  // the first delay, how much each further failure multiplies it by, its cap, and the
+  // fraction of it varied at random. Default to 100ms, 2, 30s and 0.2.
+  pub backoff_initial_ms: Option<u64>,
+  pub backoff_multiplier: Option<f64>,
+  pub backoff_max_ms: Option<u64>,
+  pub backoff_jitter: Option<f64>,
   pub serverset_znode: String,
+  // The member statuses admitted into the members map, matched ignoring case, where
+  // "all" admits everything. Defaults to just ALIVE. Also accepted under the name
+  // accepted_statuses.
+  #[serde(alias = "accepted_statuses")]
+  pub status_filter: Option<Vec<String>>,
+  // The status assumed for members whose announcement omits one. Defaults to ALIVE.
+  pub default_status: Option<String>,
+  // Whether members registered by this process are re-created after a session expiry.
+  // Defaults to true.
+  pub reregister_on_expiry: Option<bool>,
+  // Credentials added to every session, e.g. "digest" and "user:password". Without them,
+  // ZNodes whose ACLs require authentication fail with DiscoError::NoAuth.
+  #[serde(alias = "zookeeper_auth_scheme")]
+  pub zk_auth_scheme: Option<String>,
+  #[serde(alias = "zookeeper_auth_credential")]
+  pub zk_auth_credential: Option<String>,
+  // Defaults to open. Only creator_all requires zk_auth_scheme and zk_auth_credential.
+  pub registration_acl: Option<RegistrationAcl>,
+
+  // There are no TLS settings, as discotech_zookeeper 0.1 only speaks plaintext and so
+  // cannot reach a secureClientPort. To use one, connect through a local TLS proxy, or
+  // adapt a client that supports TLS as a ZkClient and pass it to with_client. Configs
+  // that still set the former zk_tls keys are rejected.
+
+  // Endpoints served by pick_random and alive_members while the Serverset has no members
+  // and its last reconciliation failed, so that services keep working, degraded, when
+  // discovery is down.
+  pub static_fallback: Option<Vec<ServiceEndpoint>>,
+  // How long after the last successful reconciliation the members are considered stale,
+  // e.g. by is_stale. Required when fail_open_on_stale is false.
+  pub stale_after_ms: Option<u64>,
+  // Whether stale members keep being served while reconciliation fails. Defaults to
+  // true; when false, the members are cleared once a failed pass finds them stale, for
+  // users who would rather fail closed than route to endpoints that may be long gone.
+  pub fail_open_on_stale: Option<bool>,
+  // Receives reconciliation outcomes. Defaults to discarding them, and is never read
+  // from or written to configuration files.
+  #[serde(skip)]
+  pub metrics: Option<Arc<Metrics>>,
+}
+impl DiscoConfig {
+  pub fn builder() -> DiscoConfigBuilder {
+    DiscoConfigBuilder::new()
+  }
+
+  // Reads a DiscoConfig from DISCO_-prefixed environment variables.
+  pub fn from_env() -> Result<DiscoConfig, ConfigError> {
+    DiscoConfig::from_env_prefixed("DISCO_")
+  }
+
+  // Reads a DiscoConfig from environment variables named with the given prefix, so that
+  // e.g. "MYAPP_" reads MYAPP_ZK_HOSTS. Only ZK_HOSTS, a comma-separated server list, and
+  // SERVERSET_ZNODE are required; everything else defaults as in the builder.
+  pub fn from_env_prefixed(prefix: &str) -> Result<DiscoConfig, ConfigError> {
+    if try!(env_var(prefix, "ZK_TLS")).unwrap_or(false) {
+      return Err(tls_unsupported("zk_tls"))
+    }
+    let zookeeper_port =
+        try!(env_var(prefix, "ZK_PORT")).unwrap_or(DEFAULT_ZOOKEEPER_PORT);
+    let zookeeper_hosts: String = match try!(env_var(prefix, "ZK_HOSTS")) {
+      Some(zookeeper_hosts) => zookeeper_hosts,
+      None => return Err(ConfigError::MissingVariable(format!("{}ZK_HOSTS", prefix))),
+    };
+    let servers = match parse_servers_with_port(&zookeeper_hosts, zookeeper_port) {
+      Ok(servers) => servers,
+      Err(reason) => return Err(ConfigError::InvalidVariable(
+          format!("{}ZK_HOSTS", prefix), reason.to_string())),
+    };
+    let serverset_znode: String = match try!(env_var(prefix, "SERVERSET_ZNODE")) {
+      Some(serverset_znode) => serverset_znode,
+      None =>
+          return Err(ConfigError::MissingVariable(format!("{}SERVERSET_ZNODE", prefix))),
+    };
+    let mut builder = DiscoConfig::builder()
+      .zookeeper_servers(ZkServers::List(servers))
+      .zookeeper_port(zookeeper_port)
+      .serverset_znode(&serverset_znode);
+    if let Some(zookeeper_chroot) = try!(env_var::<String>(prefix, "ZK_CHROOT")) {
+      builder = builder.zookeeper_chroot(&zookeeper_chroot);
+    }
+    if let Some(zookeeper_timeout_secs) = try!(env_var(prefix, "ZK_TIMEOUT_SECS")) {
+      builder = builder.zookeeper_timeout_secs(zookeeper_timeout_secs);
+    }
+    if let Some(zookeeper_poll_ms) = try!(env_var(prefix, "POLL_MS")) {
+      builder = builder.zookeeper_poll_ms(zookeeper_poll_ms);
+    }
+    if let Some(zookeeper_fallback_poll_ms) = try!(env_var(prefix, "FALLBACK_POLL_MS")) {
+      builder = builder.zookeeper_fallback_poll_ms(zookeeper_fallback_poll_ms);
+    }
+    if let Some(poll_jitter) = try!(env_var(prefix, "POLL_JITTER")) {
+      builder = builder.poll_jitter(poll_jitter);
+    }
+    if let Some(fetch_concurrency) = try!(env_var(prefix, "FETCH_CONCURRENCY")) {
+      builder = builder.zookeeper_fetch_concurrency(fetch_concurrency);
+    }
+    if let Some(member_read_retries) = try!(env_var(prefix, "MEMBER_READ_RETRIES")) {
+      builder = builder.member_read_retries(member_read_retries);
+    }
+    if let Some(payload_log_bytes) = try!(env_var(prefix, "MEMBER_PAYLOAD_LOG_BYTES")) {
+      builder = builder.member_payload_log_bytes(payload_log_bytes);
+    }
+    let backoff_initial_ms = try!(env_var(prefix, "BACKOFF_INITIAL_MS"));
+    let backoff_multiplier = try!(env_var(prefix, "BACKOFF_MULTIPLIER"));
+    let backoff_max_ms = try!(env_var(prefix, "BACKOFF_MAX_MS"));
+    let backoff_jitter = try!(env_var(prefix, "BACKOFF_JITTER"));
+    builder.backoff_initial_ms = backoff_initial_ms;
+    builder.backoff_multiplier = backoff_multiplier;
+    builder.backoff_max_ms = backoff_max_ms;
+    builder.backoff_jitter = backoff_jitter;
+    if let Some(status_filter) = try!(env_var::<String>(prefix, "STATUS_FILTER")) {
+      builder = builder.status_filter(status_filter.split(',')
+        .map(|status| status.trim().to_string())
+        .collect());
+    }
+    if let Some(default_status) = try!(env_var::<String>(prefix, "DEFAULT_STATUS")) {
+      builder = builder.default_status(&default_status);
+    }
+    if let Some(reregister_on_expiry) = try!(env_var(prefix, "REREGISTER_ON_EXPIRY")) {
+      builder = builder.reregister_on_expiry(reregister_on_expiry);
+    }
+    if let Some(stale_after_ms) = try!(env_var(prefix, "STALE_AFTER_MS")) {
+      builder = builder.stale_after_ms(stale_after_ms);
+    }
+    if let Some(fail_open_on_stale) = try!(env_var(prefix, "FAIL_OPEN_ON_STALE")) {
+      builder = builder.fail_open_on_stale(fail_open_on_stale);
+    }
+    match (try!(env_var::<String>(prefix, "ZK_AUTH_SCHEME")),
+        try!(env_var::<String>(prefix, "ZK_AUTH_CREDENTIAL"))) {
+      (Some(scheme), Some(credential)) => builder = builder.zk_auth(&scheme, &credential),
+      (None, None) => (),
+      (Some(_), None) => return Err(ConfigError::MissingVariable(
+          format!("{}ZK_AUTH_CREDENTIAL", prefix))),
+      (None, Some(_)) => return Err(ConfigError::MissingVariable(
+          format!("{}ZK_AUTH_SCHEME", prefix))),
+    }
+    match try!(env_var::<String>(prefix, "REGISTRATION_ACL")) {
+      Some(ref acl) if acl == "open" =>
+          builder = builder.registration_acl(RegistrationAcl::Open),
+      Some(ref acl) if acl == "creator_all" =>
+          builder = builder.registration_acl(RegistrationAcl::CreatorAll),
+      Some(acl) => return Err(ConfigError::InvalidVariable(
+          format!("{}REGISTRATION_ACL", prefix),
+          format!("expected open or creator_all, got {}", acl))),
+      None => (),
+    }
+    builder.build()
+  }
+
+  // Reads a DiscoConfig from a TOML file with the same keys as the JSON format. Unknown
+  // keys are rejected rather than ignored, so that a misspelt setting is noticed.
+  #[cfg(feature = "toml")]
+  pub fn from_toml_file(path: &Path) -> Result<DiscoConfig, ConfigError> {
+    let file_error = |reason: String| {
+      ConfigError::File(path.display().to_string(), reason)
+    };
+    let mut contents = String::new();
+    let read = File::open(path).and_then(|mut file| file.read_to_string(&mut contents));
+    if let Err(reason) = read {
+      return Err(file_error(reason.to_string()))
+    }
+    let value: toml::Value = match contents.parse() {
+      Ok(value) => value,
+      Err(reason) => return Err(file_error(reason.to_string())),
+    };
+    if let Some(tls_key) = value.as_table().and_then(|table| tls_key(table.keys())) {
+      return Err(tls_unsupported(tls_key))
+    }
+    if let Some(key) = unknown_toml_key(&value) {
+      return Err(file_error(format!("unknown key `{}`", key)))
+    }
+    let config: DiscoConfig = match toml::from_str(&contents) {
+      Ok(config) => config,
+      Err(reason) => return Err(file_error(reason.to_string())),
+    };
+    try!(config.validate());
+    Ok(config)
+  }
+
+  // Checks that every field holds a usable value, so that a bad setting is reported
+  // against its field rather than as a confusing ZooKeeper error later on.
+  pub fn validate(&self) -> Result<(), ConfigError> {
+    let invalid = |field: &'static str, reason: &str| {
+      Err(ConfigError::InvalidValue(field, reason.to_string()))
+    };
+    let znode = &self.serverset_znode;
+    if !znode.starts_with('/') {
+      return invalid("serverset_znode", "must start with /")
+    }
+    if znode.ends_with('/') {
+      return invalid("serverset_znode", "must not end with /")
+    }
+    if znode.contains("//") {
+      return invalid("serverset_znode", "must not contain empty path segments")
+    }
+    if znode.contains(char::is_whitespace) {
+      return invalid("serverset_znode", "must not contain whitespace")
+    }
+    if try!(self.servers()).iter().any(|server| server.port == 0) {
+      return invalid("zookeeper_port", "must not be 0")
+    }
+    if self.zookeeper_timeout_secs == 0 {
+      return invalid("zookeeper_timeout_secs", "must not be 0")
+    }
+    if self.zookeeper_poll_ms == 0 {
+      return invalid("zookeeper_poll_ms", "must not be 0")
+    }
+    if self.zookeeper_fallback_poll_ms == Some(0) {
+      return invalid("zookeeper_fallback_poll_ms", "must not be 0")
+    }
+    match self.poll_jitter {
+      Some(poll_jitter) if !(poll_jitter >= 0.0 && poll_jitter < 1.0) =>
+          return invalid("poll_jitter", "must be at least 0 and less than 1"),
+      _ => (),
+    }
+    if self.zookeeper_fetch_concurrency == Some(0) {
+      return invalid("zookeeper_fetch_concurrency", "must not be 0")
+    }
+    let backoff = self.backoff_policy();
+    if backoff.initial == Duration::from_millis(0) {
+      return invalid("backoff_initial_ms", "must not be 0")
+    }
+    if !(backoff.multiplier >= 1.0) {
+      return invalid("backoff_multiplier", "must be at least 1")
+    }
+    if backoff.max < backoff.initial {
+      return invalid("backoff_max_ms", "must not be less than backoff_initial_ms")
+    }
+    if !(backoff.jitter >= 0.0 && backoff.jitter < 1.0) {
+      return invalid("backoff_jitter", "must be at least 0 and less than 1")
+    }
+    if let Some(ref static_fallback) = self.static_fallback {
+      if static_fallback.iter().any(|endpoint| endpoint.host.is_empty()) {
+        return invalid("static_fallback", "endpoints must have a host")
+      }
+    }
+    // Checked here rather than on connecting, so that a half-set pair is reported before
+    // any session is opened with it.
+    match (&self.zk_auth_scheme, &self.zk_auth_credential) {
+      (&Some(_), &None) => return Err(ConfigError::MissingField("zk_auth_credential")),
+      (&None, &Some(_)) => return Err(ConfigError::MissingField("zk_auth_scheme")),
+      _ => (),
+    }
+    match (self.stale_after_ms, self.fail_open_on_stale) {
+      (Some(0), _) => return invalid("stale_after_ms", "must not be 0"),
+      (None, Some(false)) => return invalid("stale_after_ms",
+          "must be set when fail_open_on_stale is false"),
+      _ => (),
+    }
+    try!(self.chroot());
+    try!(self.registration_acl());
+    Ok(())
+  }
+
+  // How reconnection attempts and polls after failed passes back off, with the defaults
+  // filled in.
+  pub fn backoff_policy(&self) -> BackoffPolicy {
+    BackoffPolicy{
+      initial: Duration::from_millis(
+          self.backoff_initial_ms.unwrap_or(DEFAULT_BACKOFF_INITIAL_MS)),
+      multiplier: self.backoff_multiplier.unwrap_or(DEFAULT_BACKOFF_MULTIPLIER),
+      max: Duration::from_millis(self.backoff_max_ms.unwrap_or(DEFAULT_BACKOFF_MAX_MS)),
+      jitter: self.backoff_jitter.unwrap_or(DEFAULT_BACKOFF_JITTER),
+    }
+  }
+
+  // The ZooKeeper servers to connect to, from zookeeper_servers if it is set and from
+  // zookeeper_host and zookeeper_port otherwise.
+  pub fn servers(&self) -> Result<Vec<ZkServer>, ConfigError> {
+    match self.zookeeper_servers {
+      Some(ref servers) => servers.servers(),
+      None => Ok(vec![ZkServer{
+        host: self.zookeeper_host.clone(),
+        port: self.zookeeper_port,
+      }]),
+    }
+  }
+
+  // The validated registration ACL, which can only be creator_all for authenticated
+  // sessions.
+  pub fn registration_acl(&self) -> Result<RegistrationAcl, ConfigError> {
+    match self.registration_acl {
+      None | Some(RegistrationAcl::Open) => Ok(RegistrationAcl::Open),
+      Some(RegistrationAcl::CreatorAll) => match (&self.zk_auth_scheme,
+          &self.zk_auth_credential) {
+        (&Some(_), &Some(_)) => Ok(RegistrationAcl::CreatorAll),
+        _ => Err(ConfigError::MissingField("zk_auth_credential")),
+      },
+    }
+  }
+
+  // The validated chroot, or an empty string if there is none.
+  pub fn chroot(&self) -> Result<&str, ConfigError> {
+    match self.zookeeper_chroot {
+      None => Ok(""),
+      Some(ref chroot) if chroot.is_empty() => Ok(""),
+      Some(ref chroot) if chroot.starts_with('/') && !chroot.ends_with('/') =>
+          Ok(chroot.as_str()),
+      Some(ref chroot) => Err(ConfigError::InvalidChroot(chroot.clone())),
+    }
+  }
+}
+
+
+// Builds a DiscoConfig, defaulting everything but the ZooKeeper servers and Serverset
+// ZNode: port 2181, a 10 second session timeout, and a 5 second poll interval.
+pub struct DiscoConfigBuilder {
+  zookeeper_host: Option<String>,
+  zookeeper_port: u16,
+  zookeeper_servers: Option<ZkServers>,
+  zookeeper_chroot: Option<String>,
+  zookeeper_poll_ms: u32,
+  zookeeper_timeout_secs: u64,
+  zookeeper_fallback_poll_ms: Option<u32>,
+  poll_jitter: Option<f64>,
+  zookeeper_fetch_concurrency: Option<usize>,
+  member_read_retries: Option<usize>,
+  member_payload_log_bytes: Option<usize>,
+  backoff_initial_ms: Option<u64>,
+  backoff_multiplier: Option<f64>,
+  backoff_max_ms: Option<u64>,
+  backoff_jitter: Option<f64>,
+  serverset_znode: Option<String>,
+  status_filter: Option<Vec<String>>,
+  default_status: Option<String>,
+  reregister_on_expiry: Option<bool>,
+  zk_auth_scheme: Option<String>,
+  zk_auth_credential: Option<String>,
+  registration_acl: Option<RegistrationAcl>,
+  static_fallback: Option<Vec<ServiceEndpoint>>,
+  stale_after_ms: Option<u64>,
+  fail_open_on_stale: Option<bool>,
+  metrics: Option<Arc<Metrics>>,
+}
+impl DiscoConfigBuilder {
+  pub fn new() -> DiscoConfigBuilder {
+    DiscoConfigBuilder{
+      zookeeper_host: None,
+      zookeeper_port: DEFAULT_ZOOKEEPER_PORT,
+      zookeeper_servers: None,
+      zookeeper_chroot: None,
+      zookeeper_poll_ms: 5000,
+      zookeeper_timeout_secs: 10,
+      zookeeper_fallback_poll_ms: None,
+      poll_jitter: None,
+      zookeeper_fetch_concurrency: None,
+      member_read_retries: None,
+      member_payload_log_bytes: None,
+      backoff_initial_ms: None,
+      backoff_multiplier: None,
+      backoff_max_ms: None,
+      backoff_jitter: None,
+      serverset_znode: None,
+      status_filter: None,
+      default_status: None,
+      reregister_on_expiry: None,
+      zk_auth_scheme: None,
+      zk_auth_credential: None,
+      registration_acl: None,
+      static_fallback: None,
+      stale_after_ms: None,
+      fail_open_on_stale: None,
+      metrics: None,
+    }
+  }
+
+  pub fn zookeeper_host(mut self, zookeeper_host: &str) -> DiscoConfigBuilder {
+    self.zookeeper_host = Some(zookeeper_host.to_string());
+    self
+  }
+
+  pub fn zookeeper_port(mut self, zookeeper_port: u16) -> DiscoConfigBuilder {
+    self.zookeeper_port = zookeeper_port;
+    self
+  }
+
+  pub fn zookeeper_servers(mut self, zookeeper_servers: ZkServers) -> DiscoConfigBuilder {
+    self.zookeeper_servers = Some(zookeeper_servers);
+    self
+  }
+
+  pub fn zookeeper_chroot(mut self, zookeeper_chroot: &str) -> DiscoConfigBuilder {
+    self.zookeeper_chroot = Some(zookeeper_chroot.to_string());
+    self
+  }
+
+  pub fn zookeeper_poll_ms(mut self, zookeeper_poll_ms: u32) -> DiscoConfigBuilder {
+    self.zookeeper_poll_ms = zookeeper_poll_ms;
+    self
+  }
+
+  pub fn zookeeper_timeout_secs(mut self, zookeeper_timeout_secs: u64)
+      -> DiscoConfigBuilder {
+    self.zookeeper_timeout_secs = zookeeper_timeout_secs;
+    self
+  }
+
+  pub fn zookeeper_fallback_poll_ms(mut self, zookeeper_fallback_poll_ms: u32)
+      -> DiscoConfigBuilder {
+    self.zookeeper_fallback_poll_ms = Some(zookeeper_fallback_poll_ms);
+    self
+  }
+
+  pub fn poll_jitter(mut self, poll_jitter: f64) -> DiscoConfigBuilder {
+    self.poll_jitter = Some(poll_jitter);
+    self
+  }
+
+  pub fn zookeeper_fetch_concurrency(mut self, zookeeper_fetch_concurrency: usize)
+      -> DiscoConfigBuilder {
+    self.zookeeper_fetch_concurrency = Some(zookeeper_fetch_concurrency);
+    self
+  }
+
+  pub fn member_read_retries(mut self, member_read_retries: usize) -> DiscoConfigBuilder {
+    self.member_read_retries = Some(member_read_retries);
+    self
+  }
+
+  pub fn member_payload_log_bytes(mut self, member_payload_log_bytes: usize)
+      -> DiscoConfigBuilder {
+    self.member_payload_log_bytes = Some(member_payload_log_bytes);
+    self
+  }
+
+  pub fn backoff(mut self, initial_ms: u64, multiplier: f64, max_ms: u64, jitter: f64)
+      -> DiscoConfigBuilder {
+    self.backoff_initial_ms = Some(initial_ms);
+    self.backoff_multiplier = Some(multiplier);
+    self.backoff_max_ms = Some(max_ms);
+    self.backoff_jitter = Some(jitter);
+    self
+  }
+
+  pub fn serverset_znode(mut self, serverset_znode: &str) -> DiscoConfigBuilder {
+    self.serverset_znode = Some(serverset_znode.to_string());
+    self
+  }
+
+  pub fn status_filter(mut self, status_filter: Vec<String>) -> DiscoConfigBuilder {
+    self.status_filter = Some(status_filter);
+    self
+  }
+
+  pub fn default_status(mut self, default_status: &str) -> DiscoConfigBuilder {
+    self.default_status = Some(default_status.to_string());
+    self
+  }
+
+  pub fn reregister_on_expiry(mut self, reregister_on_expiry: bool)
+      -> DiscoConfigBuilder {
+    self.reregister_on_expiry = Some(reregister_on_expiry);
+    self
+  }
+
+  pub fn zk_auth(mut self, zk_auth_scheme: &str, zk_auth_credential: &str)
+      -> DiscoConfigBuilder {
+    self.zk_auth_scheme = Some(zk_auth_scheme.to_string());
+    self.zk_auth_credential = Some(zk_auth_credential.to_string());
+    self
+  }
+
+  pub fn registration_acl(mut self, registration_acl: RegistrationAcl)
+      -> DiscoConfigBuilder {
+    self.registration_acl = Some(registration_acl);
+    self
+  }
+
+  pub fn static_fallback(mut self, static_fallback: Vec<ServiceEndpoint>)
+      -> DiscoConfigBuilder {
+    self.static_fallback = Some(static_fallback);
+    self
+  }
+
+  pub fn stale_after_ms(mut self, stale_after_ms: u64) -> DiscoConfigBuilder {
+    self.stale_after_ms = Some(stale_after_ms);
+    self
+  }
+
+  pub fn fail_open_on_stale(mut self, fail_open_on_stale: bool) -> DiscoConfigBuilder {
+    self.fail_open_on_stale = Some(fail_open_on_stale);
+    self
+  }
+
+  pub fn metrics(mut self, metrics: Arc<Metrics>) -> DiscoConfigBuilder {
+    self.metrics = Some(metrics);
+    self
+  }
+
+  pub fn build(self) -> Result<DiscoConfig, ConfigError> {
+    let zookeeper_host = match (self.zookeeper_host, &self.zookeeper_servers) {
+      (Some(zookeeper_host), _) => zookeeper_host,
+      (None, &Some(_)) => String::new(),
+      (None, &None) => return Err(ConfigError::MissingField("zookeeper_host")),
+    };
+    let serverset_znode = match self.serverset_znode {
+      Some(serverset_znode) => serverset_znode,
+      None => return Err(ConfigError::MissingField("serverset_znode")),
+    };
+    let config = DiscoConfig{
+      zookeeper_host: zookeeper_host,
+      zookeeper_port: self.zookeeper_port,
+      zookeeper_servers: self.zookeeper_servers,
+      zookeeper_chroot: self.zookeeper_chroot,
+      zookeeper_poll_ms: self.zookeeper_poll_ms,
+      zookeeper_timeout_secs: self.zookeeper_timeout_secs,
+      zookeeper_fallback_poll_ms: self.zookeeper_fallback_poll_ms,
+      poll_jitter: self.poll_jitter,
+      zookeeper_fetch_concurrency: self.zookeeper_fetch_concurrency,
+      member_read_retries: self.member_read_retries,
+      member_payload_log_bytes: self.member_payload_log_bytes,
+      backoff_initial_ms: self.backoff_initial_ms,
+      backoff_multiplier: self.backoff_multiplier,
+      backoff_max_ms: self.backoff_max_ms,
+      backoff_jitter: self.backoff_jitter,
+      serverset_znode: serverset_znode,
+      status_filter: self.status_filter,
+      default_status: self.default_status,
+      reregister_on_expiry: self.reregister_on_expiry,
+      zk_auth_scheme: self.zk_auth_scheme,
+      zk_auth_credential: self.zk_auth_credential,
+      registration_acl: self.registration_acl,
+      static_fallback: self.static_fallback,
+      stale_after_ms: self.stale_after_ms,
+      fail_open_on_stale: self.fail_open_on_stale,
+      metrics: self.metrics,
+    };
+    try!(config.validate());
+    Ok(config)
+  }
+}
+
+
+// The aliases serde also accepts for some of DiscoConfig's keys, which do not show up
+// when it is serialized.
+#[cfg(feature = "toml")]
+const CONFIG_KEY_ALIASES: &'static [&'static str] = &["accepted_statuses",
+  "zookeeper_auth_scheme", "zookeeper_auth_credential"];
+
+// Returns every key a configuration file may set: the keys DiscoConfig serializes, which
+// are the ones it reads, taken from serde so that new fields are never missed, and their
+// aliases.
+#[cfg(feature = "toml")]
+fn config_keys() -> HashSet<String> {
+  let fields = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discotech")
+    .build().ok()
+    .and_then(|config| serde_json::to_value(&config).ok());
+  let mut config_keys: HashSet<String> = match fields {
+    Some(serde_json::Value::Object(fields)) => fields.keys().cloned().collect(),
+    _ => HashSet::new(),
+  };
+  config_keys.extend(CONFIG_KEY_ALIASES.iter().map(|alias| alias.to_string()));
+  config_keys
+}
+
+// The keys of the TLS settings discotech once had, which it cannot honour.
+const TLS_KEYS: &'static [&'static str] = &["zk_tls", "zk_tls_cert_path",
+  "zk_tls_key_path", "zk_tls_ca_path"];
+
+// Returns the first of `keys` that is a TLS setting, if any.
+fn tls_key<'a, I>(keys: I) -> Option<&'static str> where I: Iterator<Item = &'a String> {
+  for key in keys {
+    if let Some(tls_key) = TLS_KEYS.iter().find(|tls_key| **tls_key == key.as_str()) {
+      return Some(*tls_key)
+    }
+  }
+  None
+}
+
+// The error for a config asking for TLS, which rather than being ignored, would leave
+// the session in plaintext.
+fn tls_unsupported(tls_key: &'static str) -> ConfigError {
+  ConfigError::InvalidValue(tls_key,
+      "TLS is not supported; connect through a TLS proxy instead".to_string())
+}
+
+
+// Returns the first key in a TOML config that DiscoConfig does not know, if any.
+#[cfg(feature = "toml")]
+fn unknown_toml_key(value: &toml::Value) -> Option<String> {
+  let table = match value.as_table() {
+    Some(table) => table,
+    None => return None,
+  };
+  let config_keys = config_keys();
+  for (key, entry) in table.iter() {
+    if !config_keys.contains(key) {
+      return Some(key.clone())
+    }
+    if key == "zookeeper_servers" {
+      for server in entry.as_array().map(|servers| servers.as_slice()).unwrap_or(&[]) {
+        for server_key in server.as_table().iter().flat_map(|server| server.keys()) {
+          if server_key != "host" && server_key != "port" {
+            return Some(format!("zookeeper_servers.{}", server_key))
+          }
+        }
+      }
+    }
+  }
+  None
+}
+
+
+// Reads and parses the environment variable `prefix` + `name`, if it is set.
+fn env_var<T>(prefix: &str, name: &str) -> Result<Option<T>, ConfigError>
+    where T: FromStr, T::Err: Display {
+  let var = format!("{}{}", prefix, name);
+  match env::var(&var) {
+    Err(env::VarError::NotPresent) => Ok(None),
+    Err(reason) => Err(ConfigError::InvalidVariable(var, reason.to_string())),
+    Ok(value) => match value.trim().parse() {
+      Ok(parsed) => Ok(Some(parsed)),
+      Err(reason) => Err(ConfigError::InvalidVariable(var, reason.to_string())),
+    },
+  }
 }
 
 
+// Reads a DiscoConfig from a JSON file. A file that cannot be parsed, or holds an invalid
+// config, fails with io::ErrorKind::InvalidData wrapping the ConfigError.
 pub fn read_config(config_file_loc: String) -> io::Result<DiscoConfig> {
-  let mut config_file = try!(File::open(config_file_loc));
+  let mut config_file = try!(File::open(&config_file_loc));
   let mut config_file_contents = String::new();
   try!(config_file.read_to_string(&mut config_file_contents));
-  let config: DiscoConfig = json::decode(&config_file_contents).unwrap();
+  let invalid = |reason: ConfigError| io::Error::new(io::ErrorKind::InvalidData, reason);
+  let file_error = |reason: serde_json::Error| {
+    invalid(ConfigError::File(config_file_loc.clone(), reason.to_string()))
+  };
+  let value: serde_json::Value = try!(serde_json::from_str(&config_file_contents)
+    .map_err(&file_error));
+  if let Some(tls_key) = value.as_object().and_then(|object| tls_key(object.keys())) {
+    return Err(invalid(tls_unsupported(tls_key)))
+  }
+  let config: DiscoConfig = try!(serde_json::from_value(value).map_err(&file_error));
+  try!(config.validate().map_err(invalid));
   Ok(config)
 }
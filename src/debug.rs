@@ -0,0 +1,225 @@
+use error::DiscoError;
+use serverset::{Serverset, ServersetMember};
+
+use serde_json;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+
+// How many intervals between full passes /health allows without a successful one, unless
+// stale_after_ms is set.
+const DEFAULT_HEALTH_POLLS: u32 = 3;
+
+// The most of a request that is read, which is plenty for a request line and headers.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+// How long a client may take to send its request.
+const REQUEST_TIMEOUT_SECS: u64 = 5;
+
+
+// Serves a Serverset's debug endpoints until stopped or dropped.
+pub struct DebugServerHandle {
+  local_addr: SocketAddr,
+  stopped: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+impl DebugServerHandle {
+  // The address being served, e.g. to find the port chosen when binding port 0.
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  // Stops accepting requests and waits for the one being answered, if any.
+  pub fn stop(mut self) -> thread::Result<()> {
+    self.stop_thread()
+  }
+
+  fn stop_thread(&mut self) -> thread::Result<()> {
+    let thread = match self.thread.take() {
+      Some(thread) => thread,
+      None => return Ok(()),
+    };
+    self.stopped.store(true, Ordering::SeqCst);
+    // Wakes the thread from accept; it sees that it was stopped before answering.
+    let _ = TcpStream::connect(wake_addr(self.local_addr));
+    thread.join()
+  }
+}
+
+impl Drop for DebugServerHandle {
+  fn drop(&mut self) {
+    let _ = self.stop_thread();
+  }
+}
+
+
+impl Serverset {
+  // Answers HTTP requests on `addr` about what this Serverset holds, e.g. to curl from
+  // a sidecar when working out why traffic is reaching a dead host:
+  //
+  //   GET /members           every member, as JSON keyed by ZNode name
+  //   GET /members/{znode}   the named member, as JSON, or 404
+  //   GET /health            200 if the latest pass succeeded within stale_after_ms, or
+  //                          three poll intervals if unset, and 503 otherwise
+  //
+  // Requests are answered one at a time on a thread of the server's own, which holds a
+  // reference to the Serverset until the returned handle is stopped or dropped.
+  pub fn serve_debug(self: &Arc<Self>, addr: SocketAddr)
+      -> Result<DebugServerHandle, DiscoError> {
+    let listener = try!(TcpListener::bind(addr));
+    let local_addr = try!(listener.local_addr());
+    let stopped = Arc::new(AtomicBool::new(false));
+    let thread_stopped = stopped.clone();
+    let serverset = self.clone();
+    let thread = thread::spawn(move || for stream in listener.incoming() {
+      if thread_stopped.load(Ordering::SeqCst) {
+        return
+      }
+      match stream.and_then(|stream| serverset.answer_debug(stream)) {
+        Err(reason) => debug!("Could not answer debug request: {}", reason),
+        Ok(()) => (),
+      }
+    });
+    info!("Serving Serverset debug endpoints on {}", local_addr);
+    Ok(DebugServerHandle{
+      local_addr: local_addr,
+      stopped: stopped,
+      thread: Some(thread),
+    })
+  }
+
+  fn answer_debug(&self, mut stream: TcpStream) -> io::Result<()> {
+    try!(stream.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SECS))));
+    let request = try!(read_request(&mut stream));
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let response = match (request_line.next(), request_line.next()) {
+      (Some("GET"), Some(path)) => self.debug_response(path),
+      (Some(_), Some(_)) => DebugResponse::text(405, "Only GET is supported"),
+      _ => DebugResponse::text(400, "Malformed request"),
+    };
+    response.write_to(&mut stream)
+  }
+
+  fn debug_response(&self, path: &str) -> DebugResponse {
+    // Query strings are ignored, e.g. from tools that add cache busters.
+    let path = path.split('?').next().unwrap_or(path);
+    let members = self.members();
+    match path {
+      "/members" => {
+        // Sorted so that successive responses can be diffed.
+        let sorted: BTreeMap<&String, &ServersetMember> = members.iter().collect();
+        DebugResponse::json(serde_json::to_vec_pretty(&sorted))
+      },
+      "/health" => match self.debug_health() {
+        Ok(()) => DebugResponse::text(200, "OK"),
+        Err(reason) => DebugResponse::text(503, &reason),
+      },
+      path if path.starts_with("/members/") => {
+        let member_znode = &path["/members/".len()..];
+        match members.get(member_znode) {
+          Some(member) => DebugResponse::json(serde_json::to_vec_pretty(member)),
+          None => DebugResponse::text(404, &format!("No member {}", member_znode)),
+        }
+      },
+      _ => DebugResponse::text(404, "Not found"),
+    }
+  }
+
+  // Returns why the Serverset is unhealthy, if it is.
+  fn debug_health(&self) -> Result<(), String> {
+    let max_age = match self.config().stale_after_ms {
+      Some(stale_after_ms) => Duration::from_millis(stale_after_ms),
+      None => self.full_pass_interval() * DEFAULT_HEALTH_POLLS,
+    };
+    if self.last_sync_failed() {
+      return Err("The latest reconciliation pass failed".to_string())
+    }
+    match self.last_successful_reconcile() {
+      None => Err("No reconciliation pass has succeeded yet".to_string()),
+      Some(_) if self.is_stale(max_age) =>
+          Err(format!("No reconciliation pass has succeeded in {:?}", max_age)),
+      Some(_) => Ok(()),
+    }
+  }
+}
+
+
+struct DebugResponse {
+  status: u16,
+  content_type: &'static str,
+  body: Vec<u8>,
+}
+impl DebugResponse {
+  fn text(status: u16, body: &str) -> DebugResponse {
+    DebugResponse{
+      status: status,
+      content_type: "text/plain; charset=utf-8",
+      body: format!("{}\n", body).into_bytes(),
+    }
+  }
+
+  fn json(body: serde_json::Result<Vec<u8>>) -> DebugResponse {
+    match body {
+      Ok(body) => DebugResponse{
+        status: 200,
+        content_type: "application/json",
+        body: body,
+      },
+      Err(reason) => DebugResponse::text(500, &reason.to_string()),
+    }
+  }
+
+  fn write_to(&self, stream: &mut TcpStream) -> io::Result<()> {
+    let reason = match self.status {
+      200 => "OK",
+      400 => "Bad Request",
+      404 => "Not Found",
+      405 => "Method Not Allowed",
+      503 => "Service Unavailable",
+      _ => "Internal Server Error",
+    };
+    try!(write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n", self.status, reason, self.content_type,
+        self.body.len()));
+    try!(stream.write_all(&self.body));
+    stream.flush()
+  }
+}
+
+
+// Reads the request line and headers, up to MAX_REQUEST_BYTES. Any body is ignored.
+fn read_request(stream: &mut TcpStream) -> io::Result<String> {
+  let mut request = Vec::new();
+  let mut buf = [0; 1024];
+  while request.len() < MAX_REQUEST_BYTES {
+    let read = try!(stream.read(&mut buf));
+    if read == 0 {
+      break
+    }
+    request.extend_from_slice(&buf[..read]);
+    if request.windows(4).any(|window| window == b"\r\n\r\n") {
+      break
+    }
+  }
+  Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+
+// The address to connect to to reach a listener bound to `local_addr`, which may be the
+// unspecified address.
+fn wake_addr(local_addr: SocketAddr) -> SocketAddr {
+  match local_addr.ip() {
+    IpAddr::V4(ip) if ip.is_unspecified() =>
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), local_addr.port()),
+    IpAddr::V6(ip) if ip.is_unspecified() =>
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)),
+            local_addr.port()),
+    _ => local_addr,
+  }
+}
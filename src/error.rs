@@ -0,0 +1,257 @@
+extern crate discotech_zookeeper;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use codec::CodecError;
+use discotech_zookeeper::ZkError;
+
+
+#[derive(Debug)]
+pub enum DiscoError {
+  // The connect string could not be turned into a list of ZooKeeper servers.
+  InvalidConnectString(String),
+  // The ZooKeeper ensemble could not be reached in time; retrying may succeed.
+  ConnectionTimeout(String),
+  Zk(ZkError),
+  Codec(CodecError),
+  // The data of the given member could not be decoded. Holds the data as a string,
+  // truncated to member_payload_log_bytes.
+  UndecodableMember(String, CodecError, String),
+  Config(ConfigError),
+  // A ZNode that must exist, such as the Serverset ZNode, could not be found.
+  MissingZnode(String),
+  // Reading the ZNode took longer than the session timeout.
+  FetchTimeout(String),
+  // The ZNode was written by someone else since we last read or wrote it.
+  VersionConflict(String),
+  // The ZNode's ACL denies this session access, usually because zk_auth_scheme and
+  // zk_auth_credential are unset or wrong.
+  NoAuth(String),
+  // Fewer ALIVE members than required, the first count, were found before timing out.
+  NotReady(usize, usize),
+  // Fewer members of any admitted status than required were found before timing out.
+  WaitTimeout(WaitTimeout),
+  // The blocking task running an async call panicked, or was cancelled as its runtime
+  // shut down, for the given reason.
+  TaskFailed(String),
+  // A ServiceEndpoint could not be created, for the given reason.
+  InvalidEndpoint(String),
+  // A DiscoveryBackend could not list its members, for the given reason.
+  Backend(String),
+  // The named operation needs a ZooKeeper session, which a Serverset reading its members
+  // from a DiscoveryBackend does not have.
+  Unsupported(&'static str),
+  // A socket could not be set up, e.g. because the address given to serve_debug was
+  // already in use.
+  Io(io::Error),
+}
+impl DiscoError {
+  pub fn from_connect(connect_string: &str, reason: ZkError) -> DiscoError {
+    match reason {
+      ZkError::BadArguments =>
+          DiscoError::InvalidConnectString(connect_string.to_string()),
+      ZkError::ConnectionLoss | ZkError::OperationTimeout =>
+          DiscoError::ConnectionTimeout(connect_string.to_string()),
+      reason => DiscoError::Zk(reason),
+    }
+  }
+
+  // Attributes a failed operation on the given ZNode, singling out ACL denials.
+  pub fn from_zk(znode: &str, reason: ZkError) -> DiscoError {
+    match reason {
+      ZkError::NoAuth => DiscoError::NoAuth(znode.to_string()),
+      reason => DiscoError::Zk(reason),
+    }
+  }
+
+  // Returns true if the failure is likely transient and the operation is worth retrying.
+  pub fn is_retryable(&self) -> bool {
+    match *self {
+      DiscoError::InvalidConnectString(_) => false,
+      DiscoError::ConnectionTimeout(_) => true,
+      DiscoError::Zk(ZkError::ConnectionLoss) |
+      DiscoError::Zk(ZkError::OperationTimeout) |
+      DiscoError::Zk(ZkError::SessionExpired) => true,
+      DiscoError::Zk(_) => false,
+      DiscoError::Codec(_) => false,
+      DiscoError::UndecodableMember(..) => false,
+      DiscoError::Config(_) => false,
+      DiscoError::VersionConflict(_) => true,
+      DiscoError::FetchTimeout(_) => true,
+      DiscoError::MissingZnode(_) => false,
+      DiscoError::NoAuth(_) => false,
+      DiscoError::NotReady(..) => true,
+      DiscoError::WaitTimeout(_) => true,
+      DiscoError::TaskFailed(_) => false,
+      DiscoError::InvalidEndpoint(_) => false,
+      DiscoError::Backend(_) => true,
+      DiscoError::Unsupported(_) => false,
+      DiscoError::Io(_) => false,
+    }
+  }
+}
+
+impl fmt::Display for DiscoError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      DiscoError::InvalidConnectString(ref connect_string) =>
+          write!(f, "Invalid ZooKeeper connect string: {}", connect_string),
+      DiscoError::ConnectionTimeout(ref connect_string) =>
+          write!(f, "Timed out connecting to ZooKeeper at {}", connect_string),
+      // ZkError's Display impl recurses into itself, so its Debug form is used instead.
+      DiscoError::Zk(ref reason) => write!(f, "ZooKeeper error: {:?}", reason),
+      DiscoError::Codec(ref reason) => write!(f, "{}", reason),
+      DiscoError::UndecodableMember(ref znode, ref reason, ref member_data) =>
+          write!(f, "Could not decode member {}: {}; data: {}", znode, reason,
+              member_data),
+      DiscoError::Config(ref reason) => write!(f, "{}", reason),
+      DiscoError::MissingZnode(ref znode) => write!(f, "Could not find ZNode: {}", znode),
+      DiscoError::FetchTimeout(ref znode) =>
+          write!(f, "Timed out reading ZNode {}", znode),
+      DiscoError::VersionConflict(ref znode) =>
+          write!(f, "ZNode {} was modified concurrently", znode),
+      DiscoError::NoAuth(ref znode) =>
+          write!(f, "Not authorized to access ZNode {}", znode),
+      DiscoError::NotReady(alive, required) =>
+          write!(f, "Found {} of {} required ALIVE members", alive, required),
+      DiscoError::WaitTimeout(ref reason) => write!(f, "{}", reason),
+      DiscoError::TaskFailed(ref reason) => write!(f, "Blocking task failed: {}", reason),
+      DiscoError::InvalidEndpoint(ref reason) =>
+          write!(f, "Invalid service endpoint: {}", reason),
+      DiscoError::Backend(ref reason) =>
+          write!(f, "Could not list members: {}", reason),
+      DiscoError::Unsupported(operation) =>
+          write!(f, "{} requires a ZooKeeper-backed Serverset", operation),
+      DiscoError::Io(ref reason) => write!(f, "I/O error: {}", reason),
+    }
+  }
+}
+
+impl Error for DiscoError {
+  fn description(&self) -> &str {
+    match *self {
+      DiscoError::InvalidConnectString(_) => "invalid ZooKeeper connect string",
+      DiscoError::ConnectionTimeout(_) => "timed out connecting to ZooKeeper",
+      DiscoError::Zk(_) => "ZooKeeper error",
+      DiscoError::Codec(ref reason) => reason.description(),
+      DiscoError::UndecodableMember(_, ref reason, _) => reason.description(),
+      DiscoError::Config(ref reason) => reason.description(),
+      DiscoError::MissingZnode(_) => "could not find ZNode",
+      DiscoError::FetchTimeout(_) => "timed out reading ZNode",
+      DiscoError::VersionConflict(_) => "ZNode was modified concurrently",
+      DiscoError::NoAuth(_) => "not authorized to access ZNode",
+      DiscoError::NotReady(..) => "too few ALIVE members",
+      DiscoError::WaitTimeout(ref reason) => reason.description(),
+      DiscoError::TaskFailed(_) => "blocking task failed",
+      DiscoError::InvalidEndpoint(_) => "invalid service endpoint",
+      DiscoError::Backend(_) => "could not list members",
+      DiscoError::Unsupported(_) => "operation requires a ZooKeeper-backed Serverset",
+      DiscoError::Io(_) => "I/O error",
+    }
+  }
+}
+
+impl From<ZkError> for DiscoError {
+  fn from(reason: ZkError) -> DiscoError {
+    DiscoError::Zk(reason)
+  }
+}
+
+impl From<io::Error> for DiscoError {
+  fn from(reason: io::Error) -> DiscoError {
+    DiscoError::Io(reason)
+  }
+}
+
+impl From<CodecError> for DiscoError {
+  fn from(reason: CodecError) -> DiscoError {
+    DiscoError::Codec(reason)
+  }
+}
+
+impl From<ConfigError> for DiscoError {
+  fn from(reason: ConfigError) -> DiscoError {
+    DiscoError::Config(reason)
+  }
+}
+
+impl From<WaitTimeout> for DiscoError {
+  fn from(reason: WaitTimeout) -> DiscoError {
+    DiscoError::WaitTimeout(reason)
+  }
+}
+
+
+// Fewer members than required were found before a wait timed out.
+#[derive(Debug)]
+pub struct WaitTimeout {
+  pub members: usize,
+  pub required: usize,
+}
+
+impl fmt::Display for WaitTimeout {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Timed out with {} of {} required members", self.members, self.required)
+  }
+}
+
+impl Error for WaitTimeout {
+  fn description(&self) -> &str {
+    "timed out waiting for members"
+  }
+}
+
+
+#[derive(Debug)]
+pub enum ConfigError {
+  // A field without a default was never set.
+  MissingField(&'static str),
+  // An entry in the ZooKeeper server list was not of the form host[:port].
+  InvalidServer(String),
+  // The chroot did not start with a slash, or ended with one.
+  InvalidChroot(String),
+  // A field was set to a value it cannot take, such as a zero poll interval, for the
+  // given reason.
+  InvalidValue(&'static str, String),
+  // A required environment variable was unset.
+  MissingVariable(String),
+  // An environment variable could not be parsed, for the given reason.
+  InvalidVariable(String, String),
+  // A configuration file could not be read or parsed, for the given reason.
+  File(String, String),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ConfigError::MissingField(field) => write!(f, "Missing required field: {}", field),
+      ConfigError::InvalidServer(ref server) =>
+          write!(f, "Invalid ZooKeeper server: {}", server),
+      ConfigError::InvalidChroot(ref chroot) =>
+          write!(f, "Invalid ZooKeeper chroot: {}", chroot),
+      ConfigError::InvalidValue(field, ref reason) =>
+          write!(f, "Invalid value for field {}: {}", field, reason),
+      ConfigError::MissingVariable(ref var) =>
+          write!(f, "Missing required environment variable: {}", var),
+      ConfigError::InvalidVariable(ref var, ref reason) =>
+          write!(f, "Invalid environment variable {}: {}", var, reason),
+      ConfigError::File(ref path, ref reason) =>
+          write!(f, "Invalid configuration file {}: {}", path, reason),
+    }
+  }
+}
+
+impl Error for ConfigError {
+  fn description(&self) -> &str {
+    match *self {
+      ConfigError::MissingField(_) => "missing required field",
+      ConfigError::InvalidServer(_) => "invalid ZooKeeper server",
+      ConfigError::InvalidChroot(_) => "invalid ZooKeeper chroot",
+      ConfigError::InvalidValue(..) => "invalid value for field",
+      ConfigError::MissingVariable(_) => "missing required environment variable",
+      ConfigError::InvalidVariable(..) => "invalid environment variable",
+      ConfigError::File(..) => "invalid configuration file",
+    }
+  }
+}
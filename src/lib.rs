@@ -6,11 +6,65 @@
 #[macro_use]
 extern crate log;
 extern crate log4rs;
-extern crate rustc_serialize;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "prometheus")]
+extern crate prometheus;
 extern crate discotech_zookeeper;
 
-pub use config::{read_config, DiscoConfig};
-pub use serverset::Serverset;
+pub use discotech_zookeeper::{perms, Acl};
+#[cfg(feature = "async")]
+pub use asynchronous::{AsyncServerset, MemberStream};
+pub use backend::{DiscoveryBackend, FileBackend};
+pub use backoff::{BackoffPolicy, BackoffState};
+pub use codec::{CodecError, HostPortCodec, JsonCodec, MemberCodec, TypedJsonCodec};
+pub use config::{parse_servers, parse_servers_with_port, read_config, DiscoConfig,
+    DiscoConfigBuilder, RegistrationAcl, ZkServer, ZkServers};
+#[cfg(feature = "debug-server")]
+pub use debug::DebugServerHandle;
+pub use error::{ConfigError, DiscoError, WaitTimeout};
+pub use listener::{ServersetEvent, ServersetListener};
+pub use metrics::{Metrics, MetricsSink, NoopMetrics, SinkMetrics};
+#[cfg(feature = "prometheus")]
+pub use metrics::PrometheusSink;
+pub use picker::{ConsistentHashPicker, RingKey, RoundRobin, WeightedPicker};
+pub use registration::MemberHandle;
+pub use registry::ServersetRegistry;
+pub use serverset::{validate, validate_with_client, HasStatus, MemberVersion, Serverset,
+    ServersetManager, ServersetMember, ServiceEndpoint, SyncReport, ValidationReport,
+    WatchHandle};
+pub use status::{MemberStatus, StatusFilter};
+pub use testing::{MemoryBackend, MockServerset, MockZk};
+#[cfg(feature = "thrift")]
+pub use thrift::ThriftMemberCodec;
+pub use zk::ZkClient;
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod backend;
+pub mod backoff;
+pub mod codec;
 pub mod config;
+#[cfg(feature = "debug-server")]
+pub mod debug;
+pub mod error;
+pub mod listener;
+pub mod metrics;
+pub mod picker;
+pub mod registration;
+pub mod registry;
 pub mod serverset;
+pub mod status;
+pub mod testing;
+#[cfg(feature = "thrift")]
+pub mod thrift;
+pub mod zk;
@@ -0,0 +1,26 @@
+use serverset::ServersetMember;
+
+
+// Receives notifications whenever the membership of a Serverset actually changes.
+// Callbacks are invoked from the watch thread after the members lock is released, so
+// they may freely read the Serverset. A Serverset<M> takes listeners of its members'
+// type.
+pub trait ServersetListener<M = ServersetMember>: Send + Sync {
+  fn on_join(&self, znode: &str, member: &M);
+  fn on_leave(&self, znode: &str);
+  fn on_update(&self, znode: &str, member: &M);
+}
+
+
+// A change to the membership of a Serverset, as delivered by Serverset::events.
+#[derive(Clone, Debug)]
+pub enum ServersetEvent<M = ServersetMember> {
+  MemberAdded(String, M),
+  MemberRemoved(String),
+  MemberChanged(String, M),
+  // Emitted after each full reconciliation pass, once the members reflect ZooKeeper.
+  SyncCompleted,
+  // Emitted with the new ZNode path when a member registered by this process is
+  // re-created after its session expired.
+  Reregistered(String),
+}
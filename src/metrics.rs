@@ -0,0 +1,214 @@
+use error::DiscoError;
+use serverset::ServiceEndpoint;
+
+#[cfg(feature = "prometheus")]
+use prometheus::{Gauge, Histogram, HistogramOpts, IntCounter, Registry};
+#[cfg(feature = "prometheus")]
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+#[cfg(feature = "prometheus")]
+use std::sync::Mutex;
+use std::time::Duration;
+
+
+// The names SinkMetrics reports under. They are stable and follow Prometheus
+// conventions, so that dashboards built on them keep working across releases.
+// Counted for every reconciliation pass started, completed and failed.
+pub const SYNCS_STARTED: &'static str = "discotech_syncs_started_total";
+pub const SYNCS_COMPLETED: &'static str = "discotech_syncs_completed_total";
+pub const SYNCS_FAILED: &'static str = "discotech_syncs_failed_total";
+// How long each reconciliation pass took, whether or not it succeeded.
+pub const SYNC_DURATION: &'static str = "discotech_sync_duration_seconds";
+// The number of members after the latest successful pass.
+pub const MEMBERS: &'static str = "discotech_members";
+pub const MEMBERS_ADDED: &'static str = "discotech_members_added_total";
+pub const MEMBERS_REMOVED: &'static str = "discotech_members_removed_total";
+// Counted for every read of a member whose data could not be decoded.
+pub const DECODE_FAILURES: &'static str = "discotech_member_decode_failures_total";
+
+
+// Receives counts of reconciliation outcomes, e.g. to forward them to Prometheus or
+// statsd. Every hook does nothing by default, so implementations need only override the
+// ones they report. Hooks are invoked from the watch thread and should return quickly.
+pub trait Metrics: Send + Sync {
+  fn on_reconcile_started(&self) {}
+  // A reconciliation pass finished after `elapsed`, whether or not it succeeded.
+  fn on_reconcile_finished(&self, _elapsed: Duration) {}
+  // A reconciliation pass completed, leaving `member_count` members.
+  fn on_reconcile_success(&self, _member_count: usize) {}
+  fn on_reconcile_error(&self, _reason: &DiscoError) {}
+  fn on_member_added(&self, _znode: &str) {}
+  fn on_member_removed(&self, _znode: &str) {}
+  // The data of the given member ZNode could not be decoded.
+  fn on_parse_error(&self, _znode: &str) {}
+  // A reconciliation pass found more than one member announcing each of `endpoints`.
+  fn on_duplicate_endpoints(&self, _endpoints: &[ServiceEndpoint]) {}
+}
+
+// Lets DiscoConfig, which holds an Arc<Metrics>, keep deriving Debug and PartialEq. Two
+// implementations are equal only if they are the same object.
+impl fmt::Debug for Metrics {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Metrics")
+  }
+}
+
+impl PartialEq for Metrics {
+  fn eq(&self, other: &Metrics) -> bool {
+    self as *const Metrics as *const u8 == other as *const Metrics as *const u8
+  }
+}
+
+
+// The default Metrics, which discards everything.
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+impl MetricsSink for NoopMetrics {}
+
+
+// Receives named measurements, e.g. to forward them to statsd. Wrapped in SinkMetrics,
+// a sink is told of reconciliation outcomes under the names above. Every method does
+// nothing by default, and all are invoked from the watch thread.
+pub trait MetricsSink: Send + Sync {
+  fn gauge(&self, _name: &str, _value: f64) {}
+  fn counter(&self, _name: &str, _delta: u64) {}
+  fn timing(&self, _name: &str, _duration: Duration) {}
+}
+
+
+// Reports reconciliation outcomes to a MetricsSink, e.g. as the metrics of
+// DiscoConfigBuilder. Serversets sharing a SinkMetrics report under the same names.
+pub struct SinkMetrics {
+  sink: Arc<MetricsSink>,
+}
+impl SinkMetrics {
+  pub fn new(sink: Arc<MetricsSink>) -> SinkMetrics {
+    SinkMetrics{sink: sink}
+  }
+}
+
+impl Metrics for SinkMetrics {
+  fn on_reconcile_started(&self) {
+    self.sink.counter(SYNCS_STARTED, 1);
+  }
+
+  fn on_reconcile_finished(&self, elapsed: Duration) {
+    self.sink.timing(SYNC_DURATION, elapsed);
+  }
+
+  fn on_reconcile_success(&self, member_count: usize) {
+    self.sink.counter(SYNCS_COMPLETED, 1);
+    self.sink.gauge(MEMBERS, member_count as f64);
+  }
+
+  fn on_reconcile_error(&self, _reason: &DiscoError) {
+    self.sink.counter(SYNCS_FAILED, 1);
+  }
+
+  fn on_member_added(&self, _znode: &str) {
+    self.sink.counter(MEMBERS_ADDED, 1);
+  }
+
+  fn on_member_removed(&self, _znode: &str) {
+    self.sink.counter(MEMBERS_REMOVED, 1);
+  }
+
+  fn on_parse_error(&self, _znode: &str) {
+    self.sink.counter(DECODE_FAILURES, 1);
+  }
+}
+
+
+// A MetricsSink that records into a Prometheus registry, registering each metric the
+// first time it is reported: gauges as gauges, counters as counters, and timings as
+// histograms of seconds.
+#[cfg(feature = "prometheus")]
+pub struct PrometheusSink {
+  registry: Registry,
+  gauges: Mutex<HashMap<String, Gauge>>,
+  counters: Mutex<HashMap<String, IntCounter>>,
+  histograms: Mutex<HashMap<String, Histogram>>,
+}
+#[cfg(feature = "prometheus")]
+impl PrometheusSink {
+  pub fn new(registry: Registry) -> PrometheusSink {
+    PrometheusSink{
+      registry: registry,
+      gauges: Mutex::new(HashMap::new()),
+      counters: Mutex::new(HashMap::new()),
+      histograms: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn registry(&self) -> &Registry {
+    &self.registry
+  }
+
+  // Registers a new metric, logging rather than failing if the registry refuses it, so
+  // that a clash with another library's metric does not stop discovery.
+  fn register<M>(&self, name: &str, metric: &M)
+      where M: prometheus::core::Collector + Clone + 'static {
+    match self.registry.register(Box::new(metric.clone())) {
+      Err(reason) => warn!("Could not register metric {}: {}", name, reason),
+      Ok(()) => (),
+    }
+  }
+}
+
+#[cfg(feature = "prometheus")]
+impl MetricsSink for PrometheusSink {
+  fn gauge(&self, name: &str, value: f64) {
+    let mut gauges = self.gauges.lock().unwrap();
+    if !gauges.contains_key(name) {
+      match Gauge::new(name, name) {
+        Ok(gauge) => {
+          self.register(name, &gauge);
+          gauges.insert(name.to_string(), gauge);
+        },
+        Err(reason) => {
+          warn!("Invalid metric {}: {}", name, reason);
+          return
+        },
+      }
+    }
+    gauges[name].set(value);
+  }
+
+  fn counter(&self, name: &str, delta: u64) {
+    let mut counters = self.counters.lock().unwrap();
+    if !counters.contains_key(name) {
+      match IntCounter::new(name, name) {
+        Ok(counter) => {
+          self.register(name, &counter);
+          counters.insert(name.to_string(), counter);
+        },
+        Err(reason) => {
+          warn!("Invalid metric {}: {}", name, reason);
+          return
+        },
+      }
+    }
+    counters[name].inc_by(delta);
+  }
+
+  fn timing(&self, name: &str, duration: Duration) {
+    let mut histograms = self.histograms.lock().unwrap();
+    if !histograms.contains_key(name) {
+      match Histogram::with_opts(HistogramOpts::new(name, name)) {
+        Ok(histogram) => {
+          self.register(name, &histogram);
+          histograms.insert(name.to_string(), histogram);
+        },
+        Err(reason) => {
+          warn!("Invalid metric {}: {}", name, reason);
+          return
+        },
+      }
+    }
+    let seconds = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+    histograms[name].observe(seconds);
+  }
+}
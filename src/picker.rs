@@ -0,0 +1,357 @@
+use serverset::{Serverset, ServersetMember, ServiceEndpoint};
+use status::MemberStatus;
+
+use rand;
+use rand::Rng;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+// The number of points each member takes on a ConsistentHashPicker's ring by default.
+pub const DEFAULT_VIRTUAL_NODES: usize = 100;
+
+// The parameters of 64-bit FNV-1a.
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+
+// Hands out the endpoints of live Serverset members in turn. The members are ordered
+// once per change to the Serverset rather than on every call.
+pub struct RoundRobin {
+  serverset: Arc<Serverset>,
+  cursor: AtomicUsize,
+  order: RwLock<MemberOrder>,
+}
+impl RoundRobin {
+  pub fn new(serverset: Arc<Serverset>) -> RoundRobin {
+    RoundRobin{
+      serverset: serverset,
+      cursor: AtomicUsize::new(0),
+      order: RwLock::new(MemberOrder{
+        members: Arc::new(HashMap::new()),
+        alive_members: Vec::new(),
+      }),
+    }
+  }
+
+  pub fn next_endpoint(&self) -> Option<ServiceEndpoint> {
+    self.next_matching(|member| Some(&member.serviceEndpoint))
+  }
+
+  // Like next_endpoint, but hands out the additional endpoint named `name`, skipping
+  // members that do not advertise it.
+  pub fn next_additional(&self, name: &str) -> Option<ServiceEndpoint> {
+    self.next_matching(|member| member.additionalEndpoints.get(name))
+  }
+
+  fn next_matching<F>(&self, endpoint: F) -> Option<ServiceEndpoint>
+      where F: Fn(&ServersetMember) -> Option<&ServiceEndpoint> {
+    self.with_order(|alive_members| {
+      let matching = alive_members.iter().filter(|member| endpoint(member).is_some())
+        .count();
+      if matching == 0 {
+        return None
+      }
+      // Takes the cursor modulo the current count so it wraps cleanly as the Serverset
+      // changes size.
+      let idx = self.cursor.fetch_add(1, Ordering::Relaxed) % matching;
+      alive_members.iter().filter_map(&endpoint).nth(idx).cloned()
+    })
+  }
+
+  // Runs `f` on the live members ordered as of the current members.
+  fn with_order<F, T>(&self, f: F) -> T where F: Fn(&[ServersetMember]) -> T {
+    let members = self.serverset.members();
+    {
+      let order = self.order.read().unwrap();
+      if Arc::ptr_eq(&order.members, &members) {
+        return f(&order.alive_members)
+      }
+    }
+    let mut order = self.order.write().unwrap();
+    if !Arc::ptr_eq(&order.members, &members) {
+      *order = MemberOrder::new(members);
+    }
+    f(&order.alive_members)
+  }
+}
+
+
+// The live members of a RoundRobin, as of the members they were taken from.
+struct MemberOrder {
+  members: Arc<HashMap<String, ServersetMember>>,
+  alive_members: Vec<ServersetMember>,
+}
+impl MemberOrder {
+  // Orders the live members by ZNode, so successive calls walk a stable sequence.
+  fn new(members: Arc<HashMap<String, ServersetMember>>) -> MemberOrder {
+    let mut alive: Vec<(&String, &ServersetMember)> = members.iter()
+      .filter(|&(_, member)| member.status == MemberStatus::Alive)
+      .collect();
+    alive.sort_by(|a, b| a.0.cmp(b.0));
+    let alive_members = alive.into_iter().map(|(_, member)| member.clone()).collect();
+    MemberOrder{
+      members: members,
+      alive_members: alive_members,
+    }
+  }
+}
+
+
+impl Serverset {
+  // Returns a RoundRobin over this Serverset's live members. Each picker keeps its own
+  // place, and reads the current members on every call.
+  pub fn picker(self: &Arc<Self>) -> RoundRobin {
+    RoundRobin::new(self.clone())
+  }
+}
+
+
+// Maps keys to live Serverset members on a hash ring over their ZNode names, so that a
+// key keeps going to the same member while it lives, and a member joining or leaving
+// moves only the keys that land on it. RingKey::Endpoint builds the ring over their
+// serviceEndpoints instead.
+pub struct ConsistentHashPicker {
+  serverset: Arc<Serverset>,
+  ring_key: RingKey,
+  virtual_nodes: usize,
+  ring: RwLock<HashRing>,
+}
+impl ConsistentHashPicker {
+  pub fn new(serverset: Arc<Serverset>) -> ConsistentHashPicker {
+    ConsistentHashPicker::with_virtual_nodes(serverset, DEFAULT_VIRTUAL_NODES)
+  }
+
+  // Gives each member `virtual_nodes` points on the ring. More points spread keys more
+  // evenly at the cost of memory and slower rebuilds.
+  pub fn with_virtual_nodes(serverset: Arc<Serverset>, virtual_nodes: usize)
+      -> ConsistentHashPicker {
+    ConsistentHashPicker::with_ring_key(serverset, RingKey::MemberZnode, virtual_nodes)
+  }
+
+  // Places what `ring_key` names on the ring, each with `virtual_nodes` points.
+  pub fn with_ring_key(serverset: Arc<Serverset>, ring_key: RingKey, virtual_nodes: usize)
+      -> ConsistentHashPicker {
+    ConsistentHashPicker{
+      serverset: serverset,
+      ring_key: ring_key,
+      virtual_nodes: virtual_nodes,
+      ring: RwLock::new(HashRing{
+        members: Arc::new(HashMap::new()),
+        node_members: HashMap::new(),
+        points: BTreeMap::new(),
+      }),
+    }
+  }
+
+  // Returns the live member that `key` maps to, or None if there are no live members.
+  pub fn pick(&self, key: &[u8]) -> Option<ServersetMember> {
+    self.with_ring(|ring| ring.owner(key).cloned())
+  }
+
+  // Returns the endpoint of the live member that `key` maps to.
+  pub fn pick_for_key(&self, key: &[u8]) -> Option<ServiceEndpoint> {
+    self.with_ring(|ring| ring.owner(key).map(|member| member.serviceEndpoint.clone()))
+  }
+
+  // Runs `f` on a ring caught up with the current members.
+  fn with_ring<F, T>(&self, f: F) -> T where F: Fn(&HashRing) -> T {
+    let members = self.serverset.members();
+    {
+      let ring = self.ring.read().unwrap();
+      if Arc::ptr_eq(&ring.members, &members) {
+        return f(&ring)
+      }
+    }
+    let mut ring = self.ring.write().unwrap();
+    // Another caller may have caught the ring up while the lock was released.
+    if !Arc::ptr_eq(&ring.members, &members) {
+      ring.update(members, self.ring_key, self.virtual_nodes);
+    }
+    f(&ring)
+  }
+}
+
+
+// What a ConsistentHashPicker places on its ring.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RingKey {
+  // Each live member's ZNode name, the default.
+  MemberZnode,
+  // Each distinct serviceEndpoint of the live members. A restarted instance, which
+  // announces under a new ZNode, then gets its keys back.
+  Endpoint,
+}
+impl RingKey {
+  // Returns the name of `member`'s node on the ring, e.g. "10.0.0.1:9090".
+  fn node(&self, member_znode: &str, member: &ServersetMember) -> String {
+    match *self {
+      RingKey::MemberZnode => member_znode.to_string(),
+      RingKey::Endpoint =>
+          format!("{}:{}", member.serviceEndpoint.host, member.serviceEndpoint.port),
+    }
+  }
+}
+
+
+// The points of a ConsistentHashPicker, as of the members it was last updated from.
+struct HashRing {
+  members: Arc<HashMap<String, ServersetMember>>,
+  // The ZNode of a live member behind each node on the ring.
+  node_members: HashMap<String, String>,
+  points: BTreeMap<u64, String>,
+}
+impl HashRing {
+  // Adds and removes only the points of nodes that have come alive or gone since the
+  // last update.
+  fn update(&mut self, members: Arc<HashMap<String, ServersetMember>>, ring_key: RingKey,
+      virtual_nodes: usize) {
+    let mut node_members: HashMap<String, String> = HashMap::new();
+    for (member_znode, member) in members.iter() {
+      if member.status != MemberStatus::Alive {
+        continue
+      }
+      // Of several members announcing one endpoint, the lowest ZNode wins, so that the
+      // choice does not depend on the map's order.
+      let owner = node_members.entry(ring_key.node(member_znode, member))
+        .or_insert_with(|| member_znode.clone());
+      if member_znode < owner {
+        *owner = member_znode.clone();
+      }
+    }
+    for node in self.node_members.keys() {
+      if node_members.contains_key(node) {
+        continue
+      }
+      for virtual_node in 0..virtual_nodes {
+        let point = hash_point(node, virtual_node);
+        // Leaves the point alone in the unlikely event it collided with another node's.
+        if self.points.get(&point) == Some(node) {
+          self.points.remove(&point);
+        }
+      }
+    }
+    for node in node_members.keys() {
+      if self.node_members.contains_key(node) {
+        continue
+      }
+      for virtual_node in 0..virtual_nodes {
+        self.points.insert(hash_point(node, virtual_node), node.clone());
+      }
+    }
+    self.node_members = node_members;
+    self.members = members;
+  }
+
+  // Returns the member behind the first point at or after the key's hash, wrapping
+  // around to the first point on the ring.
+  fn owner(&self, key: &[u8]) -> Option<&ServersetMember> {
+    let hash = hash_bytes(key);
+    self.points.range(hash..).next()
+      .or_else(|| self.points.iter().next())
+      .and_then(|(_, node)| self.node_members.get(node))
+      .and_then(|member_znode| self.members.get(member_znode))
+  }
+}
+
+
+// Picks live members at random in proportion to their weights, so that e.g. a canary of
+// weight 1 among members of weight 99 takes about 1% of traffic. Members of weight 0 are
+// never picked. Unlike Serverset::pick_weighted, the cumulative weights are kept between
+// calls and only rebuilt when the members change, so each pick is a binary search.
+pub struct WeightedPicker {
+  serverset: Arc<Serverset>,
+  distribution: RwLock<WeightDistribution>,
+}
+impl WeightedPicker {
+  pub fn new(serverset: Arc<Serverset>) -> WeightedPicker {
+    WeightedPicker{
+      serverset: serverset,
+      distribution: RwLock::new(WeightDistribution{
+        members: Arc::new(HashMap::new()),
+        cumulative_weights: Vec::new(),
+      }),
+    }
+  }
+
+  // Returns a weighted random live endpoint, or None if no live member has any weight.
+  pub fn pick(&self) -> Option<ServiceEndpoint> {
+    let members = self.serverset.members();
+    {
+      let distribution = self.distribution.read().unwrap();
+      if Arc::ptr_eq(&distribution.members, &members) {
+        return distribution.pick()
+      }
+    }
+    let mut distribution = self.distribution.write().unwrap();
+    if !Arc::ptr_eq(&distribution.members, &members) {
+      *distribution = WeightDistribution::new(members);
+    }
+    distribution.pick()
+  }
+}
+
+
+// The running total of weights over the live members with any weight, each paired with
+// that member's endpoint.
+struct WeightDistribution {
+  members: Arc<HashMap<String, ServersetMember>>,
+  cumulative_weights: Vec<(u64, ServiceEndpoint)>,
+}
+impl WeightDistribution {
+  fn new(members: Arc<HashMap<String, ServersetMember>>) -> WeightDistribution {
+    let mut total_weight = 0;
+    let cumulative_weights = members.values()
+      .filter(|member| member.status == MemberStatus::Alive && member.weight() > 0)
+      .map(|member| {
+        total_weight += member.weight() as u64;
+        (total_weight, member.serviceEndpoint.clone())
+      })
+      .collect();
+    WeightDistribution{
+      members: members,
+      cumulative_weights: cumulative_weights,
+    }
+  }
+
+  fn pick(&self) -> Option<ServiceEndpoint> {
+    let total_weight = match self.cumulative_weights.last() {
+      Some(&(total_weight, _)) => total_weight,
+      None => return None,
+    };
+    // The first member whose running total exceeds the point owns it.
+    let point = rand::thread_rng().gen_range(0, total_weight);
+    let idx = match self.cumulative_weights.binary_search_by(|&(weight, _)| {
+      if weight <= point { cmp::Ordering::Less } else { cmp::Ordering::Greater }
+    }) {
+      Ok(idx) | Err(idx) => idx,
+    };
+    Some(self.cumulative_weights[idx].1.clone())
+  }
+}
+
+
+// Hashes with 64-bit FNV-1a, then murmur3's finalizer to spread nearby inputs, such as
+// the points of one endpoint, around the ring. Both are specified, unlike DefaultHasher,
+// so clients built with any Rust release agree on where every key lands.
+fn hash_bytes(bytes: &[u8]) -> u64 {
+  let mut hash = FNV_OFFSET_BASIS;
+  for byte in bytes {
+    hash ^= *byte as u64;
+    hash = hash.wrapping_mul(FNV_PRIME);
+  }
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+  hash ^ (hash >> 33)
+}
+
+
+// Returns where a virtual node of a ring node lies on the ring, hashing e.g.
+// "10.0.0.1:9090#3".
+fn hash_point(node: &str, virtual_node: usize) -> u64 {
+  hash_bytes(format!("{}#{}", node, virtual_node).as_bytes())
+}
@@ -0,0 +1,124 @@
+extern crate discotech_zookeeper;
+
+use codec::MemberCodec;
+use error::DiscoError;
+use serverset::ServersetMember;
+use status::MemberStatus;
+use zk::ZkClient;
+
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+use discotech_zookeeper::{Acl, ZkError};
+
+
+// The state of one announced membership, shared between its MemberHandle and the
+// Serverset so the ZNode can be re-created if the session expires.
+pub struct Registration<M = ServersetMember> {
+  pub znode: String,
+  // The zxid that created our ZNode, used to tell it apart from one re-created under the
+  // same path by another process.
+  pub czxid: i64,
+  // The data version we last wrote, for compare-and-set updates.
+  pub version: i32,
+  pub member: M,
+  // The ACL the ZNode was created with, reapplied if it is re-created.
+  pub acl: Vec<Acl>,
+  pub registered: bool,
+}
+
+
+// A Serverset membership announced by this process. The membership is withdrawn when the
+// handle is deregistered or dropped.
+pub struct MemberHandle<M = ServersetMember> {
+  // Shared with the Serverset, which swaps in a new client after reconnecting.
+  zk_client: Arc<RwLock<Arc<ZkClient>>>,
+  codec: Arc<MemberCodec<M>>,
+  registration: Arc<Mutex<Registration<M>>>,
+}
+impl<M> MemberHandle<M> {
+  pub fn new(zk_client: Arc<RwLock<Arc<ZkClient>>>, codec: Arc<MemberCodec<M>>,
+      registration: Arc<Mutex<Registration<M>>>) -> MemberHandle<M> {
+    MemberHandle{
+      zk_client: zk_client,
+      codec: codec,
+      registration: registration,
+    }
+  }
+
+  // The full path of the ephemeral ZNode created for the member. This changes if the
+  // member is re-registered after a session expiry.
+  pub fn znode(&self) -> String {
+    self.registration.lock().unwrap().znode.clone()
+  }
+
+  // Releases the handle without deleting the ZNode, leaving the membership in place for
+  // the rest of the ZooKeeper session.
+  pub fn into_znode(self) -> String {
+    let mut registration = self.registration.lock().unwrap();
+    registration.registered = false;
+    mem::replace(&mut registration.znode, String::new())
+  }
+
+  // Deletes the member's ZNode so clients stop routing to it immediately.
+  pub fn deregister(self) -> Result<(), DiscoError> {
+    self.delete()
+  }
+
+  fn client(&self) -> Arc<ZkClient> {
+    self.zk_client.read().unwrap().clone()
+  }
+
+  fn delete(&self) -> Result<(), DiscoError> {
+    let mut registration = self.registration.lock().unwrap();
+    if !registration.registered {
+      return Ok(())
+    }
+    registration.registered = false;
+    let zk_client = self.client();
+    match zk_client.exists(registration.znode.as_str(), false) {
+      Err(ZkError::NoNode) => Ok(()),
+      Err(reason) => Err(DiscoError::from_zk(&registration.znode, reason)),
+      Ok(ref stat) if stat.czxid != registration.czxid => {
+        warn!("Not deleting {}, as it was re-created by another session",
+            registration.znode);
+        Ok(())
+      },
+      Ok(stat) => match zk_client.delete(registration.znode.as_str(), stat.version) {
+        Ok(()) | Err(ZkError::NoNode) => Ok(()),
+        Err(reason) => Err(DiscoError::from_zk(&registration.znode, reason)),
+      },
+    }
+  }
+}
+
+// Only a ServersetMember's status is known to be writable.
+impl MemberHandle {
+  // Rewrites the member's status, e.g. to STOPPING during a graceful drain. Fails with
+  // DiscoError::VersionConflict if the ZNode was written by someone else since.
+  pub fn set_status(&self, status: MemberStatus) -> Result<(), DiscoError> {
+    let mut registration = self.registration.lock().unwrap();
+    let mut member = registration.member.clone();
+    member.status = status;
+    let member_data = try!(self.codec.encode(&member));
+    match self.client().set_data(registration.znode.as_str(), member_data,
+        registration.version) {
+      Ok(stat) => {
+        registration.version = stat.version;
+        registration.member = member;
+        Ok(())
+      },
+      Err(ZkError::BadVersion) =>
+          Err(DiscoError::VersionConflict(registration.znode.clone())),
+      Err(reason) => Err(DiscoError::from_zk(&registration.znode, reason)),
+    }
+  }
+}
+
+impl<M> Drop for MemberHandle<M> {
+  fn drop(&mut self) {
+    match self.delete() {
+      Err(reason) => warn!("Could not deregister {}: {}", self.znode(), reason),
+      Ok(()) => (),
+    }
+  }
+}
@@ -0,0 +1,87 @@
+use config::DiscoConfig;
+use error::DiscoError;
+use serverset::{Serverset, ServersetManager, WatchHandle};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+
+// Serversets looked up by a name of the caller's choosing, e.g. "user-service", rather
+// than by ZNode, for services talking to many backends. Every Serverset shares the one
+// ZooKeeper session and watch loop of an underlying ServersetManager while keeping its
+// own members map and event stream. Names may be added and removed while it is watched.
+pub struct ServersetRegistry {
+  manager: Arc<ServersetManager>,
+  // The ZNode and Serverset each name refers to.
+  names: RwLock<HashMap<String, (String, Arc<Serverset>)>>,
+  // Serializes add and remove, so that a ZNode is not dropped from the manager while
+  // another name is being pointed at it.
+  changing: Mutex<()>,
+}
+impl ServersetRegistry {
+  // Connects with every setting of `config` but serverset_znode, which each added name
+  // supplies instead.
+  pub fn new(discoConfig: DiscoConfig) -> Result<ServersetRegistry, DiscoError> {
+    Ok(ServersetRegistry{
+      manager: Arc::new(try!(ServersetManager::new(discoConfig))),
+      names: RwLock::new(HashMap::new()),
+      changing: Mutex::new(()),
+    })
+  }
+
+  // Starts watching the given ZNode under `name`, replacing whatever the name referred
+  // to, and returns its Serverset. Names referring to the same ZNode share a Serverset.
+  // A new Serverset is reconciled once before this returns.
+  pub fn add(&self, name: &str, serverset_znode: &str)
+      -> Result<Arc<Serverset>, DiscoError> {
+    let _changing = self.changing.lock().unwrap();
+    let serverset = try!(self.manager.serverset(serverset_znode));
+    let replaced = self.names.write().unwrap().insert(name.to_string(),
+        (serverset_znode.to_string(), serverset.clone()));
+    match replaced {
+      Some((ref replaced_znode, _)) if replaced_znode != serverset_znode =>
+          self.release(replaced_znode),
+      _ => (),
+    }
+    Ok(serverset)
+  }
+
+  pub fn get(&self, name: &str) -> Option<Arc<Serverset>> {
+    self.names.read().unwrap().get(name).map(|&(_, ref serverset)| serverset.clone())
+  }
+
+  // Forgets `name`, returning its Serverset. The ZNode stops being watched once no name
+  // refers to it.
+  pub fn remove(&self, name: &str) -> Option<Arc<Serverset>> {
+    let _changing = self.changing.lock().unwrap();
+    let removed = self.names.write().unwrap().remove(name);
+    removed.map(|(serverset_znode, serverset)| {
+      self.release(&serverset_znode);
+      serverset
+    })
+  }
+
+  // Returns every registered name, in no particular order.
+  pub fn names(&self) -> Vec<String> {
+    self.names.read().unwrap().keys().cloned().collect()
+  }
+
+  // Starts the single thread that keeps every registered Serverset up to date.
+  pub fn watch(&self) -> WatchHandle {
+    self.manager.watch()
+  }
+
+  // Returns true while the shared ZooKeeper session is connected.
+  pub fn is_connected(&self) -> bool {
+    self.manager.is_connected()
+  }
+
+  // Stops watching the given ZNode unless a name still refers to it.
+  fn release(&self, serverset_znode: &str) {
+    let in_use = self.names.read().unwrap().values()
+      .any(|&(ref named_znode, _)| named_znode == serverset_znode);
+    if !in_use {
+      self.manager.remove(serverset_znode);
+    }
+  }
+}
@@ -1,153 +1,2525 @@
 extern crate log;
 extern crate discotech_zookeeper;
 
+use backend::DiscoveryBackend;
+use backoff::{self, Backoff, BackoffState};
+use codec::{CodecError, JsonCodec, MemberCodec};
 use config::*;
+use error::{DiscoError, WaitTimeout};
+use listener::{ServersetEvent, ServersetListener};
+use metrics::{Metrics, NoopMetrics};
+use registration::{MemberHandle, Registration};
+use status::{MemberStatus, StatusFilter};
+use zk::ZkClient;
 
-use rustc_serialize::json;
-use std::sync::RwLock;
+use rand;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::cmp;
+use std::time::{Duration, Instant, SystemTime};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use discotech_zookeeper::{Acl, CreateMode, Watcher, WatchedEvent, ZkError, ZooKeeper};
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use discotech_zookeeper::{Acl, CreateMode, KeeperState, Watcher, WatchedEvent};
+use discotech_zookeeper::WatchedEventType;
+use discotech_zookeeper::{Stat, ZkError, ZooKeeper};
 use discotech_zookeeper::perms;
 
 
-#[derive(Debug, RustcDecodable, RustcEncodable)]
+// How many member ZNodes a reconciliation pass fetches at once, unless configured.
+const DEFAULT_MEMBER_FETCH_THREADS: usize = 8;
+
+// How many more times a member that could not be read is read again, unless configured,
+// and how long to wait before each.
+const DEFAULT_MEMBER_READ_RETRIES: usize = 2;
+const MEMBER_READ_RETRY_DELAY_MS: u64 = 50;
+
+// How much of a member's data that could not be decoded is quoted, unless configured.
+const DEFAULT_MEMBER_PAYLOAD_LOG_BYTES: usize = 512;
+
+// How many of the members that failed to parse a ValidationReport holds the errors of.
+const VALIDATION_SAMPLE_ERRORS: usize = 5;
+
+// How many conditional writes set_status makes before giving up on a contended member.
+const SET_STATUS_ATTEMPTS: usize = 3;
+
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct ServiceEndpoint {
   pub host: String,
   pub port: u16,
 }
+impl ServiceEndpoint {
+  // Creates an endpoint, rejecting an empty host. IPv6 literals may be given with or
+  // without brackets.
+  pub fn new(host: &str, port: u16) -> Result<ServiceEndpoint, DiscoError> {
+    let host = host.trim().trim_matches(|c| c == '[' || c == ']');
+    if host.is_empty() {
+      return Err(DiscoError::InvalidEndpoint(format!("empty host with port {}", port)))
+    }
+    Ok(ServiceEndpoint{
+      host: host.to_string(),
+      port: port,
+    })
+  }
+
+  // Resolves the host, which may be a hostname or an IPv4 or IPv6 literal, to the
+  // addresses to connect to.
+  pub fn to_socket_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+    let host = self.host.trim_matches(|c| c == '[' || c == ']');
+    Ok(try!((host, self.port).to_socket_addrs()).collect())
+  }
+
+  // Formats the endpoint as host:port, bracketing IPv6 literals as in "[::1]:9090".
+  pub fn addr(&self) -> String {
+    let host = self.host.trim_matches(|c| c == '[' || c == ']');
+    if host.contains(':') {
+      format!("[{}]:{}", host, self.port)
+    } else {
+      format!("{}:{}", host, self.port)
+    }
+  }
+}
 
 
-#[derive(Debug, RustcDecodable, RustcEncodable)]
+// Not Hash, as additionalEndpoints is a HashMap.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ServersetMember {
   pub serviceEndpoint: ServiceEndpoint,
+  #[serde(default)]
   pub additionalEndpoints: HashMap<String, ServiceEndpoint>,
-  pub status: String,
+  pub status: MemberStatus,
+  // The instance number announced by Aurora for sharded services, if any.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub shard: Option<u32>,
+  // The member's share of traffic relative to the others under pick_weighted, if it
+  // announces one. See weight().
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub weight: Option<u32>,
+  // Any other fields of the announcement, such as a datacenter or build id, which are
+  // written back out alongside the rest. See extra_fields_as().
+  #[serde(flatten)]
+  pub extra_fields: HashMap<String, serde_json::Value>,
+}
+impl ServersetMember {
+  // Parses a member as announced in a Serverset ZNode. Announcers vary, so unknown fields
+  // are kept in extra_fields, a missing additionalEndpoints is taken as empty, and a
+  // missing status is taken as the given default.
+  pub fn from_json(member_json: &str, default_status: &MemberStatus)
+      -> Result<ServersetMember, serde_json::Error> {
+    let member: AnnouncedMember = try!(serde_json::from_str(member_json));
+    Ok(ServersetMember{
+      serviceEndpoint: member.serviceEndpoint,
+      additionalEndpoints: member.additionalEndpoints,
+      status: match member.status {
+        Some(status) => status,
+        None => default_status.clone(),
+      },
+      shard: member.shard,
+      weight: member.weight,
+      extra_fields: member.extra_fields,
+    })
+  }
+
+  // Decodes the fields that ServersetMember has no place for into a caller-defined type,
+  // so that site-specific metadata can be read strongly typed.
+  pub fn extra_fields_as<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+    let extra_fields = self.extra_fields.iter()
+      .map(|(field, value)| (field.clone(), value.clone()))
+      .collect();
+    serde_json::from_value(serde_json::Value::Object(extra_fields))
+  }
+
+  // The announced weight, defaulting to 1. Members of weight 0 are never picked by
+  // pick_weighted.
+  pub fn weight(&self) -> u32 {
+    self.weight.unwrap_or(1)
+  }
+}
+
+
+// What a Serverset needs of the type its members are decoded into, when that is not
+// ServersetMember: their status, so that the status filter and the ALIVE checks still
+// apply, and optionally their serviceEndpoint, so that duplicates are still spotted.
+pub trait HasStatus: Clone + PartialEq + Send + Sync + 'static {
+  fn status(&self) -> &MemberStatus;
+
+  fn service_endpoint(&self) -> Option<&ServiceEndpoint> {
+    None
+  }
 }
 
+impl HasStatus for ServersetMember {
+  fn status(&self) -> &MemberStatus {
+    &self.status
+  }
 
-struct NullWatcher;
-impl Watcher for NullWatcher {
-  fn handle(&self, e: &WatchedEvent) {}
+  fn service_endpoint(&self) -> Option<&ServiceEndpoint> {
+    Some(&self.serviceEndpoint)
+  }
 }
 
 
-pub struct Serverset {
-  config: DiscoConfig,
-  zk_client: ZooKeeper,
-  pub members: RwLock<HashMap<String, ServersetMember>>,
+// The wire form of a ServersetMember, in which every field but serviceEndpoint may be
+// omitted.
+#[derive(Deserialize)]
+struct AnnouncedMember {
+  serviceEndpoint: ServiceEndpoint,
+  #[serde(default)]
+  additionalEndpoints: HashMap<String, ServiceEndpoint>,
+  status: Option<MemberStatus>,
+  shard: Option<u32>,
+  weight: Option<u32>,
+  #[serde(flatten)]
+  extra_fields: HashMap<String, serde_json::Value>,
 }
-impl Serverset {
-  pub fn new(discoConfig: DiscoConfig) -> Serverset {
-    match ZooKeeper::connect(format!("{}:{}/",
-        discoConfig.zookeeper_host, discoConfig.zookeeper_port).as_str(),
-        Duration::from_secs(discoConfig.zookeeper_timeout_secs), NullWatcher) {
-      Err(reason) => panic!("Unable to connect to ZooKeeper: {}", reason),
-      Ok(client) => Serverset{
-        config: discoConfig,
-        zk_client: client,
-        members: RwLock::new(HashMap::new()),
+
+
+// Joins a ZNode path and a child name, or a relative path, with exactly one slash
+// between each segment, as ZooKeeper rejects paths with empty segments. Repeated and
+// trailing slashes are collapsed, and the result is always absolute, so e.g. "/a/" and
+// "/b" give "/a/b", and joining onto "/" does not produce "//child".
+pub fn join_znode(parent: &str, child: &str) -> String {
+  let segments: Vec<&str> = parent.split('/').chain(child.split('/'))
+    .filter(|segment| !segment.is_empty())
+    .collect();
+  format!("/{}", segments.join("/"))
+}
+
+// Parses the sequence number from a member ZNode named like `member_0000000012`, which
+// Aurora uses as the member's shard id. Other names have no shard id.
+pub fn shard_id(member_znode: &str) -> Option<u64> {
+  if !member_znode.starts_with("member_") {
+    return None
+  }
+  let digits = &member_znode["member_".len()..];
+  if digits.is_empty() || !digits.chars().all(|c| c.is_digit(10)) {
+    return None
+  }
+  digits.parse().ok()
+}
+
+
+// Grants everyone full access, matching ZooKeeper's OPEN_ACL_UNSAFE.
+fn open_acl() -> Vec<Acl> {
+  vec![Acl{
+    perms: perms::ALL,
+    scheme: "world".to_string(),
+    id: "anyone".to_string(),
+  }]
+}
+
+// Grants the authenticated identity full access, matching ZooKeeper's CREATOR_ALL_ACL.
+fn creator_all_acl() -> Vec<Acl> {
+  vec![Acl{
+    perms: perms::ALL,
+    scheme: "auth".to_string(),
+    id: String::new(),
+  }]
+}
+
+// Acl is not Clone, so ACLs kept for re-registration are copied field by field.
+fn copy_acl(acl: &[Acl]) -> Vec<Acl> {
+  acl.iter()
+    .map(|entry| Acl{
+      perms: entry.perms,
+      scheme: entry.scheme.clone(),
+      id: entry.id.clone(),
+    })
+    .collect()
+}
+
+
+// ZooKeeper watch and session notifications that require part of a Serverset to be
+// reconciled.
+pub(crate) enum ZnodeEvent {
+  ChildrenChanged(String),
+  DataChanged(String),
+  Deleted(String),
+  SessionConnected,
+  SessionExpired,
+  // Sent by a WatchHandle to wake its loop so that it notices it has been stopped.
+  Stopping,
+}
+
+
+// Forwards watch notifications from the ZooKeeper event thread to the watch loop, and
+// tracks whether the session is currently connected.
+struct ServersetWatcher {
+  events: Sender<ZnodeEvent>,
+  disconnected_since: Arc<Mutex<Option<Instant>>>,
+}
+impl Watcher for ServersetWatcher {
+  fn handle(&self, e: &WatchedEvent) {
+    match (&e.event_type, &e.keeper_state) {
+      (&WatchedEventType::None, &KeeperState::SyncConnected) |
+      (&WatchedEventType::None, &KeeperState::ConnectedReadOnly) =>
+          *self.disconnected_since.lock().unwrap() = None,
+      (&WatchedEventType::None, &KeeperState::Disconnected) |
+      (&WatchedEventType::None, &KeeperState::Expired) => {
+        let mut disconnected_since = self.disconnected_since.lock().unwrap();
+        if disconnected_since.is_none() {
+          *disconnected_since = Some(Instant::now());
+        }
       },
+      _ => (),
     }
+    let path = match e.path {
+      Some(ref path) => path.clone(),
+      None => String::new(),
+    };
+    let event = match e.event_type {
+      WatchedEventType::NodeChildrenChanged => ZnodeEvent::ChildrenChanged(path),
+      WatchedEventType::NodeDataChanged => ZnodeEvent::DataChanged(path),
+      WatchedEventType::NodeDeleted => ZnodeEvent::Deleted(path),
+      WatchedEventType::None => match e.keeper_state {
+        KeeperState::SyncConnected => ZnodeEvent::SessionConnected,
+        KeeperState::Expired => ZnodeEvent::SessionExpired,
+        _ => return,
+      },
+      _ => return,
+    };
+    // The receiver only goes away along with the Serverset, so a failed send is moot.
+    let _ = self.events.send(event);
   }
+}
 
-  pub fn watch(&self) {
-    let zk_client = self.zk_client.clone();
-    thread::spawn(move || {
-      self.update_members(zk_client);
-      thread::sleep_ms(self.config.zookeeper_poll_ms);
-    });
+
+// Builds the connect string for the configured servers, shuffled so that clients spread
+// their sessions across the ensemble.
+fn connect_string(config: &DiscoConfig) -> Result<String, DiscoError> {
+  let (mut servers, chroot) = match (config.servers(), config.chroot()) {
+    (Ok(servers), Ok(chroot)) => (servers, chroot),
+    (Err(reason), _) | (_, Err(reason)) =>
+        return Err(DiscoError::InvalidConnectString(reason.to_string())),
+  };
+  rand::thread_rng().shuffle(&mut servers);
+  let hosts: Vec<String> = servers.iter()
+    .map(|server| format!("{}:{}", server.host, server.port))
+    .collect();
+  // Without a chroot, the trailing slash roots the session at "/" as before.
+  match chroot {
+    "" => Ok(format!("{}/", hosts.join(","))),
+    chroot => Ok(format!("{}{}", hosts.join(","), chroot)),
   }
+}
 
-  fn remove_member(&self, member_znode: &String) {
-    self.members.write().unwrap().remove(member_znode);
+// Opens a ZooKeeper session whose watch notifications are forwarded to `events`.
+fn connect(config: &DiscoConfig, events: Sender<ZnodeEvent>,
+    disconnected_since: Arc<Mutex<Option<Instant>>>) -> Result<ZooKeeper, DiscoError> {
+  let connect_string = try!(connect_string(config));
+  let watcher = ServersetWatcher{
+    events: events,
+    disconnected_since: disconnected_since,
+  };
+  match ZooKeeper::connect(connect_string.as_str(),
+      Duration::from_secs(config.zookeeper_timeout_secs), watcher) {
+    Err(reason) => Err(DiscoError::from_connect(&connect_string, reason)),
+    Ok(client) => {
+      // Authenticates before the session is used for anything else. The config was
+      // validated, so either both zk_auth settings are set or neither is.
+      if let (&Some(ref scheme), &Some(ref credential)) =
+          (&config.zk_auth_scheme, &config.zk_auth_credential) {
+        try!(client.add_auth(scheme, credential.clone().into_bytes()));
+      }
+      Ok(client)
+    },
   }
+}
 
-  fn znode_exists(&self, zk_client: ZooKeeper, znode: &String) -> bool {
-    match zk_client.exists(znode, false) {
-      Ok(_) => true,
-      _ => false,
+
+// Controls a running watch loop, which is stopped when the handle is dropped. Once the
+// loop exits it releases its reference to the Serverset, so the ZooKeeper session closes
+// as soon as the Serverset itself is dropped.
+pub struct WatchHandle {
+  stop_signal: Arc<StopSignal>,
+  events_tx: Sender<ZnodeEvent>,
+  thread: Option<JoinHandle<()>>,
+}
+impl WatchHandle {
+  // Signals the watch loop to exit after its current pass and waits for it to finish.
+  // A loop waiting for the next poll or reconnection attempt is woken immediately.
+  pub fn stop(mut self) -> thread::Result<()> {
+    self.signal_stop();
+    self.join_thread()
+  }
+
+  // Blocks until the watch loop exits.
+  pub fn join(mut self) -> thread::Result<()> {
+    self.join_thread()
+  }
+
+  fn signal_stop(&self) {
+    self.stop_signal.stop();
+    // The loop may be waiting on the session's events rather than sleeping.
+    let _ = self.events_tx.send(ZnodeEvent::Stopping);
+  }
+
+  fn join_thread(&mut self) -> thread::Result<()> {
+    match self.thread.take() {
+      Some(thread) => thread.join(),
+      None => Ok(()),
     }
   }
+}
 
-  fn update_member(&self, zk_client: ZooKeeper, member_znode: &String) {
-    debug!("Adding Serverset member: {}", member_znode);
-    // If the Serverset member's ZNode does not exist, does not update the member.
-    let full_member_znode = format!("{}/{}", self.config.serverset_znode, member_znode);
-    if !self.znode_exists(zk_client, &full_member_znode) {
-      return
+impl Drop for WatchHandle {
+  fn drop(&mut self) {
+    self.signal_stop();
+    if self.join_thread().is_err() {
+      warn!("Serverset watch loop panicked");
+    }
+  }
+}
+
+
+// Tells a watch loop to exit, waking it from any sleep.
+struct StopSignal {
+  stopped: Mutex<bool>,
+  stopping: Condvar,
+}
+impl StopSignal {
+  fn new() -> StopSignal {
+    StopSignal{
+      stopped: Mutex::new(false),
+      stopping: Condvar::new(),
+    }
+  }
+
+  fn stop(&self) {
+    *self.stopped.lock().unwrap() = true;
+    self.stopping.notify_all();
+  }
+
+  fn is_stopped(&self) -> bool {
+    *self.stopped.lock().unwrap()
+  }
+
+  // Sleeps for `duration` unless stopped first, returning whether it was stopped.
+  fn sleep(&self, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    let mut stopped = self.stopped.lock().unwrap();
+    while !*stopped {
+      let now = Instant::now();
+      if now >= deadline {
+        return false
+      }
+      stopped = self.stopping.wait_timeout(stopped, deadline - now).unwrap().0;
+    }
+    true
+  }
+}
+
+
+// Returns whether the ZNode exists, or the error that kept us from finding out.
+fn znode_exists(zk_client: &ZkClient, znode: &str) -> Result<bool, ZkError> {
+  match zk_client.exists(znode, false) {
+    Ok(_) => Ok(true),
+    Err(ZkError::NoNode) => Ok(false),
+    Err(reason) => Err(reason),
+  }
+}
+
+
+// Returns each serviceEndpoint announced by more than one of `members`. Compares every
+// pair rather than building an index, so that nothing is allocated in the usual case of
+// there being no duplicates.
+fn find_duplicate_endpoints<M: HasStatus>(members: &HashMap<String, M>)
+    -> Vec<ServiceEndpoint> {
+  let mut duplicates: Vec<ServiceEndpoint> = Vec::new();
+  for (idx, member) in members.values().enumerate() {
+    let endpoint = match member.service_endpoint() {
+      Some(endpoint) => endpoint,
+      None => continue,
+    };
+    if duplicates.contains(endpoint) {
+      continue
+    }
+    if members.values().skip(idx + 1)
+        .any(|other| other.service_endpoint() == Some(endpoint)) {
+      duplicates.push(endpoint.clone());
+    }
+  }
+  duplicates
+}
+
+
+// Identifies the data a cached member was decoded from. Passing `version` to a
+// conditional write makes it fail if the member has been written since it was read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemberVersion {
+  pub version: i32,
+  pub mzxid: i64,
+  // When the data was last written, in milliseconds since the epoch.
+  pub mtime: i64,
+}
+impl MemberVersion {
+  fn from_stat(stat: &Stat) -> MemberVersion {
+    MemberVersion{
+      version: stat.version,
+      mzxid: stat.mzxid,
+      mtime: stat.mtime,
+    }
+  }
+}
+
+
+// The outcome of reading a member's ZNode.
+enum MemberFetch<M> {
+  Missing,
+  // The data has not been modified since it was read at the known mzxid.
+  Unchanged,
+  // The member, and the version of the data it was decoded from.
+  Fetched(M, MemberVersion),
+}
+
+
+// What a member fetch thread reports to its reconciliation pass: that it has begun
+// fetching a member, so the fetch can be timed from then, and how the fetch went.
+enum FetchProgress<M> {
+  Started(String, Instant),
+  Finished(String, Result<MemberFetch<M>, DiscoError>),
+}
+
+
+// Counts a member fetch thread as running until it exits, however it does.
+struct FetchThreadGuard(Arc<AtomicUsize>);
+
+impl Drop for FetchThreadGuard {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+
+// Reads and decodes a member's ZNode, skipping the read if its data was last modified at
+// `known_mzxid`. Every write gives the data a new mzxid, so a member rewritten with data
+// it held before is still re-read. Leaves a data watch on the member so changes to it are
+// picked up promptly. The existence check and the read both go through the one handle
+// passed in, which callers take from the Session once per sync, so a reconnect mid-sync
+// cannot split them across two sessions. An existence check, read or decode that fails
+// is tried up to `read_retries` more times, as one racing a write may succeed straight
+// away; only NoNode means the member is missing. Data that cannot be decoded is quoted
+// in the error up to `payload_log_bytes`.
+fn fetch_member<M>(zk_client: &ZkClient, codec: &MemberCodec<M>, full_member_znode: &str,
+    known_mzxid: Option<i64>, read_retries: usize, payload_log_bytes: usize)
+    -> Result<MemberFetch<M>, DiscoError> {
+  let mut retries_left = read_retries;
+  loop {
+    let fetched = match zk_client.exists(full_member_znode, false) {
+      // The data watch left by the earlier read is still armed, as it only fires on a
+      // change.
+      Ok(ref stat) if known_mzxid == Some(stat.mzxid) =>
+          return Ok(MemberFetch::Unchanged),
+      Ok(_) => match zk_client.get_data(full_member_znode, true) {
+        Ok((member_data, stat)) =>
+            decode_member(codec, full_member_znode, &member_data, payload_log_bytes)
+              .map(|member| {
+                MemberFetch::Fetched(member, MemberVersion::from_stat(&stat))
+              }),
+        // The member left between the existence check and the read.
+        Err(ZkError::NoNode) => return Ok(MemberFetch::Missing),
+        Err(reason) => Err(DiscoError::from_zk(full_member_znode, reason)),
+      },
+      Err(ZkError::NoNode) => return Ok(MemberFetch::Missing),
+      Err(reason) => Err(DiscoError::from_zk(full_member_znode, reason)),
+    };
+    match fetched {
+      Err(DiscoError::NoAuth(_)) => return fetched,
+      Err(ref reason) if retries_left > 0 => {
+        debug!("Retrying read of {}: {}", full_member_znode, reason);
+        retries_left -= 1;
+      },
+      fetched => return fetched,
+    }
+    thread::sleep(Duration::from_millis(MEMBER_READ_RETRY_DELAY_MS));
+  }
+}
+
+// Decodes the data of the given member, quoting up to `payload_log_bytes` of it in the
+// error if it cannot be decoded.
+fn decode_member<M>(codec: &MemberCodec<M>, znode: &str, member_data: &[u8],
+    payload_log_bytes: usize) -> Result<M, DiscoError> {
+  codec.decode(member_data).map_err(|reason| {
+    let excerpt_len = cmp::min(member_data.len(), payload_log_bytes);
+    // Escapes control characters, so that the excerpt cannot break up log lines.
+    let mut excerpt = String::new();
+    for c in String::from_utf8_lossy(&member_data[..excerpt_len]).chars() {
+      if c.is_control() {
+        excerpt.extend(c.escape_default());
+      } else {
+        excerpt.push(c);
+      }
+    }
+    if excerpt_len < member_data.len() {
+      excerpt.push_str(&format!("... ({} bytes)", member_data.len()));
+    }
+    DiscoError::UndecodableMember(znode.to_string(), reason, excerpt)
+  })
+}
+
+
+// Describes why a member could not be decoded, quoting its data but not its name.
+fn decode_failure(reason: &DiscoError) -> String {
+  match *reason {
+    DiscoError::UndecodableMember(_, ref reason, ref member_data) =>
+        format!("{}; data: {}", reason, member_data),
+    ref reason => reason.to_string(),
+  }
+}
+
+
+// What validate found under a Serverset ZNode.
+#[derive(Debug)]
+pub struct ValidationReport {
+  // How many member ZNodes were read, and how many of those parsed.
+  pub total: usize,
+  pub parsed: usize,
+  pub failed: usize,
+  // The ZNode names and errors of the first VALIDATION_SAMPLE_ERRORS members that failed.
+  pub errors: Vec<(String, DiscoError)>,
+}
+impl ValidationReport {
+  pub fn is_valid(&self) -> bool {
+    self.failed == 0
+  }
+}
+
+impl fmt::Display for ValidationReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(write!(f, "{} members, {} parsed, {} failed", self.total, self.parsed,
+        self.failed));
+    for &(ref member_znode, ref reason) in self.errors.iter() {
+      try!(write!(f, "\n  {}: {}", member_znode, reason));
     }
-    // Reads Serverset member's ZNode data and attempts to parse it into a String.
-    let member_json_opt = match self.zk_client.get_data(full_member_znode.as_str(),
-        false) {
+    Ok(())
+  }
+}
+
+
+// What a full reconciliation pass changed, as returned by Serverset::refresh. Members
+// are named by their ZNode.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+  pub added: Vec<String>,
+  pub changed: Vec<String>,
+  pub removed: Vec<String>,
+  // Members that could not be read or decoded, and were kept as they were.
+  pub failed: Vec<(String, DiscoError)>,
+}
+impl SyncReport {
+  // Returns true if the pass neither changed nor failed to read any member.
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty() &&
+        self.failed.is_empty()
+  }
+}
+
+
+// Checks, as a one-shot preflight, that the Serverset ZNode of `config` exists and is
+// readable and that each of its children parses as a member, without watching it. Fails
+// if the ZNode cannot be found or listed; members that cannot be read or parsed are
+// counted in the report instead.
+pub fn validate(config: &DiscoConfig) -> Result<ValidationReport, DiscoError> {
+  try!(config.validate());
+  // No watch loop reads the session's events.
+  let (events_tx, _events_rx) = channel();
+  let disconnected_since = Arc::new(Mutex::new(Some(Instant::now())));
+  let zk_client = try!(connect(config, events_tx, disconnected_since));
+  validate_with_client(&zk_client, config)
+}
+
+// Validates the Serverset ZNode of `config` as validate does, but through a client the
+// caller already has rather than a session of its own.
+pub fn validate_with_client(zk_client: &ZkClient, config: &DiscoConfig)
+    -> Result<ValidationReport, DiscoError> {
+  try!(config.validate());
+  let serverset_znode = &config.serverset_znode;
+  match znode_exists(zk_client, serverset_znode) {
+    Ok(true) => (),
+    Ok(false) => return Err(DiscoError::MissingZnode(serverset_znode.clone())),
+    Err(reason) => return Err(DiscoError::from_zk(serverset_znode, reason)),
+  }
+  let serverset_children = match zk_client.get_children(serverset_znode, false) {
+    Ok(serverset_children) => serverset_children,
+    Err(reason) => return Err(DiscoError::from_zk(serverset_znode, reason)),
+  };
+  let codec = default_codec(config);
+  let read_retries = config.member_read_retries.unwrap_or(DEFAULT_MEMBER_READ_RETRIES);
+  let payload_log_bytes =
+      config.member_payload_log_bytes.unwrap_or(DEFAULT_MEMBER_PAYLOAD_LOG_BYTES);
+  let mut report = ValidationReport{
+    total: 0,
+    parsed: 0,
+    failed: 0,
+    errors: Vec::new(),
+  };
+  for member_znode in serverset_children {
+    let full_member_znode = join_znode(serverset_znode, &member_znode);
+    let fetched = fetch_member(zk_client, &*codec, &full_member_znode, None,
+        read_retries, payload_log_bytes);
+    match fetched {
+      // The member left after the children were listed.
+      Ok(MemberFetch::Missing) | Ok(MemberFetch::Unchanged) => continue,
+      Ok(MemberFetch::Fetched(..)) => report.parsed += 1,
       Err(reason) => {
-        error!("Could not obtain node data for {} from ZooKeeper: {}", member_znode,
-            reason);
-        None
+        report.failed += 1;
+        if report.errors.len() < VALIDATION_SAMPLE_ERRORS {
+          report.errors.push((member_znode, reason));
+        }
       },
-      Ok(node_data) => match String::from_utf8(node_data.0) {
-        Err(reason) => {
-          error!("Could not parse node string: {}", reason);
-          None
-        },
-        Ok(node_string) => Some(node_string),
+    }
+    report.total += 1;
+  }
+  Ok(report)
+}
+
+
+// A ZooKeeper session and the watch notifications delivered on it, which every Serverset
+// vended by a ServersetManager shares.
+struct Session {
+  config: DiscoConfig,
+  // Replaced wholesale when an expired session is re-established.
+  zk_client: Arc<RwLock<Arc<ZkClient>>>,
+  events_tx: Mutex<Sender<ZnodeEvent>>,
+  events: Mutex<Option<Receiver<ZnodeEvent>>>,
+  // When the session was last seen to drop, or None while it is connected.
+  disconnected_since: Arc<Mutex<Option<Instant>>>,
+  needs_reconnect: AtomicBool,
+  // Starts at zookeeper_poll_ms, and is shared with every Serverset on the session so
+  // that set_poll_interval reaches the watch loop.
+  poll_interval_ms: Arc<AtomicU64>,
+  // Whether the client was supplied by the caller, through with_client. Its watch
+  // notifications go to the caller's watcher rather than ours, and its session is never
+  // replaced.
+  borrowed: bool,
+}
+impl Session {
+  fn open(config: DiscoConfig) -> Result<Session, DiscoError> {
+    let (events_tx, events_rx) = channel();
+    let disconnected_since = Arc::new(Mutex::new(Some(Instant::now())));
+    let client = try!(connect(&config, events_tx.clone(), disconnected_since.clone()));
+    let client: Arc<ZkClient> = Arc::new(client);
+    let poll_interval_ms = config.zookeeper_poll_ms as u64;
+    Ok(Session{
+      config: config,
+      zk_client: Arc::new(RwLock::new(client)),
+      events_tx: Mutex::new(events_tx),
+      events: Mutex::new(Some(events_rx)),
+      disconnected_since: disconnected_since,
+      needs_reconnect: AtomicBool::new(false),
+      poll_interval_ms: Arc::new(AtomicU64::new(poll_interval_ms)),
+      borrowed: false,
+    })
+  }
+
+  // Wraps a client connected by the caller. Nothing tells us when it disconnects, so it
+  // is taken to be connected until a read finds otherwise; see observe.
+  fn borrow(config: DiscoConfig, client: Arc<ZkClient>) -> Session {
+    let (events_tx, events_rx) = channel();
+    let poll_interval_ms = config.zookeeper_poll_ms as u64;
+    Session{
+      config: config,
+      zk_client: Arc::new(RwLock::new(client)),
+      events_tx: Mutex::new(events_tx),
+      events: Mutex::new(Some(events_rx)),
+      disconnected_since: Arc::new(Mutex::new(None)),
+      needs_reconnect: AtomicBool::new(false),
+      poll_interval_ms: Arc::new(AtomicU64::new(poll_interval_ms)),
+      borrowed: true,
+    }
+  }
+
+  // Returns how often the watch loop polls while watches are being delivered, when
+  // polling is only a safety net and may run less often. A borrowed client delivers
+  // them to the caller instead, so its Serversets keep to `poll_interval`.
+  fn fallback_poll_interval(&self, poll_interval: Duration) -> Duration {
+    match self.config.zookeeper_fallback_poll_ms {
+      Some(fallback_poll_ms) if !self.borrowed =>
+          Duration::from_millis(fallback_poll_ms as u64),
+      _ => poll_interval,
+    }
+  }
+
+  // Tracks whether a borrowed client is connected from the outcome of each pass, since
+  // its watcher is not ours to observe.
+  fn observe<T>(&self, reconciled: &Result<T, DiscoError>) {
+    if !self.borrowed {
+      return
+    }
+    let mut disconnected_since = self.disconnected_since.lock().unwrap();
+    match *reconciled {
+      Err(DiscoError::Zk(ZkError::ConnectionLoss)) |
+      Err(DiscoError::Zk(ZkError::SessionExpired)) => if disconnected_since.is_none() {
+        *disconnected_since = Some(Instant::now());
       },
-    };
+      _ => *disconnected_since = None,
+    }
+  }
 
-    // Attempts to parse Serverset member's ZNode into a ServersetMember struct.
-    let member_opt: Option<ServersetMember> = match member_json_opt {
-      None => None,
-      Some(member_json) => match json::decode(&member_json) {
+  fn client(&self) -> Arc<ZkClient> {
+    self.zk_client.read().unwrap().clone()
+  }
+
+  fn is_connected(&self) -> bool {
+    self.disconnected_since.lock().unwrap().is_none()
+  }
+
+  // Returns true if the failure means the session is gone for good: either ZooKeeper
+  // said so, or the connection has been down for longer than the session could outlive.
+  fn is_lost(&self, reason: &DiscoError) -> bool {
+    // A borrowed session is the caller's to re-establish.
+    if self.borrowed {
+      return false
+    }
+    match *reason {
+      DiscoError::Zk(ZkError::SessionExpired) => true,
+      DiscoError::Zk(ZkError::ConnectionLoss) => {
+        let session_timeout = Duration::from_secs(self.config.zookeeper_timeout_secs);
+        match *self.disconnected_since.lock().unwrap() {
+          Some(disconnected_since) => disconnected_since.elapsed() > session_timeout,
+          None => false,
+        }
+      },
+      _ => false,
+    }
+  }
+
+  // Replaces an expired session with a new one, retrying with the configured backoff
+  // until it succeeds or the watch loop is stopped. Returns whether it succeeded.
+  fn reconnect(&self, stop_signal: &StopSignal) -> bool {
+    let mut backoff = Backoff::new(self.config.backoff_policy());
+    while !stop_signal.is_stopped() {
+      match self.reconnect_once() {
+        Ok(()) => return true,
         Err(reason) => {
-          error!("Could not parse node JSON: {}", reason);
+          let delay = backoff.fail();
+          warn!("Could not reconnect to ZooKeeper, retrying in {:?}: {}", delay, reason);
+          stop_signal.sleep(delay);
+        },
+      }
+    }
+    false
+  }
+
+  // Makes a single attempt at replacing an expired session.
+  fn reconnect_once(&self) -> Result<(), DiscoError> {
+    let events_tx = self.events_tx.lock().unwrap().clone();
+    // The client connects lazily, so a round trip is needed to tell that it worked.
+    let client = try!(connect(&self.config, events_tx, self.disconnected_since.clone()));
+    match client.exists("/", false) {
+      // The chroot need not exist for the session to be usable.
+      Ok(_) | Err(ZkError::NoNode) => (),
+      Err(reason) => return Err(DiscoError::Zk(reason)),
+    }
+    info!("Re-established ZooKeeper session");
+    *self.zk_client.write().unwrap() = Arc::new(client);
+    self.needs_reconnect.store(false, Ordering::SeqCst);
+    Ok(())
+  }
+}
+
+
+// Reconciles the Serversets returned by `serversets` whenever a watch fires on the
+// session, and polls as a fallback in case watches are lost (e.g. on session expiry),
+// until it is stopped.
+fn watch_loop<M: HasStatus, F>(session: &Session, stop_signal: &StopSignal, serversets: F)
+    where F: Fn() -> Vec<Arc<Serverset<M>>> {
+  // Only one watch loop at a time is driven by ZooKeeper watches; any other falls back
+  // to plain polling.
+  let events = session.events.lock().unwrap().take();
+  for serverset in serversets().iter() {
+    serverset.sync();
+    serverset.run_change_callbacks();
+  }
+  while !stop_signal.is_stopped() {
+    // Read on every pass, so that set_poll_interval applies from the next one.
+    let poll_interval = poll_interval(&session.poll_interval_ms);
+    let fallback_poll_interval = session.fallback_poll_interval(poll_interval);
+    let poll_jitter = session.config.poll_jitter.unwrap_or(0.0);
+    let poll_interval = backoff::jittered(poll_interval, poll_jitter);
+    let fallback_poll_interval = backoff::jittered(fallback_poll_interval, poll_jitter);
+    if session.needs_reconnect.load(Ordering::SeqCst) && session.reconnect(stop_signal) {
+      for serverset in serversets().iter() {
+        serverset.resume();
+      }
+    }
+    // None means a poll is due.
+    let event = match events {
+      Some(ref events) => match events.recv_timeout(fallback_poll_interval) {
+        Ok(event) => Some(event),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => {
+          stop_signal.sleep(poll_interval);
           None
         },
-        Ok(member) => Some(member),
+      },
+      None => {
+        stop_signal.sleep(poll_interval);
+        None
       },
     };
-
-    // If all has gone well, grabs a write lock on the members HashMap and updates it with
-    // the newly-unwrapped ServersetMember.
-    match member_opt {
-      None => None,
-      Some(member) => match member.status.as_ref() {
-        "ALIVE" => {
-          self.members.write().unwrap().insert(member_znode.clone(), member)
+    if stop_signal.is_stopped() {
+      break
+    }
+    for serverset in serversets().iter() {
+      match event {
+        Some(ref event) => serverset.handle_event(event),
+        // A Serverset whose passes keep failing is polled less and less often.
+        None => if serverset.poll_backoff.lock().unwrap().is_due() {
+          serverset.sync()
         },
-        _ => None,
+      }
+      serverset.run_change_callbacks();
+    }
+  }
+  *session.events.lock().unwrap() = events;
+}
+
+
+// Returns the current poll interval.
+fn poll_interval(poll_interval_ms: &AtomicU64) -> Duration {
+  Duration::from_millis(poll_interval_ms.load(Ordering::SeqCst))
+}
+
+
+// Keeps a Serverset fed from a DiscoveryBackend until it is stopped, listing its members
+// again whenever `wakeups` delivers a change and otherwise every poll interval.
+fn backend_loop<M: HasStatus>(serverset: &Serverset<M>, wakeups: Receiver<ZnodeEvent>,
+    stop_signal: &StopSignal) {
+  serverset.sync();
+  serverset.run_change_callbacks();
+  while !stop_signal.is_stopped() {
+    let poll_interval = backoff::jittered(poll_interval(&serverset.poll_interval_ms),
+        serverset.config.poll_jitter.unwrap_or(0.0));
+    let woken = match wakeups.recv_timeout(poll_interval) {
+      Ok(_) => true,
+      Err(RecvTimeoutError::Timeout) => false,
+      Err(RecvTimeoutError::Disconnected) => {
+        stop_signal.sleep(poll_interval);
+        false
       },
     };
+    if stop_signal.is_stopped() {
+      break
+    }
+    if !woken && !serverset.poll_backoff.lock().unwrap().is_due() {
+      continue
+    }
+    serverset.sync();
+    serverset.run_change_callbacks();
   }
+}
 
-  fn update_members(&self, zk_client: ZooKeeper) {
-    // Reconciles our local representation of the Serverset with that which has been
-    // stored in ZooKeeper.
-    debug!("Updating Serverset members...");
 
-    if !self.znode_exists(zk_client, &self.config.serverset_znode) {
-      error!("Could not find Serverset ZNode: {}", self.config.serverset_znode);
-      return
+// Where a Serverset's members come from.
+enum MemberSource {
+  ZooKeeper(Arc<Session>),
+  Backend(Arc<DiscoveryBackend>),
+}
+
+
+// Returns the codec a configuration calls for when none is given explicitly.
+fn default_codec(config: &DiscoConfig) -> Arc<MemberCodec> {
+  let default_status = match config.default_status {
+    Some(ref default_status) => MemberStatus::from(default_status.as_str()),
+    None => MemberStatus::Alive,
+  };
+  Arc::new(JsonCodec::new(default_status))
+}
+
+
+// The members of a ZNode, decoded as ServersetMembers unless a codec for another type is
+// given to with_codec, in which case the methods reading a ServersetMember's fields are
+// not available.
+pub struct Serverset<M = ServersetMember> {
+  config: DiscoConfig,
+  source: MemberSource,
+  listeners: RwLock<Vec<Box<ServersetListener<M>>>>,
+  change_callbacks: Mutex<Vec<Box<Fn(&HashMap<String, M>) + Send>>>,
+  error_callbacks: Mutex<Vec<Box<Fn(&DiscoError) + Send>>>,
+  membership_changed: AtomicBool,
+  status_filter: RwLock<StatusFilter>,
+  codec: Arc<MemberCodec<M>>,
+  metrics: Arc<Metrics>,
+  registration_acl: Vec<Acl>,
+  registrations: Mutex<Vec<Weak<Mutex<Registration<M>>>>>,
+  session_expired: AtomicBool,
+  event_senders: Mutex<Vec<Sender<ServersetEvent<M>>>>,
+  // Signaled on every membership change and reconciliation, for wait_until_ready and
+  // wait_for_members.
+  members_updated: (Mutex<()>, Condvar),
+  // Serializes the passes that replace members, so that none is lost to another.
+  updating: Mutex<()>,
+  // The version each member's data was last read at, so unchanged members are not re-read
+  // and writes can be made conditional. Versions are only recorded along with the
+  // members, inside update_map and so under the updating lock. They are forgotten outside
+  // it, as members are deleted or drop out of a listing, when a read is retried or times
+  // out, and after a session expiry. That can briefly leave a member without a version,
+  // which at worst costs a redundant read.
+  member_versions: Mutex<HashMap<String, MemberVersion>>,
+  // Members that were read but whose status the filter does not admit, for
+  // members_by_status. Only changed along with the members, inside update_map and so
+  // under the updating lock.
+  unadmitted: Mutex<HashMap<String, M>>,
+  // When each member, admitted or not, was last read successfully, for member_age.
+  last_seen: Mutex<HashMap<String, Instant>>,
+  // Why each member that could not be decoded failed, for decode_failures. Replaced by
+  // every full pass that lists the members, and updated as single members are re-read.
+  decode_failures: Mutex<BTreeMap<String, String>>,
+  // How many member fetch threads are running, including those a pass gave up waiting
+  // on, so that fetches stuck on the session do not pile up from pass to pass.
+  fetch_threads_running: Arc<AtomicUsize>,
+  // When a full reconciliation pass last succeeded, both as an Instant for is_stale and
+  // as a wall-clock time for last_sync_time. Kept apart from the members so that health
+  // checks never wait on an update.
+  last_reconciled: Mutex<Option<(Instant, SystemTime)>>,
+  // Whether the latest reconciliation pass failed, or none has succeeded yet, for
+  // using_fallback.
+  reconcile_failing: AtomicBool,
+  // Delays polls after consecutive failed reconciliation passes.
+  poll_backoff: Mutex<Backoff>,
+  // How often the members are polled, in milliseconds. The session's own, when the
+  // members come from ZooKeeper.
+  poll_interval_ms: Arc<AtomicU64>,
+  // The current membership, replaced in a single swap by each update so that readers
+  // never see one half applied. Prefer members() or snapshot(); reading through the
+  // lock keeps working for now, but this is expected to become private in a future
+  // breaking release.
+  pub members: RwLock<Arc<HashMap<String, M>>>,
+}
+impl Serverset {
+  pub fn new(discoConfig: DiscoConfig) -> Result<Serverset, DiscoError> {
+    let codec = default_codec(&discoConfig);
+    Serverset::with_codec(discoConfig, codec)
+  }
+
+  // Creates a Serverset and reconciles it once before returning, so that callers
+  // discovering backends at startup do not see an empty Serverset just because no poll
+  // has run yet. If the reconciliation fails, it is returned as an error when
+  // `sync_required` is set, and otherwise reported like any later failure.
+  pub fn new_synced(discoConfig: DiscoConfig, sync_required: bool)
+      -> Result<Serverset, DiscoError> {
+    try!(Serverset::new(discoConfig)).synced(sync_required)
+  }
+
+  // Creates a Serverset like new_synced with sync required, retrying with the configured
+  // backoff while the failure is retryable, e.g. while ZooKeeper is briefly unavailable.
+  // Gives up with the latest failure once `max_wait` would be exceeded.
+  pub fn new_with_retry(discoConfig: DiscoConfig, max_wait: Duration)
+      -> Result<Serverset, DiscoError> {
+    Serverset::retry_synced(&discoConfig, max_wait,
+        || Serverset::new_synced(discoConfig.clone(), true))
+  }
+
+  // Creates a Serverset that borrows `client`, as with_client does, retrying its first
+  // reconciliation as new_with_retry does.
+  pub fn with_client_retry(client: Arc<ZkClient>, discoConfig: DiscoConfig,
+      max_wait: Duration) -> Result<Serverset, DiscoError> {
+    Serverset::retry_synced(&discoConfig, max_wait, || {
+      try!(Serverset::with_client(client.clone(), discoConfig.clone())).synced(true)
+    })
+  }
+
+  // Creates a Serverset that shares a ZooKeeper client the caller already has, e.g. one
+  // also used for locks, rather than opening a session of its own. The client is only
+  // ever borrowed: dropping the Serverset releases its reference without closing the
+  // session, and an expired session is left for the caller to replace, along with the
+  // Serverset. The client's chroot and credentials are its own, so zookeeper_chroot
+  // should match the chroot it was connected with, and zk_auth_* are ignored. Watch
+  // notifications go to the client's watcher, so the members are kept up to date by
+  // polling every zookeeper_poll_ms. Any ZkClient will do, e.g. a testing::MockZk.
+  pub fn with_client(client: Arc<ZkClient>, discoConfig: DiscoConfig)
+      -> Result<Serverset, DiscoError> {
+    let codec = default_codec(&discoConfig);
+    Serverset::with_client_codec(client, discoConfig, codec)
+  }
+
+  // Preserves the old behavior of aborting when ZooKeeper cannot be reached.
+  pub fn new_or_panic(discoConfig: DiscoConfig) -> Serverset {
+    match Serverset::new(discoConfig) {
+      Err(reason) => panic!("Unable to connect to ZooKeeper: {}", reason),
+      Ok(serverset) => serverset,
     }
-    match zk_client.get_children(self.config.serverset_znode.as_str(), false) {
-      Err(reason) => error!("Unable to get children for {}: {}",
-          self.config.serverset_znode, reason),
-      Ok(serverset_children) => {
-        debug!("Children: {:?}", serverset_children);
-        // Updates all serverset members in parallel, tracking those which we've seen.
-        let mut current_member_znodes = HashSet::new();
-        for current_member_znode in serverset_children.iter() {
-          current_member_znodes.insert(current_member_znode);
-          self.update_member(zk_client, current_member_znode);
-        }
-        // Removes all members that have dropped out of the serverset.
-        for old_member_znode in self.members.read().unwrap().keys() {
-          if !current_member_znodes.contains(old_member_znode) {
-            self.remove_member(old_member_znode);
-          }
-        }
+  }
+}
+
+impl<M: HasStatus> Serverset<M> {
+  // Reconciles a Serverset once and returns it, failing as new_synced does, e.g. for a
+  // Serverset created by with_client or with_codec.
+  pub fn synced(self, sync_required: bool) -> Result<Serverset<M>, DiscoError> {
+    match self.reconcile() {
+      Ok(_) => (),
+      Err(reason) => if sync_required {
+        return Err(reason)
+      } else {
+        let serverset_znode = self.config.serverset_znode.clone();
+        self.report_error(&serverset_znode, &reason);
       },
     }
+    Ok(self)
+  }
+
+  fn retry_synced<F>(discoConfig: &DiscoConfig, max_wait: Duration, create: F)
+      -> Result<Serverset<M>, DiscoError>
+      where F: Fn() -> Result<Serverset<M>, DiscoError> {
+    let started = Instant::now();
+    let mut backoff = Backoff::new(discoConfig.backoff_policy());
+    loop {
+      match create() {
+        Err(reason) => {
+          let delay = backoff.fail();
+          if !reason.is_retryable() || started.elapsed() + delay > max_wait {
+            return Err(reason)
+          }
+          warn!("Could not create Serverset {}, retrying in {:?}: {}",
+              discoConfig.serverset_znode, delay, reason);
+          thread::sleep(delay);
+        },
+        created => return created,
+      }
+    }
+  }
+
+  // Creates a Serverset whose member ZNodes are read and written with the given codec
+  // rather than as Finagle/Aurora JSON, e.g. a TypedJsonCodec decoding them into a type
+  // of the caller's.
+  pub fn with_codec(discoConfig: DiscoConfig, codec: Arc<MemberCodec<M>>)
+      -> Result<Serverset<M>, DiscoError> {
+    try!(discoConfig.validate());
+    let session = try!(Session::open(discoConfig.clone()));
+    Serverset::attach(discoConfig, codec, MemberSource::ZooKeeper(Arc::new(session)))
+  }
+
+  // Creates a Serverset that borrows `client`, as with_client does, and reads its
+  // members with the given codec, as with_codec does.
+  pub fn with_client_codec(client: Arc<ZkClient>, discoConfig: DiscoConfig,
+      codec: Arc<MemberCodec<M>>) -> Result<Serverset<M>, DiscoError> {
+    try!(discoConfig.validate());
+    let session = Session::borrow(discoConfig.clone(), client);
+    Serverset::attach(discoConfig, codec, MemberSource::ZooKeeper(Arc::new(session)))
+  }
+
+  // Creates a Serverset that lists its members from `backend` rather than ZooKeeper,
+  // decoding them with `codec`. Of the ZooKeeper settings, only zookeeper_poll_ms is
+  // used, to poll backends that cannot report changes; serverset_znode just names the
+  // Serverset in logs. Registering members needs ZooKeeper, and fails with
+  // DiscoError::Unsupported.
+  pub fn with_backend(discoConfig: DiscoConfig, backend: Arc<DiscoveryBackend>,
+      codec: Arc<MemberCodec<M>>) -> Result<Serverset<M>, DiscoError> {
+    try!(discoConfig.validate());
+    Serverset::attach(discoConfig, codec, MemberSource::Backend(backend))
+  }
+
+  // Creates a Serverset reading its members from the given source.
+  fn attach(discoConfig: DiscoConfig, codec: Arc<MemberCodec<M>>, source: MemberSource)
+      -> Result<Serverset<M>, DiscoError> {
+    let registration_acl = match try!(discoConfig.registration_acl()) {
+      RegistrationAcl::Open => open_acl(),
+      RegistrationAcl::CreatorAll => creator_all_acl(),
+    };
+    let status_filter = match discoConfig.status_filter {
+      Some(ref statuses) => StatusFilter::new(statuses),
+      None => StatusFilter::default(),
+    };
+    let metrics = match discoConfig.metrics {
+      Some(ref metrics) => metrics.clone(),
+      None => Arc::new(NoopMetrics),
+    };
+    let poll_backoff = Backoff::new(discoConfig.backoff_policy());
+    let poll_interval_ms = match source {
+      MemberSource::ZooKeeper(ref session) => session.poll_interval_ms.clone(),
+      MemberSource::Backend(_) =>
+          Arc::new(AtomicU64::new(discoConfig.zookeeper_poll_ms as u64)),
+    };
+    Ok(Serverset{
+      config: discoConfig,
+      source: source,
+      listeners: RwLock::new(Vec::new()),
+      change_callbacks: Mutex::new(Vec::new()),
+      error_callbacks: Mutex::new(Vec::new()),
+      membership_changed: AtomicBool::new(false),
+      status_filter: RwLock::new(status_filter),
+      codec: codec,
+      metrics: metrics,
+      registration_acl: registration_acl,
+      registrations: Mutex::new(Vec::new()),
+      session_expired: AtomicBool::new(false),
+      event_senders: Mutex::new(Vec::new()),
+      members_updated: (Mutex::new(()), Condvar::new()),
+      updating: Mutex::new(()),
+      member_versions: Mutex::new(HashMap::new()),
+      unadmitted: Mutex::new(HashMap::new()),
+      last_seen: Mutex::new(HashMap::new()),
+      decode_failures: Mutex::new(BTreeMap::new()),
+      fetch_threads_running: Arc::new(AtomicUsize::new(0)),
+      last_reconciled: Mutex::new(None),
+      reconcile_failing: AtomicBool::new(true),
+      poll_backoff: Mutex::new(poll_backoff),
+      poll_interval_ms: poll_interval_ms,
+      members: RwLock::new(Arc::new(HashMap::new())),
+    })
+  }
+
+  pub fn watch(self: &Arc<Self>) -> WatchHandle {
+    // The spawned thread holds its own reference to the Serverset, so members may be
+    // read from the caller's thread while the watcher writes them.
+    let serverset = self.clone();
+    let stop_signal = Arc::new(StopSignal::new());
+    let thread_stop_signal = stop_signal.clone();
+    let (thread, events_tx) = match self.source {
+      MemberSource::ZooKeeper(ref session) => {
+        let events_tx = session.events_tx.lock().unwrap().clone();
+        let session = session.clone();
+        let thread = thread::spawn(move || {
+          watch_loop(&session, &thread_stop_signal, || vec![serverset.clone()]);
+        });
+        (thread, events_tx)
+      },
+      MemberSource::Backend(ref backend) => {
+        let (wakeups_tx, wakeups) = channel();
+        // Forwards the backend's changes until either end goes away.
+        if let Some(changes) = backend.changes() {
+          let changes_tx = wakeups_tx.clone();
+          let serverset_znode = self.config.serverset_znode.clone();
+          thread::spawn(move || for () in changes.iter() {
+            let changed = ZnodeEvent::ChildrenChanged(serverset_znode.clone());
+            if changes_tx.send(changed).is_err() {
+              return
+            }
+          });
+        }
+        let thread = thread::spawn(move || {
+          backend_loop(&serverset, wakeups, &thread_stop_signal);
+        });
+        (thread, wakeups_tx)
+      },
+    };
+    WatchHandle{
+      stop_signal: stop_signal,
+      events_tx: events_tx,
+      thread: Some(thread),
+    }
+  }
+
+  // Returns true while the ZooKeeper session is connected, e.g. for health checks. A
+  // Serverset reading from a DiscoveryBackend has no session to lose, and is always
+  // connected.
+  pub fn is_connected(&self) -> bool {
+    match self.source {
+      MemberSource::ZooKeeper(ref session) => session.is_connected(),
+      MemberSource::Backend(_) => true,
+    }
+  }
+
+  // Returns when a full reconciliation pass last succeeded, or None if none has yet.
+  pub fn last_successful_reconcile(&self) -> Option<Instant> {
+    self.last_reconciled.lock().unwrap().map(|(reconciled_at, _)| reconciled_at)
+  }
+
+  // Returns the wall-clock time a full reconciliation pass last succeeded, e.g. to show
+  // on a dashboard, or None if none has yet.
+  pub fn last_sync_time(&self) -> Option<SystemTime> {
+    self.last_reconciled.lock().unwrap().map(|(_, synced_at)| synced_at)
+  }
+
+  // Returns true if the latest reconciliation pass failed, or none has run yet.
+  pub fn last_sync_failed(&self) -> bool {
+    self.reconcile_failing.load(Ordering::SeqCst)
+  }
+
+  #[cfg(feature = "debug-server")]
+  pub(crate) fn config(&self) -> &DiscoConfig {
+    &self.config
+  }
+
+  // Returns how long a watched Serverset may go between full passes while nothing
+  // changes: the fallback poll interval of a session whose watch loop polls only as a
+  // safety net, and otherwise the poll interval.
+  #[cfg(feature = "debug-server")]
+  pub(crate) fn full_pass_interval(&self) -> Duration {
+    match self.source {
+      MemberSource::ZooKeeper(ref session) =>
+          session.fallback_poll_interval(self.poll_interval()),
+      MemberSource::Backend(_) => self.poll_interval(),
+    }
+  }
+
+  // Returns how far polling has backed off after consecutive failed reconciliation
+  // passes, e.g. to confirm that backoff engages while ZooKeeper is down.
+  pub fn poll_backoff(&self) -> BackoffState {
+    self.poll_backoff.lock().unwrap().state()
+  }
+
+  // Changes how often the members are polled, from the watch loop's next pass, e.g. to
+  // ease the load on ZooKeeper during an incident. Serversets vended by the same
+  // ServersetManager share their session's watch loop, and so its poll interval.
+  pub fn set_poll_interval(&self, ms: u64) {
+    self.poll_interval_ms.store(cmp::max(ms, 1), Ordering::SeqCst);
+  }
+
+  // Returns the poll interval currently in effect.
+  pub fn poll_interval(&self) -> Duration {
+    poll_interval(&self.poll_interval_ms)
+  }
+
+  // Returns true if a full reconciliation pass has succeeded within `max_staleness`, so
+  // that readiness probes can tell when discovery has silently stalled.
+  pub fn is_healthy(&self, max_staleness: Duration) -> bool {
+    !self.is_stale(max_staleness)
+  }
+
+  // Returns true unless a full reconciliation pass has succeeded within `max_age`, i.e.
+  // the members may no longer reflect ZooKeeper, e.g. because the session was lost.
+  pub fn is_stale(&self, max_age: Duration) -> bool {
+    match self.last_successful_reconcile() {
+      Some(last_reconciled) => last_reconciled.elapsed() > max_age,
+      None => true,
+    }
+  }
+
+  // Returns true while pick_random and alive_members are serving the configured
+  // static_fallback: the Serverset has no members and its latest reconciliation failed.
+  pub fn using_fallback(&self) -> bool {
+    self.fallback().is_some()
+  }
+
+  // Returns the static_fallback endpoints if they should be served in place of members.
+  fn fallback(&self) -> Option<&[ServiceEndpoint]> {
+    match self.config.static_fallback {
+      Some(ref fallback) if !fallback.is_empty() && self.members().is_empty() &&
+          self.reconcile_failing.load(Ordering::SeqCst) => Some(fallback),
+      _ => None,
+    }
+  }
+
+  // Re-creates the members registered through this Serverset and rebuilds the members
+  // map, once an expired session has been replaced.
+  fn resume(&self) {
+    self.session_expired.store(false, Ordering::SeqCst);
+    // The watches left by earlier reads went with the old session.
+    self.member_versions.lock().unwrap().clear();
+    if self.config.reregister_on_expiry.unwrap_or(true) {
+      self.reregister();
+    }
+    self.sync();
+  }
+
+  // Returns the ZooKeeper session, or DiscoError::Unsupported naming `operation` if the
+  // members come from a DiscoveryBackend.
+  fn session(&self, operation: &'static str) -> Result<&Arc<Session>, DiscoError> {
+    match self.source {
+      MemberSource::ZooKeeper(ref session) => Ok(session),
+      MemberSource::Backend(_) => Err(DiscoError::Unsupported(operation)),
+    }
+  }
+
+  fn client(&self, operation: &'static str) -> Result<Arc<ZkClient>, DiscoError> {
+    self.session(operation).map(|session| session.client())
+  }
+
+  // Replaces the filter deciding which members are admitted. Members are re-evaluated
+  // against it on the next reconciliation pass.
+  pub fn set_status_filter(&self, status_filter: StatusFilter) {
+    // Waits out a pass applying members under the old filter, which would otherwise
+    // record versions that let the next pass skip them as unchanged.
+    let _updating = self.updating.lock().unwrap();
+    *self.status_filter.write().unwrap() = status_filter;
+    // Members dropped by the old filter must be re-read to be admitted by the new one.
+    self.member_versions.lock().unwrap().clear();
+  }
+
+  // Registers a listener to be notified of members joining, leaving, or changing.
+  pub fn subscribe(&self, listener: Box<ServersetListener<M>>) {
+    self.listeners.write().unwrap().push(listener);
+  }
+
+  // Announces the given member by writing it to an ephemeral sequential ZNode under the
+  // Serverset ZNode, which is created if missing. The membership lasts as long as this
+  // ZooKeeper session, and is re-created after the session expires unless
+  // reregister_on_expiry is disabled. The ZNodes get the configured registration_acl.
+  pub fn register(&self, member: M) -> Result<MemberHandle<M>, DiscoError> {
+    self.register_with_acl(member, copy_acl(&self.registration_acl))
+  }
+
+  // Registers the given member with its ZNode, and any Serverset ZNode ancestors that
+  // have to be created, protected by `acl` rather than open to everyone.
+  pub fn register_with_acl(&self, member: M, acl: Vec<Acl>)
+      -> Result<MemberHandle<M>, DiscoError> {
+    let (created_znode, czxid) = try!(self.create_member_znode(&member, &acl));
+    debug!("Registered Serverset member: {}", created_znode);
+    let registration = Arc::new(Mutex::new(Registration{
+      znode: created_znode,
+      czxid: czxid,
+      version: 0,
+      member: member,
+      acl: acl,
+      registered: true,
+    }));
+    self.registrations.lock().unwrap().push(Arc::downgrade(&registration));
+    let zk_client = try!(self.session("register")).zk_client.clone();
+    Ok(MemberHandle::new(zk_client, self.codec.clone(), registration))
+  }
+
+  // Creates an ephemeral sequential ZNode holding the given member, returning its path
+  // and creation zxid.
+  fn create_member_znode(&self, member: &M, acl: &[Acl])
+      -> Result<(String, i64), DiscoError> {
+    let client = try!(self.client("register"));
+    let member_data = try!(self.codec.encode(member));
+    try!(self.create_znode_path(&*client, &self.config.serverset_znode, acl));
+    let member_znode = join_znode(&self.config.serverset_znode, "member_");
+    let created_znode = match client.create(member_znode.as_str(), member_data,
+        copy_acl(acl), CreateMode::EphemeralSequential) {
+      Ok(created_znode) => self.strip_chroot(&created_znode),
+      Err(reason) => return Err(DiscoError::from_zk(&member_znode, reason)),
+    };
+    let created_stat = try!(client.exists(created_znode.as_str(), false));
+    Ok((created_znode, created_stat.czxid))
+  }
+
+  // Re-creates the ZNodes of members registered through this Serverset, which vanished
+  // along with the expired session.
+  fn reregister(&self) {
+    let mut created_znodes = Vec::new();
+    {
+      let mut registrations = self.registrations.lock().unwrap();
+      // Forgets the registrations whose handles have been dropped.
+      registrations.retain(|registration| registration.upgrade().is_some());
+      for registration in registrations.iter().filter_map(|weak| weak.upgrade()) {
+        let mut registration = registration.lock().unwrap();
+        if !registration.registered {
+          continue
+        }
+        match self.create_member_znode(&registration.member, &registration.acl) {
+          Err(reason) => error!("Could not re-register Serverset member {}: {}",
+              registration.znode, reason),
+          Ok((created_znode, czxid)) => {
+            info!("Re-registered Serverset member {} as {} after session expiry",
+                registration.znode, created_znode);
+            registration.znode = created_znode.clone();
+            registration.czxid = czxid;
+            registration.version = 0;
+            created_znodes.push(created_znode);
+          },
+        }
+      }
+    }
+    for created_znode in created_znodes.into_iter() {
+      self.notify(ServersetEvent::Reregistered(created_znode));
+    }
+  }
+
+  // Withdraws a membership previously returned by announce.
+  pub fn unannounce(&self, znode: &str) -> Result<(), DiscoError> {
+    match try!(self.client("unannounce")).delete(znode, -1) {
+      Ok(()) | Err(ZkError::NoNode) => Ok(()),
+      Err(reason) => Err(DiscoError::from_zk(znode, reason)),
+    }
+  }
+
+  // Returns the version the named member's data was last read at, e.g. for making a
+  // write to it conditional.
+  pub fn member_version(&self, member_znode: &str) -> Option<MemberVersion> {
+    self.member_versions.lock().unwrap().get(member_znode).cloned()
+  }
+
+  // Returns how long ago the named member was last read successfully, admitted or not,
+  // or None if it is not known, e.g. to spot members that have stopped updating before
+  // they are pruned. Members found unchanged since their last read count as read.
+  pub fn member_age(&self, member_znode: &str) -> Option<Duration> {
+    self.last_seen.lock().unwrap().get(member_znode).map(|last_seen| last_seen.elapsed())
+  }
+
+  // Returns the name and error of each member whose data could not be decoded when last
+  // read, e.g. "Could not parse node JSON: ...; data: ...", telling data that is not
+  // UTF-8 from data that is not valid JSON. Members are dropped from the list once they
+  // decode, or are gone from the Serverset.
+  pub fn decode_failures(&self) -> Vec<(String, String)> {
+    self.decode_failures.lock().unwrap().iter()
+      .map(|(member_znode, reason)| (member_znode.clone(), reason.clone()))
+      .collect()
+  }
+
+  // Reads the data of the named member, e.g. member_0000000001, as a string, for seeing
+  // what a member that could not be decoded announced. Reads the ZNode afresh, or lists a
+  // backend's members, whether or not the member it holds was admitted.
+  pub fn raw_member_data(&self, member_znode: &str) -> Result<String, DiscoError> {
+    let full_member_znode = join_znode(&self.config.serverset_znode, member_znode);
+    let member_data = match self.source {
+      MemberSource::ZooKeeper(ref session) => {
+        match session.client().get_data(&full_member_znode, false) {
+          Ok((member_data, _)) => member_data,
+          Err(ZkError::NoNode) => return Err(DiscoError::MissingZnode(full_member_znode)),
+          Err(reason) => return Err(DiscoError::from_zk(&full_member_znode, reason)),
+        }
+      },
+      MemberSource::Backend(ref backend) => {
+        let listed = try!(backend.list_members()).into_iter()
+          .find(|&(ref listed_znode, _)| listed_znode == member_znode);
+        match listed {
+          Some((_, member_data)) => member_data,
+          None => return Err(DiscoError::MissingZnode(full_member_znode)),
+        }
+      },
+    };
+    String::from_utf8(member_data).map_err(|reason| CodecError::Utf8(reason).into())
+  }
+
+  fn payload_log_bytes(&self) -> usize {
+    self.config.member_payload_log_bytes.unwrap_or(DEFAULT_MEMBER_PAYLOAD_LOG_BYTES)
+  }
+
+  // Returns the member at the full path `znode` as last read, along with the version it
+  // was read at.
+  fn cached_member(&self, znode: &str) -> Option<(M, i32)> {
+    let member_znode = match self.member_znode_name(znode) {
+      Some(member_znode) => member_znode,
+      None => return None,
+    };
+    // Keeps a pass from replacing the member between the two reads.
+    let _updating = self.updating.lock().unwrap();
+    let version = match self.member_version(&member_znode) {
+      Some(version) => version.version,
+      None => return None,
+    };
+    self.members().get(&member_znode).map(|member| (member.clone(), version))
+  }
+
+  // Creates the given ZNode and any missing ancestors as persistent ZNodes, as ZooKeeper
+  // has no recursive create.
+  fn create_znode_path(&self, client: &ZkClient, znode: &str, acl: &[Acl])
+      -> Result<(), DiscoError> {
+    let mut path = "/".to_string();
+    for segment in znode.split('/').filter(|segment| !segment.is_empty()) {
+      path = join_znode(&path, segment);
+      match client.create(path.as_str(), Vec::new(), copy_acl(acl),
+          CreateMode::Persistent) {
+        Ok(_) | Err(ZkError::NodeExists) => (),
+        Err(reason) => return Err(DiscoError::from_zk(&path, reason)),
+      }
+    }
+    Ok(())
+  }
+
+  // Registers a callback invoked with the current members after each reconciliation in
+  // which at least one member was added or removed.
+  pub fn on_change<F>(&self, cb: F)
+      where F: Fn(&HashMap<String, M>) + Send + 'static {
+    self.change_callbacks.lock().unwrap().push(Box::new(cb));
+  }
+
+  // Registers a callback invoked with each failure encountered while reconciling, e.g.
+  // to tell connectivity problems apart from corrupt member data.
+  pub fn on_error<F>(&self, cb: F) where F: Fn(&DiscoError) + Send + 'static {
+    self.error_callbacks.lock().unwrap().push(Box::new(cb));
+  }
+
+  // Runs the on_change callbacks if membership changed since they last ran.
+  pub(crate) fn run_change_callbacks(&self) {
+    if !self.membership_changed.swap(false, Ordering::SeqCst) {
+      return
+    }
+    let members = self.members();
+    for cb in self.change_callbacks.lock().unwrap().iter() {
+      cb(&members);
+    }
+  }
+
+  // Returns a new channel on which every subsequent membership change is delivered, as
+  // the added, changed and removed members found by each pass rather than whole
+  // snapshots. Each call is an independent subscription, e.g. one for a connection pool
+  // and one for a metrics exporter, and dropping the receiver unsubscribes it.
+  pub fn events(&self) -> Receiver<ServersetEvent<M>> {
+    let (events_tx, events_rx) = channel();
+    self.event_senders.lock().unwrap().push(events_tx);
+    events_rx
+  }
+
+  // Returns the current membership, keyed by ZNode name. This is a consistent view that
+  // later updates leave untouched, and holding it does not block them.
+  pub fn members(&self) -> Arc<HashMap<String, M>> {
+    self.members.read().unwrap().clone()
+  }
+
+  // Returns a copy of every member keyed by its ZNode name, which callers may iterate,
+  // sort and filter freely.
+  pub fn snapshot(&self) -> Vec<(String, M)> {
+    self.members().iter()
+      .map(|(member_znode, member)| (member_znode.clone(), member.clone()))
+      .collect()
+  }
+
+  // Returns the number of members, of any admitted status.
+  pub fn len(&self) -> usize {
+    self.members().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.members().is_empty()
+  }
+
+  // Returns every member with the given status, e.g. "STOPPING", ignoring case. Unlike
+  // members, includes those the status filter keeps out, so that dashboards can show
+  // members that are draining or dead.
+  pub fn members_by_status(&self, status: &str) -> Vec<M> {
+    let _updating = self.updating.lock().unwrap();
+    let has_status = |member: &&M| {
+      member.status().as_str().eq_ignore_ascii_case(status)
+    };
+    let members = self.members();
+    let unadmitted = self.unadmitted.lock().unwrap();
+    members.values().filter(&has_status)
+      .chain(unadmitted.values().filter(&has_status))
+      .cloned()
+      .collect()
+  }
+
+  // Blocks until at least `min_members` members are ALIVE, so that callers starting up
+  // can wait for a backend before serving traffic. Fails with DiscoError::NotReady if the
+  // timeout passes first.
+  pub fn wait_until_ready(&self, min_members: usize, timeout: Duration)
+      -> Result<(), DiscoError> {
+    let alive_count = |members: &HashMap<String, M>| members.values()
+      .filter(|member| *member.status() == MemberStatus::Alive)
+      .count();
+    match self.wait_for_count(min_members, timeout, alive_count) {
+      Ok(_) => Ok(()),
+      Err(alive) => Err(DiscoError::NotReady(alive, min_members)),
+    }
+  }
+
+  // Blocks until at least `min_members` members of any status admitted by the status
+  // filter are known, returning how many there are. Wakes after every reconciliation
+  // pass, whether it was triggered by a watch or by polling.
+  pub fn wait_for_members(&self, min_members: usize, timeout: Duration)
+      -> Result<usize, WaitTimeout> {
+    match self.wait_for_count(min_members, timeout, |members| members.len()) {
+      Ok(count) => Ok(count),
+      Err(count) => Err(WaitTimeout{
+        members: count,
+        required: min_members,
+      }),
+    }
+  }
+
+  // Waits until `count` of the members reaches `min_members`, returning the last count
+  // as an error if the timeout passes first.
+  fn wait_for_count<F>(&self, min_members: usize, timeout: Duration, count: F)
+      -> Result<usize, usize>
+      where F: Fn(&HashMap<String, M>) -> usize {
+    let deadline = Instant::now() + timeout;
+    let (ref lock, ref members_updated) = self.members_updated;
+    let mut guard = lock.lock().unwrap();
+    loop {
+      let counted = count(&self.members());
+      if counted >= min_members {
+        return Ok(counted)
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        return Err(counted)
+      }
+      guard = members_updated.wait_timeout(guard, deadline - now).unwrap().0;
+    }
+  }
+
+  // Returns the member whose ZNode name carries the given shard id.
+  pub fn member_by_shard(&self, shard: u64) -> Option<M> {
+    self.members().iter()
+      .find(|&(member_znode, _)| shard_id(member_znode) == Some(shard))
+      .map(|(_, member)| member.clone())
+  }
+
+  // Returns the member with the given ZNode name, if it is known.
+  pub fn get_member(&self, member_znode: &str) -> Option<M> {
+    self.members().get(member_znode).cloned()
+  }
+
+  // Returns the members of any status matching `predicate`, e.g. those in one data
+  // center, without the caller having to go through the members lock.
+  pub fn filter<F>(&self, predicate: F) -> Vec<M>
+      where F: Fn(&M) -> bool {
+    self.members().values()
+      .filter(|member| predicate(member))
+      .cloned()
+      .collect()
+  }
+
+  // Returns each serviceEndpoint announced by more than one member, as happens when a
+  // botched deploy registers an instance twice. pick_random would favor such endpoints.
+  pub fn duplicate_endpoints(&self) -> Vec<ServiceEndpoint> {
+    find_duplicate_endpoints(&self.members())
+  }
+
+  fn handle_event(&self, event: &ZnodeEvent) {
+    match *event {
+      // Other Serversets sharing the session see their own child watches fire too.
+      ZnodeEvent::ChildrenChanged(ref path) =>
+          if self.strip_chroot(path) == self.config.serverset_znode {
+        self.sync()
+      },
+      ZnodeEvent::DataChanged(ref path) => match self.member_znode_name(path) {
+        Some(member_znode) => match self.client("watch")
+            .and_then(|client| self.update_member(&*client, &member_znode)) {
+          Err(reason) => self.report_error(&member_znode, &reason),
+          Ok(()) => (),
+        },
+        None => (),
+      },
+      ZnodeEvent::Deleted(ref path) => match self.member_znode_name(path) {
+        Some(member_znode) => {
+          self.member_versions.lock().unwrap().remove(&member_znode);
+          self.decode_failures.lock().unwrap().remove(&member_znode);
+          self.update_map(|members| {
+            members.remove(&member_znode);
+            self.unadmitted.lock().unwrap().remove(&member_znode);
+            self.last_seen.lock().unwrap().remove(&member_znode);
+          });
+        },
+        None => (),
+      },
+      ZnodeEvent::SessionExpired => {
+        warn!("ZooKeeper session expired");
+        self.session_expired.store(true, Ordering::SeqCst);
+        if let MemberSource::ZooKeeper(ref session) = self.source {
+          session.needs_reconnect.store(true, Ordering::SeqCst);
+        }
+      },
+      ZnodeEvent::Stopping => (),
+      ZnodeEvent::SessionConnected => {
+        if self.session_expired.swap(false, Ordering::SeqCst) {
+          if self.config.reregister_on_expiry.unwrap_or(true) {
+            self.reregister();
+          }
+          self.sync();
+        }
+      },
+    }
+  }
+
+  // ZooKeeper reports created paths including the chroot, which the client would apply a
+  // second time if they were passed back to it.
+  fn strip_chroot(&self, path: &str) -> String {
+    match self.config.chroot() {
+      Ok(chroot) if !chroot.is_empty() && path.starts_with(chroot) &&
+          path[chroot.len()..].starts_with('/') => path[chroot.len()..].to_string(),
+      _ => path.to_string(),
+    }
+  }
+
+  // Maps the full path of a watched ZNode to the name of the Serverset member it
+  // belongs to, if it is a direct child of the Serverset ZNode. Serversets sharing a
+  // session see each other's notifications, including those of Serversets nested under
+  // a path ending like this one, e.g. /x/svc/a under /svc/a.
+  fn member_znode_name(&self, path: &str) -> Option<String> {
+    let path = self.strip_chroot(path);
+    match path.rfind('/') {
+      Some(idx) if path[..idx] == self.config.serverset_znode =>
+          Some(path[idx + 1..].to_string()),
+      _ => None,
+    }
+  }
+
+  // Applies `update` to a copy of the members and publishes the result in a single swap,
+  // then notifies listeners of each member added, changed or removed, returning those
+  // changes. Members re-read with identical data are left alone and do not notify
+  // listeners.
+  fn update_map<F>(&self, update: F) -> SyncReport
+      where F: FnOnce(&mut HashMap<String, M>) {
+    let mut report = SyncReport::default();
+    let (previous, members) = {
+      let _updating = self.updating.lock().unwrap();
+      let previous = self.members();
+      let mut members = (*previous).clone();
+      update(&mut members);
+      if members == *previous {
+        return report
+      }
+      let members = Arc::new(members);
+      *self.members.write().unwrap() = members.clone();
+      (previous, members)
+    };
+    for (member_znode, member) in members.iter() {
+      match previous.get(member_znode) {
+        None => {
+          self.metrics.on_member_added(member_znode);
+          report.added.push(member_znode.clone());
+          self.notify(ServersetEvent::MemberAdded(member_znode.clone(), member.clone()))
+        },
+        Some(previous_member) if previous_member != member => {
+          report.changed.push(member_znode.clone());
+          self.notify(ServersetEvent::MemberChanged(member_znode.clone(), member.clone()))
+        },
+        Some(_) => (),
+      }
+    }
+    for member_znode in previous.keys() {
+      if !members.contains_key(member_znode) {
+        self.metrics.on_member_removed(member_znode);
+        report.removed.push(member_znode.clone());
+        self.notify(ServersetEvent::MemberRemoved(member_znode.clone()));
+      }
+    }
+    report
+  }
+
+  // Delivers a membership change to listeners and event channels. Must not be called
+  // with the members lock held.
+  fn notify(&self, event: ServersetEvent<M>) {
+    match event {
+      ServersetEvent::MemberAdded(..) | ServersetEvent::MemberRemoved(_) =>
+          self.membership_changed.store(true, Ordering::SeqCst),
+      _ => (),
+    }
+    for listener in self.listeners.read().unwrap().iter() {
+      match event {
+        ServersetEvent::MemberAdded(ref znode, ref member) =>
+            listener.on_join(znode, member),
+        ServersetEvent::MemberRemoved(ref znode) => listener.on_leave(znode),
+        ServersetEvent::MemberChanged(ref znode, ref member) =>
+            listener.on_update(znode, member),
+        ServersetEvent::SyncCompleted | ServersetEvent::Reregistered(_) => (),
+      }
+    }
+    // Drops the channels whose receivers have gone away.
+    self.event_senders.lock().unwrap()
+      .retain(|events_tx| events_tx.send(event.clone()).is_ok());
+    // Taking the lock ensures a waiter that has counted members but not yet started
+    // waiting still sees this change.
+    let (ref lock, ref members_updated) = self.members_updated;
+    let _guard = lock.lock().unwrap();
+    members_updated.notify_all();
+  }
+
+  fn update_member(&self, zk_client: &ZkClient, member_znode: &str)
+      -> Result<(), DiscoError> {
+    debug!("Adding Serverset member: {}", member_znode);
+    let full_member_znode = join_znode(&self.config.serverset_znode, member_znode);
+    // Forgets the member's version until the read succeeds, so that a failed read is
+    // retried on the next pass.
+    self.member_versions.lock().unwrap().remove(member_znode);
+    let read_retries =
+        self.config.member_read_retries.unwrap_or(DEFAULT_MEMBER_READ_RETRIES);
+    let fetched = fetch_member(zk_client, &*self.codec, &full_member_znode, None,
+        read_retries, self.payload_log_bytes());
+    match fetched {
+      Err(ref reason @ DiscoError::UndecodableMember(..)) => {
+        self.decode_failures.lock().unwrap()
+          .insert(member_znode.to_string(), decode_failure(reason));
+      },
+      Err(_) => (),
+      Ok(_) => {
+        self.decode_failures.lock().unwrap().remove(member_znode);
+      },
+    }
+    match try!(fetched) {
+      MemberFetch::Fetched(member, version) => {
+        self.update_map(|members| {
+          self.member_versions.lock().unwrap().insert(member_znode.to_string(), version);
+          self.apply_member(members, member_znode, member)
+        });
+      },
+      // If the Serverset member's ZNode does not exist, does not update the member.
+      MemberFetch::Missing | MemberFetch::Unchanged => (),
+    }
+    Ok(())
+  }
+
+  // Updates `members` with a newly-fetched M.
+  fn apply_member(&self, members: &mut HashMap<String, M>,
+      member_znode: &str, member: M) {
+    let admitted = self.status_filter.read().unwrap().admits(member.status());
+    self.last_seen.lock().unwrap().insert(member_znode.to_string(), Instant::now());
+    let mut unadmitted = self.unadmitted.lock().unwrap();
+    if admitted {
+      unadmitted.remove(member_znode);
+      members.insert(member_znode.to_string(), member);
+    } else {
+      // Members whose status is no longer admitted are dropped rather than left stale.
+      members.remove(member_znode);
+      unadmitted.insert(member_znode.to_string(), member);
+    }
+  }
+
+  fn update_members(&self, zk_client: &Arc<ZkClient>) -> Result<SyncReport, DiscoError> {
+    // Reconciles our local representation of the Serverset with that which has been
+    // stored in ZooKeeper. Members are only removed once the children have been listed
+    // and no longer include them; a pass that fails before then leaves every member as
+    // it was, so that clients keep stale but usable endpoints through an outage.
+    debug!("Updating Serverset members...");
+
+    // A missing Serverset ZNode is a configuration problem, whereas failing to check for
+    // it is usually a transient loss of the session, so the two are reported apart.
+    match znode_exists(&**zk_client, &self.config.serverset_znode) {
+      Ok(true) => (),
+      Ok(false) =>
+          return Err(DiscoError::MissingZnode(self.config.serverset_znode.clone())),
+      Err(reason) =>
+          return Err(DiscoError::from_zk(&self.config.serverset_znode, reason)),
+    }
+    // Leaves a child watch on the Serverset ZNode so joins and leaves are picked up
+    // promptly.
+    let serverset_children = match zk_client.get_children(
+        self.config.serverset_znode.as_str(), true) {
+      Ok(serverset_children) => serverset_children,
+      Err(reason) =>
+          return Err(DiscoError::from_zk(&self.config.serverset_znode, reason)),
+    };
+    debug!("Children: {:?}", serverset_children);
+    // Fetches serverset members in parallel on a bounded set of threads, collecting them
+    // to be applied together. A member that fails to update is reported without holding
+    // up the others.
+    let queue = Arc::new(Mutex::new(serverset_children.clone().into_iter()));
+    let known_versions = Arc::new(self.member_versions.lock().unwrap().clone());
+    let (results_tx, results_rx) = channel();
+    let fetch_threads =
+        self.config.zookeeper_fetch_concurrency.unwrap_or(DEFAULT_MEMBER_FETCH_THREADS);
+    let read_retries =
+        self.config.member_read_retries.unwrap_or(DEFAULT_MEMBER_READ_RETRIES);
+    let payload_log_bytes = self.payload_log_bytes();
+    // Threads still stuck on fetches an earlier pass gave up on count against the limit,
+    // so that a stalled session is not given more and more of them.
+    let running = self.fetch_threads_running.load(Ordering::SeqCst);
+    let wanted = cmp::min(fetch_threads, serverset_children.len());
+    let spawned = cmp::min(fetch_threads.saturating_sub(running), wanted);
+    if spawned < wanted {
+      warn!("{} member fetches of {} from earlier passes are still outstanding", running,
+          self.config.serverset_znode);
+    }
+    for _ in 0..spawned {
+      let queue = queue.clone();
+      let known_versions = known_versions.clone();
+      let results_tx = results_tx.clone();
+      let zk_client = zk_client.clone();
+      let codec = self.codec.clone();
+      let serverset_znode = self.config.serverset_znode.clone();
+      self.fetch_threads_running.fetch_add(1, Ordering::SeqCst);
+      let running = FetchThreadGuard(self.fetch_threads_running.clone());
+      thread::spawn(move || {
+        let _running = running;
+        loop {
+          let member_znode = match queue.lock().unwrap().next() {
+            Some(member_znode) => member_znode,
+            None => return,
+          };
+          // The reconciliation pass has given up on us if the receiver is gone.
+          let started = FetchProgress::Started(member_znode.clone(), Instant::now());
+          if results_tx.send(started).is_err() {
+            return
+          }
+          let full_member_znode = join_znode(&serverset_znode, &member_znode);
+          let known_mzxid =
+              known_versions.get(&member_znode).map(|version| version.mzxid);
+          let fetched = fetch_member(&*zk_client, &*codec, &full_member_znode,
+              known_mzxid, read_retries, payload_log_bytes);
+          if results_tx.send(FetchProgress::Finished(member_znode, fetched)).is_err() {
+            return
+          }
+        }
+      });
+    }
+    drop(results_tx);
+    // Gives up on a member whose fetch has not finished within a session timeout of it
+    // starting, as a fetch stalled that long will not complete on this session, and
+    // takes the thread running it to be stuck. Members still queued are given up on
+    // once every fetch thread is stuck, which with none to spare is straight away.
+    let fetch_timeout = Duration::from_secs(self.config.zookeeper_timeout_secs);
+    let mut pending_member_znodes: HashSet<&String> = serverset_children.iter().collect();
+    let mut fetches_started: HashMap<String, Instant> = HashMap::new();
+    let mut stuck_threads = 0;
+    let mut fetched_members = Vec::new();
+    let mut failed = Vec::new();
+    let mut skipped_count = 0;
+    while !pending_member_znodes.is_empty() {
+      if stuck_threads >= spawned {
+        // Leaves nothing for a thread that comes unstuck to fetch for this pass.
+        let _ = queue.lock().unwrap().by_ref().count();
+        for member_znode in pending_member_znodes.drain() {
+          failed.push((member_znode.clone(), self.fetch_timed_out(member_znode)));
+        }
+        break
+      }
+      let now = Instant::now();
+      let wait = match fetches_started.values().min() {
+        Some(&started) if started + fetch_timeout > now => started + fetch_timeout - now,
+        Some(_) => Duration::from_secs(0),
+        None => fetch_timeout,
+      };
+      match results_rx.recv_timeout(wait) {
+        Ok(FetchProgress::Started(member_znode, started)) => {
+          fetches_started.insert(member_znode, started);
+        },
+        Ok(FetchProgress::Finished(member_znode, fetched)) => {
+          fetches_started.remove(&member_znode);
+          // A fetch finishing after it was given up on frees its thread, but its
+          // member has already been reported.
+          if !pending_member_znodes.remove(&member_znode) {
+            stuck_threads -= 1;
+            continue
+          }
+          match fetched {
+            Ok(MemberFetch::Fetched(member, version)) =>
+                fetched_members.push((member_znode, member, version)),
+            // Unchanged since it was last read, so just as current.
+            Ok(MemberFetch::Unchanged) => {
+              self.last_seen.lock().unwrap().insert(member_znode, Instant::now());
+              skipped_count += 1
+            },
+            Ok(MemberFetch::Missing) => {
+              self.member_versions.lock().unwrap().remove(&member_znode);
+            },
+            Err(reason) => {
+              self.member_versions.lock().unwrap().remove(&member_znode);
+              self.report_error(&member_znode, &reason);
+              failed.push((member_znode, reason));
+            },
+          }
+        },
+        Err(RecvTimeoutError::Timeout) => {
+          let now = Instant::now();
+          let timed_out: Vec<String> = fetches_started.iter()
+            .filter(|&(_, &started)| started + fetch_timeout <= now)
+            .map(|(member_znode, _)| member_znode.clone())
+            .collect();
+          for member_znode in timed_out {
+            fetches_started.remove(&member_znode);
+            pending_member_znodes.remove(&member_znode);
+            stuck_threads += 1;
+            let reason = self.fetch_timed_out(&member_znode);
+            failed.push((member_znode, reason));
+          }
+        },
+        // Every fetch thread has exited, so nothing will fetch the members left.
+        Err(RecvTimeoutError::Disconnected) => stuck_threads = spawned,
+      }
+    }
+    // Applies the fetched members and removes all members that have dropped out of the
+    // serverset, publishing the result at once. Members whose fetch failed or timed out
+    // are kept as they were.
+    debug!("Fetched {} members and skipped {} unchanged members", fetched_members.len(),
+        skipped_count);
+    let current_member_znodes: HashSet<&String> = serverset_children.iter().collect();
+    self.member_versions.lock().unwrap()
+      .retain(|member_znode, _| current_member_znodes.contains(member_znode));
+    let mut report = self.update_map(|members| {
+      let mut member_versions = self.member_versions.lock().unwrap();
+      for (member_znode, member, version) in fetched_members {
+        member_versions.insert(member_znode.clone(), version);
+        self.apply_member(members, &member_znode, member);
+      }
+      members.retain(|member_znode, _| current_member_znodes.contains(member_znode));
+      self.unadmitted.lock().unwrap()
+        .retain(|member_znode, _| current_member_znodes.contains(member_znode));
+      self.last_seen.lock().unwrap()
+        .retain(|member_znode, _| current_member_znodes.contains(member_znode));
+    });
+    self.record_decode_failures(&failed);
+    report.failed = failed;
+    self.finish_pass();
+    Ok(report)
+  }
+
+  // Forgets the version of a member whose fetch was given up on, and reports the
+  // timeout.
+  fn fetch_timed_out(&self, member_znode: &str) -> DiscoError {
+    self.member_versions.lock().unwrap().remove(member_znode);
+    let full_member_znode = join_znode(&self.config.serverset_znode, member_znode);
+    let reason = DiscoError::FetchTimeout(full_member_znode);
+    self.report_error(member_znode, &reason);
+    reason
+  }
+
+  // Reconciles the members with a listing from `backend`. Members that cannot be decoded
+  // are reported and kept as they were, and members no longer listed are removed.
+  fn update_from_backend(&self, backend: &DiscoveryBackend)
+      -> Result<SyncReport, DiscoError> {
+    debug!("Listing Serverset members from backend...");
+    let listed = try!(backend.list_members());
+    let listed_member_znodes: HashSet<String> = listed.iter()
+      .map(|&(ref member_znode, _)| member_znode.clone())
+      .collect();
+    let mut decoded_members = Vec::new();
+    let mut failed = Vec::new();
+    let payload_log_bytes = self.payload_log_bytes();
+    for (member_znode, member_data) in listed {
+      match decode_member(&*self.codec, &member_znode, &member_data, payload_log_bytes) {
+        Ok(member) => decoded_members.push((member_znode, member)),
+        Err(reason) => {
+          self.report_error(&member_znode, &reason);
+          failed.push((member_znode, reason));
+        },
+      }
+    }
+    let mut report = self.update_map(|members| {
+      for (member_znode, member) in decoded_members {
+        self.apply_member(members, &member_znode, member);
+      }
+      members.retain(|member_znode, _| listed_member_znodes.contains(member_znode));
+      self.unadmitted.lock().unwrap()
+        .retain(|member_znode, _| listed_member_znodes.contains(member_znode));
+      self.last_seen.lock().unwrap()
+        .retain(|member_znode, _| listed_member_znodes.contains(member_znode));
+    });
+    self.record_decode_failures(&failed);
+    report.failed = failed;
+    self.finish_pass();
+    Ok(report)
+  }
+
+  // Replaces the decode failures with those among the members a full pass failed on.
+  fn record_decode_failures(&self, failed: &[(String, DiscoError)]) {
+    *self.decode_failures.lock().unwrap() = failed.iter()
+      .filter(|&&(_, ref reason)| match *reason {
+        DiscoError::UndecodableMember(..) => true,
+        _ => false,
+      })
+      .map(|&(ref member_znode, ref reason)| {
+        (member_znode.clone(), decode_failure(reason))
+      })
+      .collect();
+  }
+
+  // Reports what a successful full pass found wrong with the members, and wakes those
+  // waiting on it.
+  fn finish_pass(&self) {
+    let duplicates = find_duplicate_endpoints(&self.members());
+    if !duplicates.is_empty() {
+      warn!("Serverset {} has duplicate endpoints: {:?}", self.config.serverset_znode,
+          duplicates);
+      self.metrics.on_duplicate_endpoints(&duplicates);
+    }
+    self.notify(ServersetEvent::SyncCompleted);
+  }
+
+  // Runs a full reconciliation pass, reporting rather than returning its failure.
+  pub(crate) fn sync(&self) {
+    match self.reconcile() {
+      Err(reason) => self.report_error(&self.config.serverset_znode, &reason),
+      Ok(_) => (),
+    }
+  }
+
+  // Takes the watch notifications delivered on the session, as a watch loop would, so
+  // that an AsyncServerset can wake as they arrive. None if a watch loop already has
+  // them, or the members come from a DiscoveryBackend.
+  #[cfg(feature = "async")]
+  pub(crate) fn take_events(&self) -> Option<Receiver<ZnodeEvent>> {
+    match self.source {
+      MemberSource::ZooKeeper(ref session) => session.events.lock().unwrap().take(),
+      MemberSource::Backend(_) => None,
+    }
+  }
+
+  // Runs one pass of a watch loop without waiting: replaces an expired session, handles
+  // `events`, the watch notifications delivered since the last call, and otherwise
+  // re-reads the members if a poll is due. Returns how long to wait before calling it
+  // again. Blocks on ZooKeeper, so AsyncServerset calls it on tokio's blocking thread
+  // pool.
+  #[cfg(feature = "async")]
+  pub(crate) fn poll_once(&self, events: Vec<ZnodeEvent>) -> Duration {
+    if let MemberSource::ZooKeeper(ref session) = self.source {
+      if session.needs_reconnect.load(Ordering::SeqCst) {
+        match session.reconnect_once() {
+          Ok(()) => self.resume(),
+          Err(reason) => warn!("Could not reconnect to ZooKeeper: {}", reason),
+        }
+      }
+    }
+    for event in events.iter() {
+      self.handle_event(event);
+    }
+    // Members of a DiscoveryBackend are only ever polled for.
+    if events.is_empty() && self.poll_backoff.lock().unwrap().is_due() {
+      self.sync();
+    }
+    self.run_change_callbacks();
+    backoff::jittered(poll_interval(&self.poll_interval_ms),
+        self.config.poll_jitter.unwrap_or(0.0))
+  }
+
+  // Runs a full reconciliation pass now and returns what it changed, or why it failed,
+  // e.g. for callers that cannot wait for the next poll. Failures are still logged and
+  // passed to the on_error callbacks, and on_change callbacks run on this thread.
+  pub fn refresh(&self) -> Result<SyncReport, DiscoError> {
+    let refreshed = self.reconcile();
+    match refreshed {
+      Err(ref reason) => self.report_error(&self.config.serverset_znode, reason),
+      Ok(_) => (),
+    }
+    self.run_change_callbacks();
+    refreshed
+  }
+
+  // Runs a full reconciliation pass and records its outcome in the metrics.
+  fn reconcile(&self) -> Result<SyncReport, DiscoError> {
+    let started = Instant::now();
+    self.metrics.on_reconcile_started();
+    let reconciled = match self.source {
+      MemberSource::ZooKeeper(ref session) => {
+        let reconciled = self.update_members(&session.client());
+        session.observe(&reconciled);
+        match reconciled {
+          Err(ref reason) if session.is_lost(reason) =>
+              session.needs_reconnect.store(true, Ordering::SeqCst),
+          _ => (),
+        }
+        reconciled
+      },
+      MemberSource::Backend(ref backend) => self.update_from_backend(&**backend),
+    };
+    self.metrics.on_reconcile_finished(started.elapsed());
+    match reconciled {
+      Err(reason) => {
+        self.reconcile_failing.store(true, Ordering::SeqCst);
+        self.poll_backoff.lock().unwrap().fail();
+        self.clear_if_stale();
+        self.metrics.on_reconcile_error(&reason);
+        Err(reason)
+      },
+      Ok(report) => {
+        self.reconcile_failing.store(false, Ordering::SeqCst);
+        self.poll_backoff.lock().unwrap().succeed();
+        *self.last_reconciled.lock().unwrap() = Some((Instant::now(), SystemTime::now()));
+        self.metrics.on_reconcile_success(self.len());
+        Ok(report)
+      },
+    }
+  }
+
+  // Clears the members once they are older than stale_after_ms, if fail_open_on_stale is
+  // false. Their versions are forgotten too, so the next successful pass reads every
+  // member afresh.
+  fn clear_if_stale(&self) {
+    let stale_after = match (self.config.fail_open_on_stale, self.config.stale_after_ms) {
+      (Some(false), Some(stale_after_ms)) => Duration::from_millis(stale_after_ms),
+      _ => return,
+    };
+    if !self.is_stale(stale_after) {
+      return
+    }
+    if !self.is_empty() {
+      warn!("Serverset {} is stale, clearing its members", self.config.serverset_znode);
+    }
+    self.update_map(|members| {
+      members.clear();
+      self.member_versions.lock().unwrap().clear();
+      self.unadmitted.lock().unwrap().clear();
+      self.last_seen.lock().unwrap().clear();
+    });
+  }
+
+  // Logs a reconciliation failure for the given ZNode and passes it on to the on_error
+  // callbacks.
+  fn report_error(&self, znode: &str, reason: &DiscoError) {
+    if reason.is_retryable() {
+      warn!("Could not update {}, will retry: {}", znode, reason);
+    } else {
+      error!("Could not update {}: {}", znode, reason);
+    }
+    match *reason {
+      DiscoError::Codec(_) | DiscoError::UndecodableMember(..) =>
+          self.metrics.on_parse_error(znode),
+      _ => (),
+    }
+    for cb in self.error_callbacks.lock().unwrap().iter() {
+      cb(reason);
+    }
+  }
+}
+
+// Reads and writes of the fields only a ServersetMember is known to have.
+impl Serverset {
+  // Announces this process as an ALIVE member and returns the created ZNode's path. The
+  // membership is not tied to a MemberHandle, and lasts until the session ends.
+  pub fn announce(&self, endpoint: ServiceEndpoint,
+      additional: HashMap<String, ServiceEndpoint>) -> Result<String, DiscoError> {
+    self.announce_with_acl(endpoint, additional, copy_acl(&self.registration_acl))
+  }
+
+  // Announces this process like announce, with its ZNode protected by `acl`.
+  pub fn announce_with_acl(&self, endpoint: ServiceEndpoint,
+      additional: HashMap<String, ServiceEndpoint>, acl: Vec<Acl>)
+      -> Result<String, DiscoError> {
+    let member = ServersetMember{
+      serviceEndpoint: endpoint,
+      additionalEndpoints: additional,
+      status: MemberStatus::Alive,
+      shard: None,
+      weight: None,
+      extra_fields: HashMap::new(),
+    };
+    self.register_with_acl(member, acl).map(|handle| handle.into_znode())
+  }
+
+  // Rewrites the status of an announced member, e.g. so clients drain it before the
+  // process exits. Each write is conditional on the version of the data it started from,
+  // beginning with the cached member if there is one, so concurrent writes are not
+  // clobbered. A write that loses a race is retried from a fresh read, and fails with
+  // DiscoError::VersionConflict once SET_STATUS_ATTEMPTS writes have lost.
+  pub fn set_status(&self, znode: &str, status: MemberStatus) -> Result<(), DiscoError> {
+    let client = try!(self.client("set_status"));
+    let mut cached = self.cached_member(znode);
+    for _ in 0..SET_STATUS_ATTEMPTS {
+      let (mut member, version) = match cached.take() {
+        Some(cached) => cached,
+        None => match client.get_data(znode, false) {
+          Ok((member_data, stat)) => (try!(decode_member(&*self.codec, znode,
+              &member_data, self.payload_log_bytes())), stat.version),
+          Err(reason) => return Err(DiscoError::from_zk(znode, reason)),
+        },
+      };
+      member.status = status.clone();
+      let member_data = try!(self.codec.encode(&member));
+      match client.set_data(znode, member_data, version) {
+        Ok(_) => return Ok(()),
+        Err(ZkError::BadVersion) =>
+            debug!("{} was written concurrently, retrying", znode),
+        Err(reason) => return Err(DiscoError::from_zk(znode, reason)),
+      }
+    }
+    Err(DiscoError::VersionConflict(znode.to_string()))
+  }
+
+  // Returns a snapshot of every ALIVE member, taken without holding on to the lock, or
+  // the static_fallback endpoints as ALIVE members while using_fallback.
+  pub fn alive_members(&self) -> Vec<ServersetMember> {
+    if let Some(fallback) = self.fallback() {
+      return fallback.iter()
+        .map(|endpoint| ServersetMember{
+          serviceEndpoint: endpoint.clone(),
+          additionalEndpoints: HashMap::new(),
+          status: MemberStatus::Alive,
+          shard: None,
+          weight: None,
+          extra_fields: HashMap::new(),
+        })
+        .collect()
+    }
+    self.members().values()
+      .filter(|member| member.status == MemberStatus::Alive)
+      .cloned()
+      .collect()
+  }
+
+  // Resolves the serviceEndpoint of every ALIVE member. Members whose host cannot be
+  // resolved are logged and left out rather than failing the whole call.
+  pub fn resolved_endpoints(&self) -> Vec<SocketAddr> {
+    let mut addrs = Vec::new();
+    for member in self.alive_members() {
+      match member.serviceEndpoint.to_socket_addrs() {
+        Ok(member_addrs) => addrs.extend(member_addrs),
+        Err(reason) =>
+            warn!("Could not resolve {}: {}", member.serviceEndpoint.addr(), reason),
+      }
+    }
+    addrs
+  }
+
+  // Returns the member announcing the given shard, for routing to a specific instance of
+  // a sharded service.
+  pub fn member_for_shard(&self, shard: u32) -> Option<ServersetMember> {
+    self.members().values()
+      .find(|member| member.shard == Some(shard))
+      .cloned()
+  }
+
+  // Returns a uniformly random endpoint among the ALIVE members, if there are any, or
+  // among the static_fallback endpoints while using_fallback.
+  pub fn pick_random(&self) -> Option<ServiceEndpoint> {
+    if let Some(fallback) = self.fallback() {
+      let idx = rand::thread_rng().gen_range(0, fallback.len());
+      return Some(fallback[idx].clone())
+    }
+    let members = self.members();
+    let alive_endpoints: Vec<&ServiceEndpoint> = members.values()
+      .filter(|member| member.status == MemberStatus::Alive)
+      .map(|member| &member.serviceEndpoint)
+      .collect();
+    if alive_endpoints.is_empty() {
+      return None
+    }
+    let idx = rand::thread_rng().gen_range(0, alive_endpoints.len());
+    Some(alive_endpoints[idx].clone())
+  }
+
+  // Returns the endpoint named `name` from the additionalEndpoints of a random ALIVE
+  // member, skipping members that do not advertise it.
+  pub fn pick_additional(&self, name: &str) -> Option<ServiceEndpoint> {
+    let members = self.members();
+    let named_endpoints: Vec<&ServiceEndpoint> = members.values()
+      .filter(|member| member.status == MemberStatus::Alive)
+      .filter_map(|member| member.additionalEndpoints.get(name))
+      .collect();
+    if named_endpoints.is_empty() {
+      return None
+    }
+    let idx = rand::thread_rng().gen_range(0, named_endpoints.len());
+    Some(named_endpoints[idx].clone())
+  }
+
+  // Returns the names of every additional endpoint advertised by an ALIVE member, such as
+  // "http" or "thrift".
+  pub fn additional_endpoint_names(&self) -> HashSet<String> {
+    self.members().values()
+      .filter(|member| member.status == MemberStatus::Alive)
+      .flat_map(|member| member.additionalEndpoints.keys().cloned())
+      .collect()
+  }
+
+  // Returns the ALIVE members advertising an additional endpoint named `name`, for
+  // building a pool of the members offering one capability.
+  pub fn members_with(&self, name: &str) -> Vec<ServersetMember> {
+    self.members().values()
+      .filter(|member| member.status == MemberStatus::Alive)
+      .filter(|member| member.additionalEndpoints.contains_key(name))
+      .cloned()
+      .collect()
+  }
+
+  // Returns true if any member announces host:port, as its serviceEndpoint or as one of
+  // its additionalEndpoints.
+  pub fn contains_endpoint(&self, host: &str, port: u16) -> bool {
+    let matches =
+        |endpoint: &ServiceEndpoint| endpoint.host == host && endpoint.port == port;
+    self.members().values().any(|member| matches(&member.serviceEndpoint) ||
+        member.additionalEndpoints.values().any(|endpoint| matches(endpoint)))
+  }
+
+  // Returns the additional endpoint named `name` of every member of any status that
+  // advertises one, e.g. all the "http-admin" ports.
+  pub fn endpoints_named(&self, name: &str) -> Vec<ServiceEndpoint> {
+    self.members().values()
+      .filter_map(|member| member.additionalEndpoints.get(name))
+      .cloned()
+      .collect()
+  }
+
+  // Returns the endpoint of a random ALIVE member, chosen in proportion to the members'
+  // weights.
+  pub fn pick_weighted(&self) -> Option<ServiceEndpoint> {
+    let members = self.members();
+    let alive_members: Vec<&ServersetMember> = members.values()
+      .filter(|member| member.status == MemberStatus::Alive && member.weight() > 0)
+      .collect();
+    let total_weight: u64 = alive_members.iter()
+      .map(|member| member.weight() as u64)
+      .sum();
+    if total_weight == 0 {
+      return None
+    }
+    let mut remaining = rand::thread_rng().gen_range(0, total_weight);
+    for member in alive_members.iter() {
+      if remaining < member.weight() as u64 {
+        return Some(member.serviceEndpoint.clone())
+      }
+      remaining -= member.weight() as u64;
+    }
+    None
+  }
+}
+
+
+// Watches any number of Serversets over a single ZooKeeper session and watch thread,
+// e.g. for a gateway fronting many services.
+pub struct ServersetManager {
+  config: DiscoConfig,
+  session: Arc<Session>,
+  serversets: RwLock<HashMap<String, Arc<Serverset>>>,
+}
+impl ServersetManager {
+  // Connects with every setting of `config` but serverset_znode, which each Serverset
+  // supplies instead.
+  pub fn new(discoConfig: DiscoConfig) -> Result<ServersetManager, DiscoError> {
+    try!(discoConfig.validate());
+    let session = try!(Session::open(discoConfig.clone()));
+    Ok(ServersetManager{
+      config: discoConfig,
+      session: Arc::new(session),
+      serversets: RwLock::new(HashMap::new()),
+    })
+  }
+
+  // Creates a manager whose Serversets share a ZooKeeper client the caller already has,
+  // borrowing it as Serverset::with_client does.
+  pub fn with_client(client: Arc<ZkClient>, discoConfig: DiscoConfig)
+      -> Result<ServersetManager, DiscoError> {
+    try!(discoConfig.validate());
+    let session = Session::borrow(discoConfig.clone(), client);
+    Ok(ServersetManager{
+      config: discoConfig,
+      session: Arc::new(session),
+      serversets: RwLock::new(HashMap::new()),
+    })
+  }
+
+  // Returns the Serverset tracking the given ZNode, creating it on first use. A new
+  // Serverset is reconciled once before it is returned, and kept up to date from then
+  // on by the manager's watch loop.
+  pub fn serverset(&self, serverset_znode: &str) -> Result<Arc<Serverset>, DiscoError> {
+    match self.serversets.read().unwrap().get(serverset_znode) {
+      Some(serverset) => return Ok(serverset.clone()),
+      None => (),
+    }
+    let serverset = {
+      let mut serversets = self.serversets.write().unwrap();
+      // Another thread may have created it while the lock was released.
+      match serversets.get(serverset_znode) {
+        Some(serverset) => return Ok(serverset.clone()),
+        None => (),
+      }
+      let mut config = self.config.clone();
+      config.serverset_znode = serverset_znode.to_string();
+      try!(config.validate());
+      let codec = default_codec(&config);
+      let serverset = Arc::new(try!(Serverset::attach(config, codec,
+          MemberSource::ZooKeeper(self.session.clone()))));
+      serversets.insert(serverset_znode.to_string(), serverset.clone());
+      serverset
+    };
+    serverset.sync();
+    Ok(serverset)
+  }
+
+  // Stops keeping the Serverset tracking the given ZNode up to date, returning it if
+  // there was one. Safe while the watch loop is running, which picks up the change on
+  // its next pass; the Serverset keeps its last members for anyone still holding it.
+  pub fn remove(&self, serverset_znode: &str) -> Option<Arc<Serverset>> {
+    self.serversets.write().unwrap().remove(serverset_znode)
+  }
+
+  // Starts a single thread that keeps every Serverset vended by this manager up to date.
+  pub fn watch(self: &Arc<Self>) -> WatchHandle {
+    let manager = self.clone();
+    let stop_signal = Arc::new(StopSignal::new());
+    let thread_stop_signal = stop_signal.clone();
+    let thread = thread::spawn(move || {
+      watch_loop(&manager.session, &thread_stop_signal,
+          || manager.serversets.read().unwrap().values().cloned().collect());
+    });
+    WatchHandle{
+      stop_signal: stop_signal,
+      events_tx: self.session.events_tx.lock().unwrap().clone(),
+      thread: Some(thread),
+    }
+  }
+
+  // Changes how often the manager's watch loop polls, from its next pass.
+  pub fn set_poll_interval(&self, ms: u64) {
+    self.session.poll_interval_ms.store(cmp::max(ms, 1), Ordering::SeqCst);
+  }
+
+  // Returns true while the shared ZooKeeper session is connected.
+  pub fn is_connected(&self) -> bool {
+    self.session.is_connected()
   }
 }
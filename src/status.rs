@@ -0,0 +1,102 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
+use std::fmt;
+
+
+// The lifecycle state announced by a Serverset member, as used by Finagle and Aurora.
+// Statuses outside that vocabulary are kept verbatim rather than failing to parse.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MemberStatus {
+  Dead,
+  Starting,
+  Alive,
+  Stopping,
+  Stopped,
+  Warning,
+  Unknown(String),
+}
+impl MemberStatus {
+  pub fn as_str(&self) -> &str {
+    match *self {
+      MemberStatus::Dead => "DEAD",
+      MemberStatus::Starting => "STARTING",
+      MemberStatus::Alive => "ALIVE",
+      MemberStatus::Stopping => "STOPPING",
+      MemberStatus::Stopped => "STOPPED",
+      MemberStatus::Warning => "WARNING",
+      MemberStatus::Unknown(ref status) => status,
+    }
+  }
+}
+
+impl<'a> From<&'a str> for MemberStatus {
+  // Known names match ignoring case, e.g. "alive"; any other status is kept as written.
+  fn from(status: &'a str) -> MemberStatus {
+    match status.to_uppercase().as_str() {
+      "DEAD" => MemberStatus::Dead,
+      "STARTING" => MemberStatus::Starting,
+      "ALIVE" => MemberStatus::Alive,
+      "STOPPING" => MemberStatus::Stopping,
+      "STOPPED" => MemberStatus::Stopped,
+      "WARNING" => MemberStatus::Warning,
+      _ => MemberStatus::Unknown(status.to_string()),
+    }
+  }
+}
+
+impl fmt::Display for MemberStatus {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+impl Serialize for MemberStatus {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.as_str())
+  }
+}
+
+impl<'de> Deserialize<'de> for MemberStatus {
+  fn deserialize<D>(deserializer: D) -> Result<MemberStatus, D::Error>
+      where D: Deserializer<'de> {
+    let status = try!(String::deserialize(deserializer));
+    Ok(MemberStatus::from(status.as_str()))
+  }
+}
+
+
+// Decides which members are admitted into a Serverset's members map by their status.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatusFilter {
+  All,
+  Only(HashSet<MemberStatus>),
+}
+impl StatusFilter {
+  // Builds a filter from status names, where the special name "all" admits every member
+  // including those with unknown statuses. Names match ignoring case, as they do for
+  // Serverset::members_by_status.
+  pub fn new(statuses: &[String]) -> StatusFilter {
+    if statuses.iter().any(|status| status.eq_ignore_ascii_case("all")) {
+      return StatusFilter::All
+    }
+    StatusFilter::Only(statuses.iter().map(|status| MemberStatus::from(status.as_str()))
+        .collect())
+  }
+
+  pub fn admits(&self, status: &MemberStatus) -> bool {
+    match *self {
+      StatusFilter::All => true,
+      StatusFilter::Only(ref statuses) => statuses.iter()
+        .any(|admitted| admitted.as_str().eq_ignore_ascii_case(status.as_str())),
+    }
+  }
+}
+
+impl Default for StatusFilter {
+  // Admits only ALIVE members.
+  fn default() -> StatusFilter {
+    let mut statuses = HashSet::new();
+    statuses.insert(MemberStatus::Alive);
+    StatusFilter::Only(statuses)
+  }
+}
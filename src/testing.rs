@@ -0,0 +1,430 @@
+use backend::DiscoveryBackend;
+use codec::{CodecError, JsonCodec};
+use config::DiscoConfig;
+use error::DiscoError;
+use serverset::{Serverset, ServersetMember};
+use status::MemberStatus;
+use zk::ZkClient;
+
+use serde_json;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use discotech_zookeeper::{Acl, CreateMode, Stat, ZkError, ZkResult};
+
+
+// Members held in memory, for testing code that consumes a Serverset without a live
+// ZooKeeper. Every change wakes the Serversets watching the backend.
+pub struct MemoryBackend {
+  members: Mutex<BTreeMap<String, ServersetMember>>,
+  change_senders: Mutex<Vec<Sender<()>>>,
+}
+impl MemoryBackend {
+  pub fn new() -> MemoryBackend {
+    MemoryBackend{
+      members: Mutex::new(BTreeMap::new()),
+      change_senders: Mutex::new(Vec::new()),
+    }
+  }
+
+  // Adds the member under the given ZNode name, e.g. member_0000000001, replacing any
+  // member already there.
+  pub fn add_member(&self, znode: &str, member: ServersetMember) {
+    self.members.lock().unwrap().insert(znode.to_string(), member);
+    self.changed();
+  }
+
+  // Removes the named member, returning false if there was none.
+  pub fn remove_member(&self, znode: &str) -> bool {
+    let removed = self.members.lock().unwrap().remove(znode).is_some();
+    self.changed();
+    removed
+  }
+
+  // Rewrites the status of the named member, returning false if there was none.
+  pub fn set_member_status(&self, znode: &str, status: MemberStatus) -> bool {
+    let updated = match self.members.lock().unwrap().get_mut(znode) {
+      Some(member) => {
+        member.status = status;
+        true
+      },
+      None => false,
+    };
+    self.changed();
+    updated
+  }
+
+  fn changed(&self) {
+    self.change_senders.lock().unwrap().retain(|change_tx| change_tx.send(()).is_ok());
+  }
+}
+
+impl Default for MemoryBackend {
+  fn default() -> MemoryBackend {
+    MemoryBackend::new()
+  }
+}
+
+impl DiscoveryBackend for MemoryBackend {
+  fn list_members(&self) -> Result<Vec<(String, Vec<u8>)>, DiscoError> {
+    let mut listed = Vec::new();
+    for (member_znode, member) in self.members.lock().unwrap().iter() {
+      match serde_json::to_vec(member) {
+        Ok(member_data) => listed.push((member_znode.clone(), member_data)),
+        Err(reason) => return Err(DiscoError::Codec(CodecError::Json(reason))),
+      }
+    }
+    Ok(listed)
+  }
+
+  fn changes(&self) -> Option<Receiver<()>> {
+    let (change_tx, change_rx) = channel();
+    self.change_senders.lock().unwrap().push(change_tx);
+    Some(change_rx)
+  }
+}
+
+
+// A Serverset over a MemoryBackend that applies each change before returning, so tests
+// see its members, listeners, callbacks, events and pickers update deterministically,
+// without watching it. Dereferences to the Arc<Serverset>, so e.g. `mock.picker()` works.
+pub struct MockServerset {
+  backend: Arc<MemoryBackend>,
+  serverset: Arc<Serverset>,
+}
+impl MockServerset {
+  // Creates an empty MockServerset with the default configuration, which admits only
+  // ALIVE members.
+  pub fn new() -> MockServerset {
+    let config = DiscoConfig::builder()
+      .zookeeper_host("localhost")
+      .serverset_znode("/discovery/mock")
+      .build()
+      .unwrap();
+    MockServerset::with_config(config).unwrap()
+  }
+
+  // Creates an empty MockServerset, e.g. with a status filter or metrics. The ZooKeeper
+  // fields are never used but must be valid.
+  pub fn with_config(discoConfig: DiscoConfig) -> Result<MockServerset, DiscoError> {
+    let backend = Arc::new(MemoryBackend::new());
+    // MemoryBackend encodes every member with its status, so no default is needed.
+    let serverset = try!(Serverset::with_backend(discoConfig, backend.clone(),
+        Arc::new(JsonCodec::new(MemberStatus::Alive))));
+    Ok(MockServerset{
+      backend: backend,
+      serverset: Arc::new(serverset),
+    })
+  }
+
+  pub fn backend(&self) -> &Arc<MemoryBackend> {
+    &self.backend
+  }
+
+  pub fn serverset(&self) -> &Arc<Serverset> {
+    &self.serverset
+  }
+
+  pub fn add_member(&self, znode: &str, member: ServersetMember) {
+    self.backend.add_member(znode, member);
+    self.apply();
+  }
+
+  pub fn remove_member(&self, znode: &str) -> bool {
+    let removed = self.backend.remove_member(znode);
+    self.apply();
+    removed
+  }
+
+  pub fn set_member_status(&self, znode: &str, status: MemberStatus) -> bool {
+    let updated = self.backend.set_member_status(znode, status);
+    self.apply();
+    updated
+  }
+
+  // Runs the reconciliation pass and on_change callbacks a watch would have run.
+  fn apply(&self) {
+    self.serverset.sync();
+    self.serverset.run_change_callbacks();
+  }
+}
+
+impl Default for MockServerset {
+  fn default() -> MockServerset {
+    MockServerset::new()
+  }
+}
+
+impl Deref for MockServerset {
+  type Target = Arc<Serverset>;
+
+  fn deref(&self) -> &Arc<Serverset> {
+    &self.serverset
+  }
+}
+
+
+// An in-memory ZooKeeper tree, for testing how a Serverset reads and registers members
+// without a live ensemble: pass it to Serverset::with_client. Versions, zxids and
+// sequential names are kept as ZooKeeper would, but watches are never delivered, so
+// tests apply their changes with Serverset::refresh. Starts with just the root ZNode.
+pub struct MockZk {
+  // Shared with every chrooted view of the tree.
+  tree: Arc<Mutex<MockTree>>,
+  connected: Arc<AtomicBool>,
+  // Prefixed to every path given, or empty.
+  chroot: String,
+}
+impl MockZk {
+  pub fn new() -> MockZk {
+    let mut znodes = BTreeMap::new();
+    znodes.insert("/".to_string(), MockZnode::new(Vec::new(), 0, false));
+    MockZk{
+      tree: Arc::new(Mutex::new(MockTree{
+        znodes: znodes,
+        zxid: 0,
+      })),
+      connected: Arc::new(AtomicBool::new(true)),
+      chroot: String::new(),
+    }
+  }
+
+  // Returns the same tree as seen by a client connected under `chroot`, e.g.
+  // "/apps/prod", for a Serverset configured with that zookeeper_chroot. Paths given to
+  // it are relative to the chroot, which need not exist, while create returns the full
+  // path, as discotech_zookeeper does.
+  pub fn chrooted(&self, chroot: &str) -> MockZk {
+    MockZk{
+      tree: self.tree.clone(),
+      connected: self.connected.clone(),
+      chroot: format!("{}{}", self.chroot, chroot.trim_right_matches('/')),
+    }
+  }
+
+  // While disconnected, every operation fails with ZkError::ConnectionLoss, as if the
+  // ensemble could not be reached.
+  pub fn set_connected(&self, connected: bool) {
+    self.connected.store(connected, Ordering::SeqCst);
+  }
+
+  // Deletes every ephemeral ZNode, as ZooKeeper does when the session that created them
+  // expires.
+  pub fn expire_ephemerals(&self) {
+    let mut tree = self.tree.lock().unwrap();
+    let ephemerals: Vec<String> = tree.znodes.iter()
+      .filter(|&(_, znode)| znode.ephemeral)
+      .map(|(path, _)| path.clone())
+      .collect();
+    for path in ephemerals {
+      tree.remove(&path);
+    }
+  }
+
+  fn full_path(&self, path: &str) -> ZkResult<String> {
+    match (self.chroot.as_str(), path) {
+      (_, path) if !path.starts_with('/') => Err(ZkError::BadArguments),
+      (chroot, "/") if !chroot.is_empty() => Ok(chroot.to_string()),
+      (chroot, path) => Ok(format!("{}{}", chroot, path)),
+    }
+  }
+
+  fn tree(&self) -> ZkResult<MutexGuard<MockTree>> {
+    if !self.connected.load(Ordering::SeqCst) {
+      return Err(ZkError::ConnectionLoss)
+    }
+    Ok(self.tree.lock().unwrap())
+  }
+}
+
+impl Default for MockZk {
+  fn default() -> MockZk {
+    MockZk::new()
+  }
+}
+
+impl ZkClient for MockZk {
+  fn exists(&self, path: &str, _watch: bool) -> ZkResult<Stat> {
+    let path = try!(self.full_path(path));
+    let tree = try!(self.tree());
+    tree.stat(&path)
+  }
+
+  fn get_data(&self, path: &str, _watch: bool) -> ZkResult<(Vec<u8>, Stat)> {
+    let path = try!(self.full_path(path));
+    let tree = try!(self.tree());
+    let stat = try!(tree.stat(&path));
+    Ok((tree.znodes[&path].data.clone(), stat))
+  }
+
+  fn get_children(&self, path: &str, _watch: bool) -> ZkResult<Vec<String>> {
+    let path = try!(self.full_path(path));
+    let tree = try!(self.tree());
+    if !tree.znodes.contains_key(&path) {
+      return Err(ZkError::NoNode)
+    }
+    Ok(tree.children(&path))
+  }
+
+  fn create(&self, path: &str, data: Vec<u8>, _acl: Vec<Acl>, mode: CreateMode)
+      -> ZkResult<String> {
+    let path = try!(self.full_path(path));
+    let mut tree = try!(self.tree());
+    let parent = try!(parent_znode(&path)).to_string();
+    let (sequential, ephemeral) = match mode {
+      CreateMode::Persistent => (false, false),
+      CreateMode::Ephemeral => (false, true),
+      CreateMode::PersistentSequential => (true, false),
+      CreateMode::EphemeralSequential => (true, true),
+    };
+    let path = match tree.znodes.get(&parent) {
+      None => return Err(ZkError::NoNode),
+      Some(parent) if parent.ephemeral => return Err(ZkError::NoChildrenForEphemerals),
+      // Like ZooKeeper, numbers sequential children by how often the parent's children
+      // have changed.
+      Some(parent) if sequential => format!("{}{:010}", path, parent.cversion),
+      Some(_) => path,
+    };
+    if tree.znodes.contains_key(&path) {
+      return Err(ZkError::NodeExists)
+    }
+    tree.zxid += 1;
+    let znode = MockZnode::new(data, tree.zxid, ephemeral);
+    tree.znodes.insert(path.clone(), znode);
+    tree.znodes.get_mut(&parent).unwrap().cversion += 1;
+    Ok(path)
+  }
+
+  fn set_data(&self, path: &str, data: Vec<u8>, version: i32) -> ZkResult<Stat> {
+    let path = try!(self.full_path(path));
+    let mut tree = try!(self.tree());
+    try!(tree.check_version(&path, version));
+    tree.zxid += 1;
+    let zxid = tree.zxid;
+    {
+      let znode = tree.znodes.get_mut(&path).unwrap();
+      znode.data = data;
+      znode.mzxid = zxid;
+      znode.version += 1;
+    }
+    tree.stat(&path)
+  }
+
+  fn delete(&self, path: &str, version: i32) -> ZkResult<()> {
+    let path = try!(self.full_path(path));
+    let mut tree = try!(self.tree());
+    try!(parent_znode(&path));
+    try!(tree.check_version(&path, version));
+    if !tree.children(&path).is_empty() {
+      return Err(ZkError::NotEmpty)
+    }
+    tree.remove(&path);
+    Ok(())
+  }
+}
+
+
+struct MockTree {
+  // Every ZNode by its full path.
+  znodes: BTreeMap<String, MockZnode>,
+  // The zxid of the latest write.
+  zxid: i64,
+}
+impl MockTree {
+  fn stat(&self, path: &str) -> ZkResult<Stat> {
+    match self.znodes.get(path) {
+      Some(znode) => Ok(znode.stat(self.children(path).len())),
+      None => Err(ZkError::NoNode),
+    }
+  }
+
+  // Returns the names of the ZNode's children, in order.
+  fn children(&self, path: &str) -> Vec<String> {
+    let prefix = match path {
+      "/" => "/".to_string(),
+      path => format!("{}/", path),
+    };
+    self.znodes.keys()
+      .filter(|child| child.starts_with(&prefix) && child.len() > prefix.len())
+      .map(|child| child[prefix.len()..].to_string())
+      .filter(|name| !name.contains('/'))
+      .collect()
+  }
+
+  fn check_version(&self, path: &str, version: i32) -> ZkResult<()> {
+    match self.znodes.get(path) {
+      None => Err(ZkError::NoNode),
+      Some(znode) if version != -1 && version != znode.version =>
+          Err(ZkError::BadVersion),
+      Some(_) => Ok(()),
+    }
+  }
+
+  // Removes the ZNode, counting the change against its parent's children.
+  fn remove(&mut self, path: &str) {
+    if self.znodes.remove(path).is_none() {
+      return
+    }
+    self.zxid += 1;
+    if let Ok(parent) = parent_znode(path) {
+      if let Some(parent) = self.znodes.get_mut(parent) {
+        parent.cversion += 1;
+      }
+    }
+  }
+}
+
+
+struct MockZnode {
+  data: Vec<u8>,
+  czxid: i64,
+  mzxid: i64,
+  version: i32,
+  // How many times a child has been created or deleted.
+  cversion: i32,
+  ephemeral: bool,
+}
+impl MockZnode {
+  fn new(data: Vec<u8>, zxid: i64, ephemeral: bool) -> MockZnode {
+    MockZnode{
+      data: data,
+      czxid: zxid,
+      mzxid: zxid,
+      version: 0,
+      cversion: 0,
+      ephemeral: ephemeral,
+    }
+  }
+
+  fn stat(&self, num_children: usize) -> Stat {
+    Stat{
+      czxid: self.czxid,
+      mzxid: self.mzxid,
+      ctime: 0,
+      mtime: 0,
+      version: self.version,
+      cversion: self.cversion,
+      aversion: 0,
+      // Ephemeral ZNodes all belong to the one session the mock stands for.
+      ephemeral_owner: if self.ephemeral { 1 } else { 0 },
+      data_length: self.data.len() as i32,
+      num_children: num_children as i32,
+      pzxid: self.mzxid,
+    }
+  }
+}
+
+
+// Returns the path of the ZNode's parent, or ZkError::BadArguments for the root or any
+// path ZooKeeper would reject as malformed.
+fn parent_znode(path: &str) -> ZkResult<&str> {
+  if !path.starts_with('/') || path == "/" || path.ends_with('/') || path.contains("//") {
+    return Err(ZkError::BadArguments)
+  }
+  match path.rfind('/') {
+    Some(0) => Ok("/"),
+    Some(slash) => Ok(&path[..slash]),
+    None => Err(ZkError::BadArguments),
+  }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use codec::{CodecError, MemberCodec};
+use serverset::{ServersetMember, ServiceEndpoint};
+use status::MemberStatus;
+
+
+// Thrift binary protocol field types.
+const T_STOP: u8 = 0;
+const T_BOOL: u8 = 2;
+const T_BYTE: u8 = 3;
+const T_DOUBLE: u8 = 4;
+const T_I16: u8 = 6;
+const T_I32: u8 = 8;
+const T_I64: u8 = 10;
+const T_STRING: u8 = 11;
+const T_STRUCT: u8 = 12;
+const T_MAP: u8 = 13;
+const T_SET: u8 = 14;
+const T_LIST: u8 = 15;
+
+
+// The Twitter commons ServiceInstance format, written with TBinaryProtocol:
+//
+//   struct Endpoint { 1: string host, 2: i32 port }
+//   enum Status { DEAD = 0, STARTING = 1, ALIVE = 2, STOPPING = 3, STOPPED = 4,
+//     WARNING = 5 }
+//   struct ServiceInstance { 1: Endpoint serviceEndpoint,
+//     2: map<string, Endpoint> additionalEndpoints, 3: Status status,
+//     4: optional i32 shard }
+pub struct ThriftMemberCodec {
+  default_status: MemberStatus,
+}
+impl ThriftMemberCodec {
+  // Instances that leave their status unset are given `default_status`.
+  pub fn new(default_status: MemberStatus) -> ThriftMemberCodec {
+    ThriftMemberCodec{
+      default_status: default_status,
+    }
+  }
+}
+
+impl MemberCodec for ThriftMemberCodec {
+  fn decode(&self, bytes: &[u8]) -> Result<ServersetMember, CodecError> {
+    let mut reader = Reader{
+      bytes: bytes,
+      pos: 0,
+    };
+    reader.read_service_instance(&self.default_status)
+  }
+
+  fn encode(&self, member: &ServersetMember) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::new();
+    write_field_header(&mut out, T_STRUCT, 1);
+    write_endpoint(&mut out, &member.serviceEndpoint);
+    write_field_header(&mut out, T_MAP, 2);
+    out.push(T_STRING);
+    out.push(T_STRUCT);
+    write_i32(&mut out, member.additionalEndpoints.len() as i32);
+    for (name, endpoint) in member.additionalEndpoints.iter() {
+      write_string(&mut out, name);
+      write_endpoint(&mut out, endpoint);
+    }
+    write_field_header(&mut out, T_I32, 3);
+    write_i32(&mut out, try!(status_value(&member.status)));
+    if let Some(shard) = member.shard {
+      write_field_header(&mut out, T_I32, 4);
+      write_i32(&mut out, shard as i32);
+    }
+    out.push(T_STOP);
+    Ok(out)
+  }
+}
+
+
+fn status_from_value(value: i32) -> MemberStatus {
+  match value {
+    0 => MemberStatus::Dead,
+    1 => MemberStatus::Starting,
+    2 => MemberStatus::Alive,
+    3 => MemberStatus::Stopping,
+    4 => MemberStatus::Stopped,
+    5 => MemberStatus::Warning,
+    other => MemberStatus::Unknown(other.to_string()),
+  }
+}
+
+fn status_value(status: &MemberStatus) -> Result<i32, CodecError> {
+  match *status {
+    MemberStatus::Dead => Ok(0),
+    MemberStatus::Starting => Ok(1),
+    MemberStatus::Alive => Ok(2),
+    MemberStatus::Stopping => Ok(3),
+    MemberStatus::Stopped => Ok(4),
+    MemberStatus::Warning => Ok(5),
+    // Unknown values read from the wire round-trip as the number they arrived as.
+    MemberStatus::Unknown(ref status) => match status.parse() {
+      Ok(value) => Ok(value),
+      Err(_) => Err(CodecError::Malformed(format!("no Thrift value for {}", status))),
+    },
+  }
+}
+
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+    if self.bytes.len() - self.pos < len {
+      return Err(CodecError::Malformed("truncated Thrift payload".to_string()));
+    }
+    let taken = &self.bytes[self.pos..self.pos + len];
+    self.pos += len;
+    Ok(taken)
+  }
+
+  fn read_byte(&mut self) -> Result<u8, CodecError> {
+    Ok(try!(self.take(1))[0])
+  }
+
+  fn read_i16(&mut self) -> Result<i16, CodecError> {
+    let bytes = try!(self.take(2));
+    Ok(((bytes[0] as i16) << 8) | bytes[1] as i16)
+  }
+
+  fn read_i32(&mut self) -> Result<i32, CodecError> {
+    let bytes = try!(self.take(4));
+    Ok(bytes.iter().fold(0, |acc, &byte| (acc << 8) | byte as i32))
+  }
+
+  fn read_size(&mut self) -> Result<usize, CodecError> {
+    let size = try!(self.read_i32());
+    if size < 0 {
+      return Err(CodecError::Malformed(format!("negative Thrift size {}", size)));
+    }
+    Ok(size as usize)
+  }
+
+  fn read_string(&mut self) -> Result<String, CodecError> {
+    let len = try!(self.read_size());
+    let bytes = try!(self.take(len));
+    Ok(try!(String::from_utf8(bytes.to_vec())))
+  }
+
+  // Reads a field header, returning None at the end of a struct.
+  fn read_field_header(&mut self) -> Result<Option<(u8, i16)>, CodecError> {
+    match try!(self.read_byte()) {
+      T_STOP => Ok(None),
+      field_type => Ok(Some((field_type, try!(self.read_i16())))),
+    }
+  }
+
+  fn expect(&self, field_type: u8, expected: u8, field: &str) -> Result<(), CodecError> {
+    if field_type == expected {
+      Ok(())
+    } else {
+      Err(CodecError::Malformed(format!("unexpected Thrift type {} for {}", field_type,
+          field)))
+    }
+  }
+
+  // Skips a value of any type, so fields added to the IDL later are tolerated.
+  fn skip(&mut self, field_type: u8) -> Result<(), CodecError> {
+    match field_type {
+      T_BOOL | T_BYTE => try!(self.take(1)),
+      T_I16 => try!(self.take(2)),
+      T_I32 => try!(self.take(4)),
+      T_DOUBLE | T_I64 => try!(self.take(8)),
+      T_STRING => {
+        let len = try!(self.read_size());
+        try!(self.take(len))
+      },
+      T_STRUCT => {
+        while let Some((field_type, _)) = try!(self.read_field_header()) {
+          try!(self.skip(field_type));
+        }
+        return Ok(());
+      },
+      T_MAP => {
+        let key_type = try!(self.read_byte());
+        let value_type = try!(self.read_byte());
+        for _ in 0..try!(self.read_size()) {
+          try!(self.skip(key_type));
+          try!(self.skip(value_type));
+        }
+        return Ok(());
+      },
+      T_SET | T_LIST => {
+        let element_type = try!(self.read_byte());
+        for _ in 0..try!(self.read_size()) {
+          try!(self.skip(element_type));
+        }
+        return Ok(());
+      },
+      other =>
+          return Err(CodecError::Malformed(format!("unknown Thrift type {}", other))),
+    };
+    Ok(())
+  }
+
+  fn read_endpoint(&mut self) -> Result<ServiceEndpoint, CodecError> {
+    let mut host = None;
+    let mut port = None;
+    while let Some((field_type, field_id)) = try!(self.read_field_header()) {
+      match field_id {
+        1 => {
+          try!(self.expect(field_type, T_STRING, "host"));
+          host = Some(try!(self.read_string()));
+        },
+        2 => {
+          try!(self.expect(field_type, T_I32, "port"));
+          let value = try!(self.read_i32());
+          if value < 0 || value > 65535 {
+            return Err(CodecError::Malformed(format!("bad port {}", value)));
+          }
+          port = Some(value as u16);
+        },
+        _ => try!(self.skip(field_type)),
+      }
+    }
+    match (host, port) {
+      (Some(host), Some(port)) => Ok(ServiceEndpoint{host: host, port: port}),
+      _ => Err(CodecError::Malformed("endpoint without host and port".to_string())),
+    }
+  }
+
+  fn read_service_instance(&mut self, default_status: &MemberStatus)
+      -> Result<ServersetMember, CodecError> {
+    let mut service_endpoint = None;
+    let mut additional_endpoints = HashMap::new();
+    let mut status = None;
+    let mut shard = None;
+    while let Some((field_type, field_id)) = try!(self.read_field_header()) {
+      match field_id {
+        1 => {
+          try!(self.expect(field_type, T_STRUCT, "serviceEndpoint"));
+          service_endpoint = Some(try!(self.read_endpoint()));
+        },
+        2 => {
+          try!(self.expect(field_type, T_MAP, "additionalEndpoints"));
+          let key_type = try!(self.read_byte());
+          let value_type = try!(self.read_byte());
+          try!(self.expect(key_type, T_STRING, "additionalEndpoints key"));
+          try!(self.expect(value_type, T_STRUCT, "additionalEndpoints value"));
+          for _ in 0..try!(self.read_size()) {
+            let name = try!(self.read_string());
+            additional_endpoints.insert(name, try!(self.read_endpoint()));
+          }
+        },
+        3 => {
+          try!(self.expect(field_type, T_I32, "status"));
+          status = Some(status_from_value(try!(self.read_i32())));
+        },
+        4 => {
+          try!(self.expect(field_type, T_I32, "shard"));
+          shard = Some(try!(self.read_i32()) as u32);
+        },
+        _ => try!(self.skip(field_type)),
+      }
+    }
+    match service_endpoint {
+      Some(service_endpoint) => Ok(ServersetMember{
+        serviceEndpoint: service_endpoint,
+        additionalEndpoints: additional_endpoints,
+        status: status.unwrap_or_else(|| default_status.clone()),
+        shard: shard,
+        weight: None,
+        extra_fields: HashMap::new(),
+      }),
+      None => Err(CodecError::Malformed("ServiceInstance without serviceEndpoint"
+          .to_string())),
+    }
+  }
+}
+
+
+fn write_i32(out: &mut Vec<u8>, value: i32) {
+  out.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+      value as u8]);
+}
+
+fn write_field_header(out: &mut Vec<u8>, field_type: u8, field_id: i16) {
+  out.push(field_type);
+  out.extend_from_slice(&[(field_id >> 8) as u8, field_id as u8]);
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+  write_i32(out, value.len() as i32);
+  out.extend_from_slice(value.as_bytes());
+}
+
+fn write_endpoint(out: &mut Vec<u8>, endpoint: &ServiceEndpoint) {
+  write_field_header(out, T_STRING, 1);
+  write_string(out, &endpoint.host);
+  write_field_header(out, T_I32, 2);
+  write_i32(out, endpoint.port as i32);
+  out.push(T_STOP);
+}
@@ -0,0 +1,58 @@
+extern crate discotech_zookeeper;
+
+use discotech_zookeeper::{Acl, CreateMode, Stat, ZkResult, ZooKeeper};
+
+
+// The ZooKeeper operations a Serverset reads its members and registers them with, so
+// that something other than a live session, such as testing::MockZk, can stand in for
+// one. Each behaves as the ZooKeeper method of the same name; `watch` sets a watch whose
+// notifications go to whatever watcher the client was connected with.
+//
+// Serversets hold their client as an Arc<ZkClient>, as they do their codec, rather than
+// taking it as a type parameter, so Serverset and ServersetManager stay the same types
+// whichever client is used. Pass one to Serverset::with_client or
+// ServersetManager::with_client; another client crate, even an async one, can be
+// adapted by implementing these methods over it, blocking on its futures if need be.
+pub trait ZkClient: Send + Sync {
+  fn exists(&self, path: &str, watch: bool) -> ZkResult<Stat>;
+
+  fn get_data(&self, path: &str, watch: bool) -> ZkResult<(Vec<u8>, Stat)>;
+
+  fn get_children(&self, path: &str, watch: bool) -> ZkResult<Vec<String>>;
+
+  // Returns the path created, which for a sequential mode has its counter appended.
+  fn create(&self, path: &str, data: Vec<u8>, acl: Vec<Acl>, mode: CreateMode)
+      -> ZkResult<String>;
+
+  // Replaces the data if the ZNode is at `version`, or whatever its version if -1.
+  fn set_data(&self, path: &str, data: Vec<u8>, version: i32) -> ZkResult<Stat>;
+
+  fn delete(&self, path: &str, version: i32) -> ZkResult<()>;
+}
+
+impl ZkClient for ZooKeeper {
+  fn exists(&self, path: &str, watch: bool) -> ZkResult<Stat> {
+    ZooKeeper::exists(self, path, watch)
+  }
+
+  fn get_data(&self, path: &str, watch: bool) -> ZkResult<(Vec<u8>, Stat)> {
+    ZooKeeper::get_data(self, path, watch)
+  }
+
+  fn get_children(&self, path: &str, watch: bool) -> ZkResult<Vec<String>> {
+    ZooKeeper::get_children(self, path, watch)
+  }
+
+  fn create(&self, path: &str, data: Vec<u8>, acl: Vec<Acl>, mode: CreateMode)
+      -> ZkResult<String> {
+    ZooKeeper::create(self, path, data, acl, mode)
+  }
+
+  fn set_data(&self, path: &str, data: Vec<u8>, version: i32) -> ZkResult<Stat> {
+    ZooKeeper::set_data(self, path, data, version)
+  }
+
+  fn delete(&self, path: &str, version: i32) -> ZkResult<()> {
+    ZooKeeper::delete(self, path, version)
+  }
+}
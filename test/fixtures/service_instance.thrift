@@ -0,0 +1,23 @@
+// The Twitter commons serverset IDL the thrift_member*.bin fixtures are laid out from.
+namespace java com.twitter.thrift
+
+struct Endpoint {
+  1: string host
+  2: i32 port
+}
+
+enum Status {
+  DEAD = 0,
+  STARTING = 1,
+  ALIVE = 2,
+  STOPPING = 3,
+  STOPPED = 4,
+  WARNING = 5
+}
+
+struct ServiceInstance {
+  1: Endpoint serviceEndpoint
+  2: map<string, Endpoint> additionalEndpoints
+  3: Status status
+  4: optional i32 shard
+}
@@ -1,39 +1,2232 @@
 extern crate discotech;
+extern crate discotech_zookeeper;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "prometheus")]
+extern crate prometheus;
+
+use discotech::backoff::jittered;
+use discotech::picker::DEFAULT_VIRTUAL_NODES;
+use discotech::serverset::{join_znode, shard_id};
+use discotech::{BackoffPolicy, BackoffState, CodecError, ConfigError,
+    ConsistentHashPicker, DiscoError, DiscoveryBackend, FileBackend, HasStatus,
+    HostPortCodec, JsonCodec, MemberCodec, MemberStatus, Metrics, MetricsSink,
+    MockServerset, MockZk, Serverset, ServersetEvent, ServersetListener, ServersetManager,
+    ServersetMember, ServersetRegistry, ServiceEndpoint, SinkMetrics, DiscoConfig,
+    RegistrationAcl, RingKey, StatusFilter, TypedJsonCodec, ValidationReport, WaitTimeout,
+    WeightedPicker, ZkClient, ZkServer, ZkServers, parse_servers, read_config};
+use discotech_zookeeper::{Acl, CreateMode, Stat, ZkError};
 
-use discotech::{Serverset, DiscoConfig, read_config};
+#[cfg(feature = "thrift")]
+use discotech::ThriftMemberCodec;
+#[cfg(feature = "async")]
+use discotech::AsyncServerset;
+#[cfg(feature = "async")]
+use futures::StreamExt;
+#[cfg(feature = "prometheus")]
+use discotech::PrometheusSink;
 
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fs::{self, File};
+use std::io;
+#[cfg(feature = "debug-server")]
+use std::io::Read;
+use std::io::Write;
+#[cfg(feature = "debug-server")]
+use std::net::{SocketAddr, TcpStream};
+#[cfg(feature = "toml")]
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+static LOGGING: Once = ONCE_INIT;
 
 fn initialize_logging() {
-  let root = log4rs::config::Root::builder(log::LogLevelFilter::Debug)
-    .appender("stderr".to_string());
-  let console = Box::new(log4rs::appender::ConsoleAppender::builder().build());
-  let config = log4rs::config::Config::builder(root.build())
-    .appender(log4rs::config::Appender::builder("stderr".to_string(), console).build());
-  log4rs::init_config(config.build().unwrap()).unwrap();
+  LOGGING.call_once(|| {
+    let root = log4rs::config::Root::builder(log::LogLevelFilter::Debug)
+      .appender("stderr".to_string());
+    let console = Box::new(log4rs::appender::ConsoleAppender::builder().build());
+    let config = log4rs::config::Config::builder(root.build())
+      .appender(log4rs::config::Appender::builder("stderr".to_string(), console).build());
+    log4rs::init_config(config.build().unwrap()).unwrap();
+  });
+}
+
+fn test_config() -> DiscoConfig {
+  let config_file_loc = match env::var("DISCO_CONF") {
+    Err(_) => panic!("Please set the DISCO_CONF environment variable"),
+    Ok(location) => location,
+  };
+  match read_config(config_file_loc) {
+    Err(reason) => panic!("Unable to read configuration; bailing: {}", reason),
+    Ok(config) => config,
+  }
+}
+
+// A configuration that needs no live ensemble, since the client connects lazily.
+fn local_config() -> DiscoConfig {
+  DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap()
+}
+
+// A ZooKeeper client whose every operation fails with ConnectionLoss straight away, for
+// tests of an unreachable ensemble, which a live client would block on instead.
+fn unreachable_zk() -> Arc<MockZk> {
+  let zk = MockZk::new();
+  zk.set_connected(false);
+  Arc::new(zk)
+}
+
+fn member(host: &str, port: u16, status: MemberStatus) -> ServersetMember {
+  ServersetMember{
+    serviceEndpoint: ServiceEndpoint{host: host.to_string(), port: port},
+    additionalEndpoints: HashMap::new(),
+    status: status,
+    shard: None,
+    weight: None,
+    extra_fields: HashMap::new(),
+  }
+}
+
+// Stands in for a reconciliation pass, replacing the members of `serverset`.
+fn set_members(serverset: &Serverset, members: Vec<(&str, ServersetMember)>) {
+  *serverset.members.write().unwrap() = Arc::new(members.into_iter()
+    .map(|(member_znode, member)| (member_znode.to_string(), member))
+    .collect());
 }
 
 fn initialize(config: DiscoConfig) {
   initialize_logging();
-  let serverset = *Box::new(Serverset::new(config));
-  debug!("THINGS");
-  serverset.update_members();
+  let poll_ms = config.zookeeper_poll_ms as u64;
+  let serverset = Arc::new(Serverset::new(config).unwrap());
+  let watcher = serverset.watch();
+  thread::sleep(Duration::from_millis(poll_ms));
   for member in serverset.members.read().unwrap().iter() {
     debug!("Member: {:?}", member);
   }
+  watcher.stop().unwrap();
 }
 
 #[test]
 fn integration() {
-  let config_file_loc = match env::var("DISCO_CONF") {
-    Err(_) => panic!("Please set the DISCO_CONF environment variable"),
-    Ok(location) => location,
+  initialize(test_config());
+}
+
+#[test]
+fn watch_concurrent_reads() {
+  initialize_logging();
+  let config = test_config();
+  let poll_ms = config.zookeeper_poll_ms as u64;
+  let serverset = Arc::new(Serverset::new(config).unwrap());
+  let watcher = serverset.watch();
+  // Reads members from this thread while the watcher thread keeps writing them.
+  for _ in 0..10 {
+    let member_count = serverset.members.read().unwrap().len();
+    debug!("Observed {} members", member_count);
+    thread::sleep(Duration::from_millis(poll_ms / 2 + 1));
+  }
+  watcher.stop().unwrap();
+}
+
+#[test]
+fn dropping_watch_handle_stops_the_watch_loop() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Arc::new(Serverset::new(config).unwrap());
+  drop(serverset.watch());
+  // The loop has exited and let go of its reference, so dropping ours closes the session.
+  assert_eq!(Arc::strong_count(&serverset), 1);
+}
+
+#[test]
+fn manager_vends_one_serverset_per_znode() {
+  let manager = ServersetManager::new(local_config()).unwrap();
+  let api = manager.serverset("/aurora/prod/api").unwrap();
+  let web = manager.serverset("/aurora/prod/web").unwrap();
+  assert!(Arc::ptr_eq(&api, &manager.serverset("/aurora/prod/api").unwrap()));
+  assert!(!Arc::ptr_eq(&api, &web));
+  set_members(&api, vec![("member_0000000001", member("10.0.0.1", 9090,
+      MemberStatus::Alive))]);
+  assert!(web.members().is_empty());
+}
+
+// Counts the operations made through it, standing in for a ZooKeeper client other than
+// discotech_zookeeper's.
+struct CountedZk {
+  zk: MockZk,
+  operations: AtomicUsize,
+}
+
+impl CountedZk {
+  fn count(&self) {
+    self.operations.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+impl ZkClient for CountedZk {
+  fn exists(&self, path: &str, watch: bool) -> Result<Stat, ZkError> {
+    self.count();
+    self.zk.exists(path, watch)
+  }
+
+  fn get_data(&self, path: &str, watch: bool) -> Result<(Vec<u8>, Stat), ZkError> {
+    self.count();
+    self.zk.get_data(path, watch)
+  }
+
+  fn get_children(&self, path: &str, watch: bool) -> Result<Vec<String>, ZkError> {
+    self.count();
+    self.zk.get_children(path, watch)
+  }
+
+  fn create(&self, path: &str, data: Vec<u8>, acl: Vec<Acl>, mode: CreateMode)
+      -> Result<String, ZkError> {
+    self.count();
+    self.zk.create(path, data, acl, mode)
+  }
+
+  fn set_data(&self, path: &str, data: Vec<u8>, version: i32) -> Result<Stat, ZkError> {
+    self.count();
+    self.zk.set_data(path, data, version)
+  }
+
+  fn delete(&self, path: &str, version: i32) -> Result<(), ZkError> {
+    self.count();
+    self.zk.delete(path, version)
+  }
+}
+
+#[test]
+fn managers_share_any_zookeeper_client() {
+  let client = Arc::new(CountedZk{
+    zk: MockZk::new(),
+    operations: AtomicUsize::new(0),
+  });
+  for path in &["/aurora", "/aurora/prod", "/aurora/prod/api"] {
+    client.zk.create(path, Vec::new(), Vec::new(), CreateMode::Persistent).unwrap();
+  }
+  let member_data = serde_json::to_vec(&member("10.0.0.1", 9090, MemberStatus::Alive))
+    .unwrap();
+  client.zk.create("/aurora/prod/api/member_", member_data, Vec::new(),
+      CreateMode::EphemeralSequential).unwrap();
+  let manager = ServersetManager::with_client(client.clone(), local_config()).unwrap();
+  let api = manager.serverset("/aurora/prod/api").unwrap();
+  assert_eq!(api.members()["member_0000000000"].serviceEndpoint.host, "10.0.0.1");
+  assert!(client.operations.load(Ordering::SeqCst) > 0);
+  // Members registered through the manager's Serversets go through the client too.
+  let handle = api.register(member("10.0.0.2", 9090, MemberStatus::Alive)).unwrap();
+  assert_eq!(handle.znode(), "/aurora/prod/api/member_0000000001");
+}
+
+#[test]
+fn managers_keep_nested_serversets_apart() {
+  let zk = Arc::new(MockZk::new());
+  let manager = ServersetManager::with_client(zk.clone(), local_config()).unwrap();
+  let outer = manager.serverset("/svc/a").unwrap();
+  let nested = manager.serverset("/x/svc/a").unwrap();
+  let _outer_handle = outer.register(member("10.0.0.1", 9090, MemberStatus::Alive))
+    .unwrap();
+  let handle = nested.register(member("10.0.0.2", 9090, MemberStatus::Alive)).unwrap();
+  assert_eq!(handle.znode(), "/x/svc/a/member_0000000000");
+  outer.refresh().unwrap();
+  nested.refresh().unwrap();
+  // The outer Serverset has a member of the same name, which it must not take for the
+  // nested one and write over it with.
+  outer.set_status(&handle.znode(), MemberStatus::Stopping).unwrap();
+  let (member_data, _) = zk.get_data(&handle.znode(), false).unwrap();
+  let member: ServersetMember = serde_json::from_slice(&member_data).unwrap();
+  assert_eq!(member.serviceEndpoint.host, "10.0.0.2");
+  assert_eq!(member.status, MemberStatus::Stopping);
+  assert_eq!(outer.members()["member_0000000000"].serviceEndpoint.host, "10.0.0.1");
+}
+
+#[test]
+fn registries_look_up_serversets_by_name() {
+  let registry = ServersetRegistry::new(local_config()).unwrap();
+  let watch = registry.watch();
+  let user = registry.add("user-service", "/services/user/prod").unwrap();
+  let user_alias = registry.add("users", "/services/user/prod").unwrap();
+  assert!(Arc::ptr_eq(&user, &user_alias));
+  registry.add("web", "/services/web/prod").unwrap();
+  let mut names = registry.names();
+  names.sort();
+  assert_eq!(names, vec!["user-service", "users", "web"]);
+  assert!(Arc::ptr_eq(&registry.get("user-service").unwrap(), &user));
+  assert!(registry.get("billing").is_none());
+
+  // The ZNode stays watched, and its Serverset shared, until no name refers to it.
+  assert!(registry.remove("user-service").is_some());
+  assert!(Arc::ptr_eq(&registry.add("user-service", "/services/user/prod").unwrap(),
+      &user));
+  registry.remove("user-service");
+  registry.remove("users");
+  assert!(!Arc::ptr_eq(&registry.add("user-service", "/services/user/prod").unwrap(),
+      &user));
+  assert!(registry.remove("billing").is_none());
+  watch.stop().unwrap();
+}
+
+struct CountingMetrics {
+  reconcile_errors: AtomicUsize,
+  missing_znodes: AtomicUsize,
+}
+
+impl Metrics for CountingMetrics {
+  fn on_reconcile_error(&self, reason: &DiscoError) {
+    self.reconcile_errors.fetch_add(1, Ordering::SeqCst);
+    match *reason {
+      DiscoError::MissingZnode(_) => {
+        self.missing_znodes.fetch_add(1, Ordering::SeqCst);
+      },
+      _ => (),
+    }
+  }
+}
+
+#[test]
+fn metrics_observe_failed_reconciliations() {
+  let metrics = Arc::new(CountingMetrics{
+    reconcile_errors: AtomicUsize::new(0),
+    missing_znodes: AtomicUsize::new(0),
+  });
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .metrics(metrics.clone())
+    .build()
+    .unwrap();
+  // ZooKeeper cannot be reached, so the first reconciliation of a vended Serverset
+  // fails, and not because the Serverset ZNode is missing.
+  let manager = ServersetManager::with_client(unreachable_zk(), config).unwrap();
+  manager.serverset("/discovery/test").unwrap();
+  assert_eq!(metrics.reconcile_errors.load(Ordering::SeqCst), 1);
+  assert_eq!(metrics.missing_znodes.load(Ordering::SeqCst), 0);
+}
+
+// Records every measurement it receives but timings, whose values vary, by name only.
+struct RecordingSink(Mutex<Vec<String>>);
+
+impl MetricsSink for RecordingSink {
+  fn gauge(&self, name: &str, value: f64) {
+    self.0.lock().unwrap().push(format!("{} = {}", name, value));
+  }
+
+  fn counter(&self, name: &str, delta: u64) {
+    self.0.lock().unwrap().push(format!("{} += {}", name, delta));
+  }
+
+  fn timing(&self, name: &str, _duration: Duration) {
+    self.0.lock().unwrap().push(name.to_string());
+  }
+}
+
+#[test]
+fn metrics_sinks_see_each_pass_and_membership_change() {
+  let sink = Arc::new(RecordingSink(Mutex::new(Vec::new())));
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .metrics(Arc::new(SinkMetrics::new(sink.clone())))
+    .build()
+    .unwrap();
+  let serverset = MockServerset::with_config(config).unwrap();
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  serverset.add_member("member_0000000002",
+      member("10.0.0.2", 9090, MemberStatus::Alive));
+  serverset.remove_member("member_0000000001");
+  let pass = |changes: &[&str], member_count: usize| {
+    let mut emitted = vec!["discotech_syncs_started_total += 1".to_string()];
+    emitted.extend(changes.iter().map(|change| format!("{} += 1", change)));
+    emitted.push("discotech_sync_duration_seconds".to_string());
+    emitted.push("discotech_syncs_completed_total += 1".to_string());
+    emitted.push(format!("discotech_members = {}", member_count));
+    emitted
   };
-  match read_config(config_file_loc) {
-    Err(reason) => panic!("Unable to read configuration; bailing: {}", reason),
-    Ok(config) => initialize(config),
+  let mut expected = pass(&["discotech_members_added_total"], 1);
+  expected.extend(pass(&["discotech_members_added_total"], 2));
+  expected.extend(pass(&["discotech_members_removed_total"], 1));
+  assert_eq!(*sink.0.lock().unwrap(), expected);
+
+  // ZooKeeper cannot be reached, so the pass fails.
+  let sink = Arc::new(RecordingSink(Mutex::new(Vec::new())));
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .metrics(Arc::new(SinkMetrics::new(sink.clone())))
+    .build()
+    .unwrap();
+  let serverset = Serverset::with_client(unreachable_zk(), config).unwrap();
+  assert!(serverset.refresh().is_err());
+  assert_eq!(*sink.0.lock().unwrap(), vec!["discotech_syncs_started_total += 1",
+      "discotech_sync_duration_seconds", "discotech_syncs_failed_total += 1"]);
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus_sinks_register_metrics_as_they_are_reported() {
+  let sink = Arc::new(PrometheusSink::new(prometheus::Registry::new()));
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .metrics(Arc::new(SinkMetrics::new(sink.clone())))
+    .build()
+    .unwrap();
+  let serverset = MockServerset::with_config(config).unwrap();
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  let families = sink.registry().gather();
+  let mut names: Vec<&str> = families.iter().map(|family| family.get_name()).collect();
+  names.sort();
+  assert_eq!(names, vec!["discotech_members", "discotech_members_added_total",
+      "discotech_sync_duration_seconds", "discotech_syncs_completed_total",
+      "discotech_syncs_started_total"]);
+}
+
+#[test]
+fn duplicate_endpoints_are_detected() {
+  let serverset = Serverset::new(local_config()).unwrap();
+  set_members(&serverset, vec![
+    ("member_0000000001", member("10.0.0.1", 9090, MemberStatus::Alive)),
+    ("member_0000000002", member("10.0.0.2", 9090, MemberStatus::Alive)),
+  ]);
+  assert!(serverset.duplicate_endpoints().is_empty());
+  set_members(&serverset, vec![
+    ("member_0000000001", member("10.0.0.1", 9090, MemberStatus::Alive)),
+    ("member_0000000002", member("10.0.0.1", 9090, MemberStatus::Alive)),
+    ("member_0000000003", member("10.0.0.1", 9090, MemberStatus::Starting)),
+    ("member_0000000004", member("10.0.0.1", 9091, MemberStatus::Alive)),
+  ]);
+  assert_eq!(serverset.duplicate_endpoints(), vec![ServiceEndpoint{
+    host: "10.0.0.1".to_string(),
+    port: 9090,
+  }]);
+}
+
+#[test]
+fn members_can_be_looked_up_by_znode_and_endpoint() {
+  let serverset = Serverset::new(local_config()).unwrap();
+  let mut admin = member("10.0.0.1", 9090, MemberStatus::Alive);
+  admin.additionalEndpoints.insert("http-admin".to_string(),
+      ServiceEndpoint{host: "10.0.0.1".to_string(), port: 9990});
+  set_members(&serverset, vec![
+    ("member_0000000001", admin.clone()),
+    ("member_0000000002", member("10.0.0.2", 9090, MemberStatus::Alive)),
+  ]);
+  assert_eq!(serverset.get_member("member_0000000001"), Some(admin));
+  assert_eq!(serverset.get_member("member_0000000003"), None);
+  assert!(serverset.contains_endpoint("10.0.0.2", 9090));
+  assert!(serverset.contains_endpoint("10.0.0.1", 9990));
+  assert!(!serverset.contains_endpoint("10.0.0.2", 9990));
+  assert_eq!(serverset.endpoints_named("http-admin"),
+      vec![ServiceEndpoint{host: "10.0.0.1".to_string(), port: 9990}]);
+  assert!(serverset.endpoints_named("thrift").is_empty());
+}
+
+#[test]
+fn members_can_be_filtered_by_predicate() {
+  let serverset = Serverset::new(local_config()).unwrap();
+  let mut heavy = member("10.1.0.1", 9090, MemberStatus::Alive);
+  heavy.weight = Some(10);
+  set_members(&serverset, vec![
+    ("member_0000000001", heavy.clone()),
+    ("member_0000000002", member("10.2.0.1", 9090, MemberStatus::Alive)),
+  ]);
+  assert_eq!(serverset.filter(|member| member.weight() > 1), vec![heavy]);
+  let in_10 = serverset.filter(|member| member.serviceEndpoint.host.starts_with("10."));
+  assert_eq!(in_10.len(), 2);
+  assert!(serverset.filter(|_| false).is_empty());
+}
+
+#[test]
+fn pickers_cycle_through_live_members() {
+  let serverset = MockServerset::new();
+  let mut thrift = member("10.0.0.2", 9090, MemberStatus::Alive);
+  thrift.additionalEndpoints.insert("thrift".to_string(),
+      ServiceEndpoint{host: "10.0.0.2".to_string(), port: 9091});
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  serverset.add_member("member_0000000002", thrift);
+  serverset.add_member("member_0000000003", member("10.0.0.3", 9090, MemberStatus::Dead));
+  let picker = serverset.picker();
+  let hosts: Vec<String> = (0..4)
+    .map(|_| picker.next_endpoint().unwrap().host)
+    .collect();
+  assert_eq!(hosts, vec!["10.0.0.1", "10.0.0.2", "10.0.0.1", "10.0.0.2"]);
+  assert_eq!(picker.next_additional("thrift").unwrap().port, 9091);
+  assert!(picker.next_additional("http").is_none());
+  serverset.set_member_status("member_0000000001", MemberStatus::Dead);
+  assert_eq!(picker.next_endpoint().unwrap().host, "10.0.0.2");
+  serverset.remove_member("member_0000000002");
+  assert!(picker.next_endpoint().is_none());
+}
+
+#[test]
+fn consistent_hashing_only_remaps_keys_of_departed_members() {
+  let serverset = MockServerset::new();
+  for i in 0..10 {
+    serverset.add_member(&format!("member_{:010}", i),
+        member(&format!("10.0.0.{}", i), 9090, MemberStatus::Alive));
+  }
+  let picker = ConsistentHashPicker::new(serverset.serverset().clone());
+  let keys: Vec<String> = (0..10000).map(|i| format!("user-{}", i)).collect();
+  let before: Vec<String> = keys.iter()
+    .map(|key| picker.pick(key.as_bytes()).unwrap().serviceEndpoint.host)
+    .collect();
+  serverset.remove_member("member_0000000000");
+  let mut remapped = 0;
+  for (key, host) in keys.iter().zip(before.iter()) {
+    let picked = picker.pick(key.as_bytes()).unwrap().serviceEndpoint.host;
+    if picked != *host {
+      // Only keys held by the removed member may move.
+      assert_eq!(host, "10.0.0.0");
+      remapped += 1;
+    }
+  }
+  // About a tenth of the keys lived on the removed member.
+  assert!(remapped > 500 && remapped < 2000, "remapped {} keys", remapped);
+}
+
+#[test]
+fn endpoint_keyed_rings_give_restarted_instances_their_keys_back() {
+  let serverset = MockServerset::new();
+  for i in 0..10 {
+    serverset.add_member(&format!("member_{:010}", i),
+        member(&format!("10.0.0.{}", i), 9090, MemberStatus::Alive));
+  }
+  let picker = ConsistentHashPicker::with_ring_key(serverset.serverset().clone(),
+      RingKey::Endpoint, DEFAULT_VIRTUAL_NODES);
+  let keys: Vec<String> = (0..10000).map(|i| format!("user-{}", i)).collect();
+  let before: Vec<String> = keys.iter()
+    .map(|key| picker.pick_for_key(key.as_bytes()).unwrap().host)
+    .collect();
+  serverset.remove_member("member_0000000000");
+  assert!(keys.iter().all(|key| picker.pick_for_key(key.as_bytes()).unwrap().host !=
+      "10.0.0.0"));
+
+  // The removed instance comes back under a new ZNode and takes back its keys.
+  serverset.add_member("member_0000000010",
+      member("10.0.0.0", 9090, MemberStatus::Alive));
+  for (key, host) in keys.iter().zip(before.iter()) {
+    assert_eq!(picker.pick_for_key(key.as_bytes()).unwrap().host, *host);
+  }
+  let owner = picker.pick(b"user-0").unwrap();
+  assert_eq!(owner.serviceEndpoint.host, before[0]);
+}
+
+#[test]
+fn consistent_hashing_maps_keys_the_same_across_releases() {
+  let serverset = MockServerset::new();
+  for i in 1..4 {
+    serverset.add_member(&format!("member_{:010}", i),
+        member(&format!("10.0.0.{}", i), 9090, MemberStatus::Alive));
+  }
+  let by_znode = ConsistentHashPicker::new(serverset.serverset().clone());
+  let by_endpoint = ConsistentHashPicker::with_ring_key(serverset.serverset().clone(),
+      RingKey::Endpoint, DEFAULT_VIRTUAL_NODES);
+  // Computed independently from the ring's specified hash; any change here reshuffles
+  // the keys of every deployed client.
+  let owners = [("user-0", "10.0.0.1", "10.0.0.2"), ("user-1", "10.0.0.3", "10.0.0.1"),
+      ("user-2", "10.0.0.2", "10.0.0.3"), ("user-3", "10.0.0.1", "10.0.0.1"),
+      ("user-4", "10.0.0.3", "10.0.0.1"), ("user-5", "10.0.0.1", "10.0.0.2"),
+      ("session-42", "10.0.0.2", "10.0.0.2"), ("", "10.0.0.3", "10.0.0.3")];
+  for &(key, znode_host, endpoint_host) in owners.iter() {
+    assert_eq!(by_znode.pick_for_key(key.as_bytes()).unwrap().host, znode_host,
+        "key {:?}", key);
+    assert_eq!(by_endpoint.pick_for_key(key.as_bytes()).unwrap().host, endpoint_host,
+        "key {:?}", key);
+  }
+}
+
+#[test]
+fn pick_weighted_follows_member_weights() {
+  let serverset = MockServerset::new();
+  assert_eq!(serverset.pick_weighted(), None);
+  let mut heavy = member("10.0.0.1", 9090, MemberStatus::Alive);
+  heavy.weight = Some(3);
+  let mut drained = member("10.0.0.2", 9090, MemberStatus::Alive);
+  drained.weight = Some(0);
+  serverset.add_member("member_0000000001", heavy);
+  serverset.add_member("member_0000000002", drained);
+  serverset.add_member("member_0000000003",
+      member("10.0.0.3", 9090, MemberStatus::Alive));
+  let mut picks = HashMap::new();
+  for _ in 0..4000 {
+    *picks.entry(serverset.pick_weighted().unwrap().host).or_insert(0) += 1;
+  }
+  assert!(!picks.contains_key("10.0.0.2"));
+  // The weight-3 member should get about three times the traffic of the default weight 1.
+  let ratio = picks["10.0.0.1"] as f64 / picks["10.0.0.3"] as f64;
+  assert!(ratio > 2.0 && ratio < 4.5, "unexpected ratio {}", ratio);
+
+  let weighted: ServersetMember = serde_json::from_str(r#"{"status": "ALIVE",
+      "serviceEndpoint": {"host": "10.0.0.1", "port": 9090}, "weight": 5}"#).unwrap();
+  assert_eq!(weighted.weight(), 5);
+  assert_eq!(member("10.0.0.1", 9090, MemberStatus::Alive).weight(), 1);
+}
+
+#[test]
+fn weighted_pickers_match_the_weight_distribution() {
+  let serverset = MockServerset::new();
+  let picker = WeightedPicker::new(serverset.serverset().clone());
+  assert_eq!(picker.pick(), None);
+  let mut drained = member("10.0.0.1", 9090, MemberStatus::Alive);
+  drained.weight = Some(0);
+  serverset.add_member("member_0000000001", drained);
+  assert_eq!(picker.pick(), None);
+
+  let mut canary = member("10.0.0.2", 9090, MemberStatus::Alive);
+  canary.weight = Some(1);
+  let mut stable = member("10.0.0.3", 9090, MemberStatus::Alive);
+  stable.weight = Some(9);
+  serverset.add_member("member_0000000002", canary);
+  serverset.add_member("member_0000000003", stable);
+  let picks = 100000;
+  let canary_picks = (0..picks)
+    .filter(|_| picker.pick().unwrap().host == "10.0.0.2")
+    .count();
+  let canary_share = canary_picks as f64 / picks as f64;
+  assert!(canary_share > 0.09 && canary_share < 0.11, "canary share {}", canary_share);
+}
+
+#[test]
+fn alive_members_excludes_other_statuses() {
+  let serverset = Serverset::new(local_config()).unwrap();
+  set_members(&serverset, vec![
+    ("member_0000000001", member("10.0.0.1", 9090, MemberStatus::Alive)),
+    ("member_0000000002", member("10.0.0.2", 9090, MemberStatus::Dead)),
+    ("member_0000000003", member("10.0.0.3", 9090, MemberStatus::Stopping)),
+  ]);
+  let alive_members = serverset.alive_members();
+  assert_eq!(alive_members.len(), 1);
+  assert_eq!(alive_members[0].serviceEndpoint.host, "10.0.0.1");
+}
+
+#[test]
+fn members_kept_out_by_the_status_filter_stay_observable() {
+  let serverset = MockServerset::new();
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  serverset.add_member("member_0000000002",
+      member("10.0.0.2", 9090, MemberStatus::Stopping));
+  serverset.add_member("member_0000000003", member("10.0.0.3", 9090, MemberStatus::Dead));
+  assert_eq!(serverset.len(), 1);
+  assert_eq!(serverset.pick_random().unwrap().host, "10.0.0.1");
+  assert_eq!(serverset.members_by_status("STOPPING"),
+      vec![member("10.0.0.2", 9090, MemberStatus::Stopping)]);
+  assert_eq!(serverset.members_by_status("alive").len(), 1);
+
+  serverset.set_member_status("member_0000000001", MemberStatus::Dead);
+  assert!(serverset.is_empty());
+  assert_eq!(serverset.members_by_status("DEAD").len(), 2);
+  serverset.remove_member("member_0000000002");
+  assert!(serverset.members_by_status("STOPPING").is_empty());
+  // Filters match status names ignoring case, just as members_by_status does.
+  serverset.set_status_filter(StatusFilter::new(&["dead".to_string()]));
+  serverset.refresh().unwrap();
+  assert_eq!(serverset.len(), 2);
+}
+
+#[test]
+fn status_filters_match_names_ignoring_case() {
+  let filter = StatusFilter::new(&["alive".to_string(), "Draining".to_string()]);
+  assert!(filter.admits(&MemberStatus::Alive));
+  assert!(filter.admits(&MemberStatus::Unknown("DRAINING".to_string())));
+  assert!(!filter.admits(&MemberStatus::Stopping));
+  assert_eq!(StatusFilter::new(&["ALL".to_string()]), StatusFilter::All);
+}
+
+#[test]
+fn lowercase_member_statuses_count_as_known_statuses() {
+  assert_eq!(MemberStatus::from("alive"), MemberStatus::Alive);
+  assert_eq!(MemberStatus::from("Stopping"), MemberStatus::Stopping);
+  assert_eq!(MemberStatus::from("draining"),
+      MemberStatus::Unknown("draining".to_string()));
+  let zk = MockZk::new();
+  for path in &["/discovery", "/discovery/test"] {
+    zk.create(path, Vec::new(), Vec::new(), CreateMode::Persistent).unwrap();
+  }
+  let announced = [("member_0000000001", "10.0.0.1", "alive"),
+      ("member_0000000002", "10.0.0.2", "stopping")];
+  for &(member_znode, host, status) in announced.iter() {
+    let member_data = format!(r#"{{"status": "{}",
+        "serviceEndpoint": {{"host": "{}", "port": 9090}}}}"#, status, host);
+    zk.create(&format!("/discovery/test/{}", member_znode), member_data.into_bytes(),
+        Vec::new(), CreateMode::Persistent).unwrap();
+  }
+  let serverset = Serverset::with_client(Arc::new(zk), local_config()).unwrap();
+  serverset.refresh().unwrap();
+  // The default filter admits ALIVE members, which a lowercase "alive" is.
+  assert_eq!(serverset.len(), 1);
+  let alive_members = serverset.alive_members();
+  assert_eq!(alive_members.len(), 1);
+  assert_eq!(alive_members[0].status, MemberStatus::Alive);
+  assert_eq!(serverset.pick_random().unwrap().host, "10.0.0.1");
+  serverset.wait_until_ready(1, Duration::from_millis(100)).unwrap();
+
+  serverset.set_status_filter(StatusFilter::new(&["STOPPING".to_string()]));
+  serverset.refresh().unwrap();
+  assert_eq!(serverset.len(), 1);
+  assert_eq!(serverset.members_by_status("STOPPING")[0].serviceEndpoint.host,
+      "10.0.0.2");
+}
+
+#[test]
+fn additional_endpoints_are_grouped_by_name() {
+  let serverset = Serverset::new(local_config()).unwrap();
+  let mut thrift = member("10.0.0.1", 9090, MemberStatus::Alive);
+  thrift.additionalEndpoints.insert("thrift".to_string(), ServiceEndpoint{
+    host: "10.0.0.1".to_string(),
+    port: 9091,
+  });
+  let mut http = member("10.0.0.2", 9090, MemberStatus::Alive);
+  http.additionalEndpoints.insert("http".to_string(), ServiceEndpoint{
+    host: "10.0.0.2".to_string(),
+    port: 8080,
+  });
+  let mut dead = member("10.0.0.3", 9090, MemberStatus::Dead);
+  dead.additionalEndpoints.insert("admin".to_string(), ServiceEndpoint{
+    host: "10.0.0.3".to_string(),
+    port: 9990,
+  });
+  set_members(&serverset, vec![
+    ("member_0000000001", thrift.clone()),
+    ("member_0000000002", http),
+    ("member_0000000003", dead),
+  ]);
+  let mut names: Vec<String> =
+      serverset.additional_endpoint_names().into_iter().collect();
+  names.sort();
+  assert_eq!(names, vec!["http".to_string(), "thrift".to_string()]);
+  assert_eq!(serverset.members_with("thrift"), vec![thrift]);
+  assert!(serverset.members_with("admin").is_empty());
+}
+
+#[test]
+fn service_endpoints_resolve_ipv4_and_ipv6_literals() {
+  let ipv4 = ServiceEndpoint::new("127.0.0.1", 9090).unwrap();
+  assert_eq!(ipv4.to_socket_addrs().unwrap(), vec!["127.0.0.1:9090".parse().unwrap()]);
+  let ipv6 = ServiceEndpoint::new("[::1]", 9090).unwrap();
+  assert_eq!(ipv6.host, "::1");
+  assert_eq!(ipv6.to_socket_addrs().unwrap(), vec!["[::1]:9090".parse().unwrap()]);
+  assert!(ServiceEndpoint::new("", 9090).is_err());
+  assert!(ServiceEndpoint::new("  ", 9090).is_err());
+  assert_eq!(ipv4.addr(), "127.0.0.1:9090");
+  assert_eq!(ipv6.addr(), "[::1]:9090");
+
+  let serverset = Serverset::new(local_config()).unwrap();
+  set_members(&serverset, vec![
+    ("member_0000000001", member("127.0.0.1", 9090, MemberStatus::Alive)),
+    ("member_0000000002", member("", 9090, MemberStatus::Alive)),
+    ("member_0000000003", member("127.0.0.2", 9090, MemberStatus::Dead)),
+  ]);
+  assert_eq!(serverset.resolved_endpoints(), vec!["127.0.0.1:9090".parse().unwrap()]);
+}
+
+#[test]
+fn endpoints_can_be_collected_into_sets() {
+  let endpoints: HashSet<ServiceEndpoint> = vec![
+    member("10.0.0.1", 9090, MemberStatus::Alive).serviceEndpoint,
+    member("10.0.0.1", 9090, MemberStatus::Dead).serviceEndpoint,
+    member("10.0.0.2", 9090, MemberStatus::Alive).serviceEndpoint,
+  ].into_iter().collect();
+  assert_eq!(endpoints.len(), 2);
+  assert!(member("10.0.0.1", 9090, MemberStatus::Alive) !=
+      member("10.0.0.1", 9090, MemberStatus::Dead));
+}
+
+#[test]
+fn snapshot_copies_members_out_of_the_lock() {
+  let serverset = Serverset::new(local_config()).unwrap();
+  assert!(serverset.is_empty());
+  set_members(&serverset, vec![
+    ("member_0000000002", member("10.0.0.2", 9090, MemberStatus::Starting)),
+    ("member_0000000001", member("10.0.0.1", 9090, MemberStatus::Alive)),
+  ]);
+  let mut snapshot = serverset.snapshot();
+  let published = serverset.members();
+  // Neither view blocks or is changed by a later update.
+  set_members(&serverset, vec![
+    ("member_0000000001", member("10.0.0.1", 9090, MemberStatus::Alive)),
+  ]);
+  assert_eq!(published.len(), 2);
+  snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+  assert_eq!(snapshot.len(), 2);
+  assert_eq!(snapshot[0].0, "member_0000000001");
+  assert_eq!(snapshot[1].1.status, MemberStatus::Starting);
+  assert_eq!(serverset.len(), 1);
+  assert!(!serverset.is_empty());
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn watch_async_yields_the_current_members() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Arc::new(Serverset::new(config).unwrap());
+  let alive = member("10.0.0.1", 9090, MemberStatus::Alive);
+  set_members(&serverset, vec![("member_0000000001", alive.clone())]);
+  let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  let mut members = serverset.watch_async();
+  assert_eq!(runtime.block_on(members.next()), Some(vec![alive.clone()]));
+  assert_eq!(runtime.block_on(serverset.pick_random_async()),
+      Some(alive.serviceEndpoint));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_serversets_publish_each_change() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let mock = MockServerset::with_config(config).unwrap();
+  let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build()
+    .unwrap();
+  let _entered = runtime.enter();
+  let serverset = AsyncServerset::from_serverset(mock.serverset().clone());
+  let mut changes = serverset.changes();
+  assert!(serverset.members().is_empty());
+  mock.add_member("member_0000000001", member("10.0.0.1", 9090, MemberStatus::Alive));
+  assert_eq!(runtime.block_on(serverset.wait_for_members(1, Duration::from_secs(5)))
+      .unwrap(), 1);
+  runtime.block_on(changes.changed()).unwrap();
+  assert_eq!(changes.borrow().len(), 1);
+  assert_eq!(serverset.members().len(), 1);
+  // Dropping the AsyncServerset stops its task, which closes the channel.
+  drop(serverset);
+  runtime.block_on(tokio::task::yield_now());
+  assert!(runtime.block_on(changes.changed()).is_err());
+}
+
+// Sends a GET for `path` to the debug server, returning the status code and body.
+#[cfg(feature = "debug-server")]
+fn debug_get(addr: SocketAddr, path: &str) -> (u16, String) {
+  let mut stream = TcpStream::connect(addr).unwrap();
+  write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+  let mut response = String::new();
+  stream.read_to_string(&mut response).unwrap();
+  let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+  let body = response.splitn(2, "\r\n\r\n").nth(1).unwrap().to_string();
+  (status, body)
+}
+
+#[cfg(feature = "debug-server")]
+#[test]
+fn debug_server_serves_members_and_health() {
+  let mock = MockServerset::new();
+  let server = mock.serve_debug("127.0.0.1:0".parse().unwrap()).unwrap();
+  let addr = server.local_addr();
+  // Nothing has been read yet, so there is no pass to vouch for the members.
+  assert_eq!(debug_get(addr, "/health").0, 503);
+  mock.add_member("member_0000000001", member("10.0.0.1", 9090, MemberStatus::Alive));
+  let (status, body) = debug_get(addr, "/members");
+  assert_eq!(status, 200);
+  let members: HashMap<String, ServersetMember> = serde_json::from_str(&body).unwrap();
+  assert_eq!(members, *mock.members());
+  let (status, body) = debug_get(addr, "/members/member_0000000001");
+  assert_eq!(status, 200);
+  let member: ServersetMember = serde_json::from_str(&body).unwrap();
+  assert_eq!(member.serviceEndpoint.host, "10.0.0.1");
+  assert_eq!(debug_get(addr, "/members/member_0000000002").0, 404);
+  assert_eq!(debug_get(addr, "/health").0, 200);
+  assert_eq!(debug_get(addr, "/metrics").0, 404);
+  server.stop().unwrap();
+  assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn wait_until_ready_times_out_without_enough_members() {
+  let serverset = Serverset::new(local_config()).unwrap();
+  set_members(&serverset, vec![("member_0000000001", member("10.0.0.1", 9090,
+      MemberStatus::Alive))]);
+  assert!(serverset.wait_until_ready(1, Duration::from_millis(0)).is_ok());
+  match serverset.wait_until_ready(2, Duration::from_millis(50)) {
+    Err(DiscoError::NotReady(1, 2)) => (),
+    other => panic!("expected NotReady, got {:?}", other),
+  }
+}
+
+#[test]
+fn new_synced_surfaces_a_failed_first_reconciliation() {
+  // ZooKeeper cannot be reached, so the reconciliation fails.
+  let unsynced = Serverset::with_client(unreachable_zk(), local_config()).unwrap();
+  assert!(unsynced.synced(true).is_err());
+  let unsynced = Serverset::with_client(unreachable_zk(), local_config()).unwrap();
+  let serverset = unsynced.synced(false).unwrap();
+  assert!(serverset.is_empty());
+  assert!(serverset.last_successful_reconcile().is_none());
+  assert!(!serverset.is_healthy(Duration::from_secs(60)));
+}
+
+#[test]
+fn static_fallback_serves_while_discovery_is_down() {
+  let fallback = vec![ServiceEndpoint::new("10.9.0.1", 9090).unwrap()];
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .static_fallback(fallback.clone())
+    .build()
+    .unwrap();
+  // ZooKeeper cannot be reached, so the first reconciliation fails.
+  let serverset = Serverset::with_client(unreachable_zk(), config.clone()).unwrap()
+    .synced(false)
+    .unwrap();
+  assert!(serverset.using_fallback());
+  assert_eq!(serverset.pick_random(), Some(fallback[0].clone()));
+  assert_eq!(serverset.alive_members(),
+      vec![member("10.9.0.1", 9090, MemberStatus::Alive)]);
+  set_members(&serverset, vec![("member_0000000001", member("10.0.0.1", 9090,
+      MemberStatus::Alive))]);
+  assert!(!serverset.using_fallback());
+  assert_eq!(serverset.pick_random().unwrap().host, "10.0.0.1");
+
+  // A healthy Serverset without members serves none.
+  let serverset = MockServerset::with_config(config).unwrap();
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  serverset.remove_member("member_0000000001");
+  assert!(!serverset.using_fallback());
+  assert_eq!(serverset.pick_random(), None);
+}
+
+#[test]
+fn failed_reconciliations_keep_known_members() {
+  let serverset = Arc::new(Serverset::with_client(unreachable_zk(), local_config())
+    .unwrap());
+  let alive = member("10.0.0.1", 9090, MemberStatus::Alive);
+  set_members(&serverset, vec![("member_0000000001", alive.clone())]);
+  // ZooKeeper cannot be reached, so listing the Serverset's children fails.
+  let watch = serverset.watch();
+  thread::sleep(Duration::from_millis(50));
+  watch.stop().unwrap();
+  assert_eq!(serverset.snapshot(), vec![("member_0000000001".to_string(), alive)]);
+}
+
+// Records the membership changes it is notified of.
+struct RecordingListener(Arc<Mutex<Vec<String>>>);
+
+impl ServersetListener for RecordingListener {
+  fn on_join(&self, znode: &str, _member: &ServersetMember) {
+    self.0.lock().unwrap().push(format!("join {}", znode));
+  }
+
+  fn on_leave(&self, znode: &str) {
+    self.0.lock().unwrap().push(format!("leave {}", znode));
+  }
+
+  fn on_update(&self, znode: &str, member: &ServersetMember) {
+    self.0.lock().unwrap().push(format!("update {} {:?}", znode, member.status));
+  }
+}
+
+#[test]
+fn listeners_and_events_follow_membership_changes() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .status_filter(vec!["ALIVE".to_string(), "STOPPING".to_string()])
+    .build()
+    .unwrap();
+  let serverset = MockServerset::with_config(config).unwrap();
+  let notified = Arc::new(Mutex::new(Vec::new()));
+  serverset.subscribe(Box::new(RecordingListener(notified.clone())));
+  let events = serverset.events();
+  let changes = Arc::new(AtomicUsize::new(0));
+  let counted = changes.clone();
+  serverset.on_change(move |_| {
+    counted.fetch_add(1, Ordering::SeqCst);
+  });
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  // A member re-listed unchanged notifies no one.
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  assert!(serverset.set_member_status("member_0000000001", MemberStatus::Stopping));
+  assert!(serverset.remove_member("member_0000000001"));
+  assert!(!serverset.remove_member("member_0000000001"));
+  assert_eq!(*notified.lock().unwrap(), vec![
+    "join member_0000000001".to_string(),
+    "update member_0000000001 Stopping".to_string(),
+    "leave member_0000000001".to_string(),
+  ]);
+  // on_change only runs for passes that add or remove members.
+  assert_eq!(changes.load(Ordering::SeqCst), 2);
+  let changes: Vec<String> = events.try_iter()
+    .filter_map(|event| match event {
+      ServersetEvent::MemberAdded(member_znode, _) =>
+          Some(format!("added {}", member_znode)),
+      ServersetEvent::MemberChanged(member_znode, _) =>
+          Some(format!("changed {}", member_znode)),
+      ServersetEvent::MemberRemoved(member_znode) =>
+          Some(format!("removed {}", member_znode)),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(changes, vec!["added member_0000000001", "changed member_0000000001",
+      "removed member_0000000001"]);
+}
+
+#[test]
+fn wait_for_members_counts_members_of_any_status() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .status_filter(vec!["all".to_string()])
+    .build()
+    .unwrap();
+  let serverset = MockServerset::with_config(config).unwrap();
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  serverset.add_member("member_0000000002",
+      member("10.0.0.2", 9090, MemberStatus::Starting));
+  assert_eq!(serverset.wait_for_members(2, Duration::from_millis(0)).unwrap(), 2);
+  match serverset.wait_for_members(3, Duration::from_millis(50)) {
+    Err(WaitTimeout{members: 2, required: 3}) => (),
+    other => panic!("expected WaitTimeout, got {:?}", other),
+  }
+}
+
+#[test]
+fn poll_jitter_varies_the_interval_within_bounds() {
+  let interval = Duration::from_millis(1000);
+  assert_eq!(jittered(interval, 0.0), interval);
+  for _ in 0..100 {
+    let delay = jittered(interval, 0.2);
+    assert!(delay >= Duration::from_millis(800) && delay <= Duration::from_millis(1200),
+        "{:?} is outside the jitter", delay);
+  }
+  let invalid = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .poll_jitter(1.0)
+    .build();
+  match invalid {
+    Err(ConfigError::InvalidValue("poll_jitter", _)) => (),
+    other => panic!("expected an invalid poll_jitter, got {:?}", other),
+  }
+}
+
+#[test]
+fn backoff_delays_grow_to_their_cap() {
+  let policy = BackoffPolicy{
+    initial: Duration::from_millis(100),
+    multiplier: 2.0,
+    max: Duration::from_millis(1000),
+    jitter: 0.2,
+  };
+  let delays: Vec<u64> = (0..6)
+    .map(|failures| policy.delay(failures).as_secs() * 1000 +
+        policy.delay(failures).subsec_nanos() as u64 / 1000000)
+    .collect();
+  assert_eq!(delays, vec![0, 100, 200, 400, 800, 1000]);
+  assert_eq!(policy.delay(1000), policy.max);
+  assert_eq!(local_config().backoff_policy(), BackoffPolicy{
+    initial: Duration::from_millis(100),
+    multiplier: 2.0,
+    max: Duration::from_secs(30),
+    jitter: 0.2,
+  });
+  let invalid = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .backoff(100, 2.0, 1000, 1.5)
+    .build();
+  match invalid {
+    Err(ConfigError::InvalidValue("backoff_jitter", _)) => (),
+    other => panic!("expected an invalid backoff_jitter, got {:?}", other),
+  }
+}
+
+#[test]
+fn failed_passes_back_off_polling() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .backoff(200, 2.0, 10000, 0.0)
+    .build()
+    .unwrap();
+  // ZooKeeper cannot be reached, so every pass fails.
+  let zk = unreachable_zk();
+  let serverset = Arc::new(Serverset::with_client(zk.clone(), config.clone()).unwrap()
+    .synced(false)
+    .unwrap());
+  assert_eq!(serverset.poll_backoff(), BackoffState{
+    consecutive_failures: 1,
+    delay: Duration::from_millis(200),
+  });
+  let watch = serverset.watch();
+  // The watch loop's first pass fails too, after which polls wait 400ms rather than 10ms.
+  thread::sleep(Duration::from_millis(150));
+  watch.stop().unwrap();
+  assert_eq!(serverset.poll_backoff(), BackoffState{
+    consecutive_failures: 2,
+    delay: Duration::from_millis(400),
+  });
+
+  let started = Instant::now();
+  assert!(Serverset::with_client_retry(zk, config, Duration::from_millis(0)).is_err());
+  assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn stopping_a_watch_does_not_wait_out_the_poll_interval() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(60000)
+    .zookeeper_fallback_poll_ms(60000)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Arc::new(Serverset::new(config).unwrap());
+  let watch = serverset.watch();
+  // Let the loop finish its first pass and start waiting.
+  thread::sleep(Duration::from_millis(50));
+  let started = Instant::now();
+  watch.stop().unwrap();
+  assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+// Lists whatever members the test last stored.
+struct ListedMembers(Mutex<Vec<(String, Vec<u8>)>>);
+
+impl DiscoveryBackend for ListedMembers {
+  fn list_members(&self) -> Result<Vec<(String, Vec<u8>)>, DiscoError> {
+    Ok(self.0.lock().unwrap().clone())
+  }
+}
+
+#[test]
+fn backends_supply_members_in_place_of_zookeeper() {
+  let alive = br#"{"serviceEndpoint": {"host": "10.0.0.1", "port": 9090},
+    "additionalEndpoints": {}, "status": "ALIVE"}"#;
+  let backend = Arc::new(ListedMembers(Mutex::new(vec![
+    ("member_0000000001".to_string(), alive.to_vec()),
+  ])));
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Arc::new(Serverset::with_backend(config, backend.clone(),
+    Arc::new(JsonCodec::new(MemberStatus::Alive))).unwrap());
+  let watch = serverset.watch();
+  assert_eq!(serverset.wait_for_members(1, Duration::from_secs(5)).unwrap(), 1);
+  let listed = member("10.0.0.1", 9090, MemberStatus::Alive);
+  assert_eq!(serverset.snapshot(), vec![("member_0000000001".to_string(), listed)]);
+  backend.0.lock().unwrap().clear();
+  let started = Instant::now();
+  while !serverset.members().is_empty() {
+    assert!(started.elapsed() < Duration::from_secs(5), "member was never removed");
+    thread::sleep(Duration::from_millis(10));
+  }
+  watch.stop().unwrap();
+  let endpoint = ServiceEndpoint::new("10.0.0.2", 9090).unwrap();
+  match serverset.announce(endpoint, HashMap::new()) {
+    Err(DiscoError::Unsupported(_)) => (),
+    other => panic!("expected Unsupported, got {:?}", other),
   }
 }
+
+// Lists its members until told to fail.
+struct FlakyMembers(Vec<(String, Vec<u8>)>, AtomicBool);
+
+impl DiscoveryBackend for FlakyMembers {
+  fn list_members(&self) -> Result<Vec<(String, Vec<u8>)>, DiscoError> {
+    if self.1.load(Ordering::SeqCst) {
+      return Err(DiscoError::Backend("unavailable".to_string()))
+    }
+    Ok(self.0.clone())
+  }
+}
+
+#[test]
+fn stale_members_are_cleared_when_failing_closed() {
+  let fail_closed = || DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .backoff(10, 1.0, 10, 0.0)
+    .fail_open_on_stale(false);
+  assert!(fail_closed().build().is_err(), "stale_after_ms should be required");
+  let config = fail_closed().stale_after_ms(50).build().unwrap();
+  let alive = br#"{"serviceEndpoint": {"host": "10.0.0.1", "port": 9090},
+    "additionalEndpoints": {}, "status": "ALIVE"}"#;
+  let backend = Arc::new(FlakyMembers(
+      vec![("member_0000000001".to_string(), alive.to_vec())], AtomicBool::new(false)));
+  let serverset = Arc::new(Serverset::with_backend(config, backend.clone(),
+    Arc::new(JsonCodec::new(MemberStatus::Alive))).unwrap());
+  assert!(serverset.is_stale(Duration::from_secs(60)));
+  assert!(serverset.last_sync_time().is_none());
+  let watch = serverset.watch();
+  let started = Instant::now();
+  while serverset.is_stale(Duration::from_secs(60)) {
+    assert!(started.elapsed() < Duration::from_secs(5), "never synced");
+    thread::sleep(Duration::from_millis(10));
+  }
+  assert_eq!(serverset.len(), 1);
+  assert!(serverset.last_sync_time().unwrap() <= SystemTime::now());
+
+  backend.1.store(true, Ordering::SeqCst);
+  wait_for_exactly(&serverset, 0);
+  assert!(serverset.is_stale(Duration::from_millis(50)));
+  backend.1.store(false, Ordering::SeqCst);
+  assert_eq!(serverset.wait_for_members(1, Duration::from_secs(5)).unwrap(), 1);
+  watch.stop().unwrap();
+}
+
+// Lists no members, counting how often it is asked to.
+struct CountedListings(AtomicUsize);
+
+impl DiscoveryBackend for CountedListings {
+  fn list_members(&self) -> Result<Vec<(String, Vec<u8>)>, DiscoError> {
+    self.0.fetch_add(1, Ordering::SeqCst);
+    Ok(Vec::new())
+  }
+}
+
+#[test]
+fn poll_intervals_change_at_runtime() {
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let backend = Arc::new(CountedListings(AtomicUsize::new(0)));
+  let serverset = Arc::new(Serverset::with_backend(config, backend.clone(),
+    Arc::new(JsonCodec::new(MemberStatus::Alive))).unwrap());
+  assert_eq!(serverset.poll_interval(), Duration::from_millis(10));
+  let watch = serverset.watch();
+  let started = Instant::now();
+  while backend.0.load(Ordering::SeqCst) < 5 {
+    assert!(started.elapsed() < Duration::from_secs(5), "polls never ran");
+    thread::sleep(Duration::from_millis(10));
+  }
+  serverset.set_poll_interval(60000);
+  assert_eq!(serverset.poll_interval(), Duration::from_secs(60));
+  // The pass already waiting may still run, but none after it.
+  thread::sleep(Duration::from_millis(50));
+  let listings = backend.0.load(Ordering::SeqCst);
+  thread::sleep(Duration::from_millis(200));
+  assert_eq!(backend.0.load(Ordering::SeqCst), listings);
+  watch.stop().unwrap();
+}
+
+#[test]
+fn refresh_reports_what_the_pass_changed() {
+  let alive = br#"{"serviceEndpoint": {"host": "10.0.0.1", "port": 9090},
+    "additionalEndpoints": {}, "status": "ALIVE"}"#;
+  let backend = Arc::new(ListedMembers(Mutex::new(vec![
+    ("member_0000000001".to_string(), alive.to_vec()),
+    ("member_0000000002".to_string(), b"not json".to_vec()),
+  ])));
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Serverset::with_backend(config, backend.clone(),
+    Arc::new(JsonCodec::new(MemberStatus::Alive))).unwrap();
+  let report = serverset.refresh().unwrap();
+  assert_eq!(report.added, vec!["member_0000000001".to_string()]);
+  assert!(report.changed.is_empty() && report.removed.is_empty());
+  match report.failed.as_slice() {
+    [(ref member_znode, DiscoError::UndecodableMember(..))] =>
+        assert_eq!(member_znode, "member_0000000002"),
+    other => panic!("expected member_0000000002 to fail, got {:?}", other),
+  }
+  backend.0.lock().unwrap().clear();
+  let report = serverset.refresh().unwrap();
+  assert_eq!(report.removed, vec!["member_0000000001".to_string()]);
+  assert!(report.added.is_empty() && report.failed.is_empty());
+  assert!(serverset.refresh().unwrap().is_empty());
+
+  // ZooKeeper cannot be reached, so the pass fails as a whole.
+  let serverset = Serverset::with_client(unreachable_zk(), local_config()).unwrap();
+  assert!(serverset.refresh().is_err());
+}
+
+// Waits for `serverset` to hold exactly `count` members.
+fn wait_for_exactly(serverset: &Serverset, count: usize) {
+  let started = Instant::now();
+  while serverset.len() != count {
+    assert!(started.elapsed() < Duration::from_secs(5),
+        "have {} members", serverset.len());
+    thread::sleep(Duration::from_millis(10));
+  }
+}
+
+#[test]
+fn file_backends_follow_the_member_file() {
+  let path = env::temp_dir().join("discotech_file_backend.json");
+  let _ = fs::remove_file(&path);
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_poll_ms(10)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let backend = Arc::new(FileBackend::new(&path));
+  let serverset = Arc::new(Serverset::with_backend(config, backend,
+    Arc::new(JsonCodec::new(MemberStatus::Alive))).unwrap());
+  let events = serverset.events();
+  let watch = serverset.watch();
+  // A missing file holds no members.
+  assert!(serverset.wait_until_ready(1, Duration::from_millis(50)).is_err());
+
+  File::create(&path).unwrap()
+    .write_all(include_bytes!("fixtures/file_backend.json")).unwrap();
+  wait_for_exactly(&serverset, 2);
+  // The member with a malformed port is skipped.
+  assert_eq!(serverset.get_member("member_0000000002").unwrap().shard, Some(1));
+  assert!(serverset.get_member("member_0000000003").is_none());
+
+  let remaining = br#"{"member_0000000002": {
+    "serviceEndpoint": {"host": "10.0.0.2", "port": 9090}, "status": "ALIVE"}}"#;
+  File::create(&path).unwrap().write_all(remaining).unwrap();
+  wait_for_exactly(&serverset, 1);
+  fs::remove_file(&path).unwrap();
+  wait_for_exactly(&serverset, 0);
+  watch.stop().unwrap();
+  let mut changes: Vec<String> = events.try_iter()
+    .filter_map(|event| match event {
+      ServersetEvent::MemberAdded(member_znode, _) => Some(format!("+{}", member_znode)),
+      ServersetEvent::MemberRemoved(member_znode) => Some(format!("-{}", member_znode)),
+      _ => None,
+    })
+    .collect();
+  // The members of one pass are applied in no particular order.
+  changes.sort();
+  assert_eq!(changes, vec!["+member_0000000001", "+member_0000000002",
+      "-member_0000000001", "-member_0000000002"]);
+}
+
+#[test]
+fn undecodable_members_quote_their_data() {
+  let garbage = br#"{"serviceEndpoint": "10.0.0.1:9090"}"#;
+  let backend = Arc::new(ListedMembers(Mutex::new(vec![
+    ("member_0000000001".to_string(), garbage.to_vec()),
+  ])));
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .member_payload_log_bytes(20)
+    .build()
+    .unwrap();
+  let serverset = Serverset::with_backend(config, backend,
+    Arc::new(JsonCodec::new(MemberStatus::Alive))).unwrap();
+  let errors = Arc::new(Mutex::new(Vec::new()));
+  let reported = errors.clone();
+  serverset.on_error(move |reason| reported.lock().unwrap().push(reason.to_string()));
+  let serverset = Arc::new(serverset);
+  let watch = serverset.watch();
+  let started = Instant::now();
+  while errors.lock().unwrap().is_empty() {
+    assert!(started.elapsed() < Duration::from_secs(5), "no error was reported");
+    thread::sleep(Duration::from_millis(10));
+  }
+  watch.stop().unwrap();
+  assert!(serverset.members().is_empty());
+  let error = errors.lock().unwrap()[0].clone();
+  assert!(error.contains(r#"data: {"serviceEndpoint": ... (36 bytes)"#), "{}", error);
+  let raw_member_data = serverset.raw_member_data("member_0000000001").unwrap();
+  assert_eq!(raw_member_data.as_bytes(), &garbage[..]);
+  match serverset.raw_member_data("member_0000000002") {
+    Err(DiscoError::MissingZnode(_)) => (),
+    other => panic!("expected MissingZnode, got {:?}", other),
+  }
+}
+
+#[test]
+fn member_ages_reset_whenever_members_are_read() {
+  let serverset = MockServerset::new();
+  assert_eq!(serverset.member_age("member_0000000001"), None);
+  serverset.add_member("member_0000000001",
+      member("10.0.0.1", 9090, MemberStatus::Alive));
+  thread::sleep(Duration::from_millis(50));
+  let age = serverset.member_age("member_0000000001").unwrap();
+  assert!(age >= Duration::from_millis(50), "{:?}", age);
+  // Members the status filter keeps out are still read.
+  serverset.set_member_status("member_0000000001", MemberStatus::Stopping);
+  assert!(serverset.member_age("member_0000000001").unwrap() < age);
+  serverset.remove_member("member_0000000001");
+  assert_eq!(serverset.member_age("member_0000000001"), None);
+}
+
+#[test]
+fn decode_failures_track_the_latest_pass() {
+  let alive = br#"{"serviceEndpoint": {"host": "10.0.0.1", "port": 9090},
+    "additionalEndpoints": {}, "status": "ALIVE"}"#;
+  let backend = Arc::new(ListedMembers(Mutex::new(vec![
+    ("member_0000000001".to_string(), b"\xff\xfe".to_vec()),
+    ("member_0000000002".to_string(), b"{\n  \"serviceEndpoint\"".to_vec()),
+  ])));
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Serverset::with_backend(config, backend.clone(),
+    Arc::new(JsonCodec::new(MemberStatus::Alive))).unwrap();
+  assert!(serverset.decode_failures().is_empty());
+  serverset.refresh().unwrap();
+  let failures = serverset.decode_failures();
+  assert_eq!(failures.len(), 2);
+  assert_eq!(failures[0].0, "member_0000000001");
+  assert!(failures[0].1.starts_with("Could not parse node string"), "{}", failures[0].1);
+  assert_eq!(failures[1].0, "member_0000000002");
+  assert!(failures[1].1.starts_with("Could not parse node JSON"), "{}", failures[1].1);
+  assert!(failures[1].1.ends_with(r#"data: {\n  "serviceEndpoint""#),
+      "{}", failures[1].1);
+
+  // Fixing the announcement clears its failure on the next pass.
+  backend.0.lock().unwrap()[0].1 = alive.to_vec();
+  serverset.refresh().unwrap();
+  let failures = serverset.decode_failures();
+  assert_eq!(failures.len(), 1);
+  assert_eq!(failures[0].0, "member_0000000002");
+  backend.0.lock().unwrap().pop();
+  serverset.refresh().unwrap();
+  assert!(serverset.decode_failures().is_empty());
+}
+
+#[test]
+fn serversets_borrow_an_existing_client() {
+  let client = unreachable_zk();
+  let serverset = Serverset::with_client(client.clone(), local_config()).unwrap();
+  // A borrowed client is taken to be connected until a read finds otherwise.
+  assert!(serverset.is_connected());
+  // ZooKeeper cannot be reached, so the pass fails.
+  assert!(serverset.refresh().is_err());
+  assert!(!serverset.is_connected());
+  drop(serverset);
+  // The client is left to the caller rather than closed.
+  assert_eq!(Arc::strong_count(&client), 1);
+}
+
+#[test]
+fn serversets_read_and_register_members_on_a_mock_zookeeper() {
+  let zk = Arc::new(MockZk::new());
+  let serverset = Serverset::with_client(zk.clone(), local_config()).unwrap();
+  // The Serverset ZNode is only created by the first registration.
+  match serverset.refresh() {
+    Err(DiscoError::MissingZnode(ref znode)) => assert_eq!(znode, "/discovery/test"),
+    other => panic!("expected a missing Serverset ZNode, got {:?}", other),
+  }
+  let first = serverset.register(member("10.0.0.1", 9090, MemberStatus::Alive)).unwrap();
+  let second = serverset.register(member("10.0.0.2", 9090, MemberStatus::Alive)).unwrap();
+  assert_eq!(first.znode(), "/discovery/test/member_0000000000");
+  assert_eq!(second.znode(), "/discovery/test/member_0000000001");
+  let mut report = serverset.refresh().unwrap();
+  // Members are read in parallel, so are added in no particular order.
+  report.added.sort();
+  assert_eq!(report.added, vec!["member_0000000000", "member_0000000001"]);
+  assert_eq!(serverset.members()["member_0000000000"].serviceEndpoint.host, "10.0.0.1");
+  // Status updates are compare-and-set against the version last written.
+  first.set_status(MemberStatus::Alive).unwrap();
+  assert_eq!(zk.exists(&first.znode(), false).unwrap().version, 1);
+  zk.set_data(&first.znode(), Vec::new(), -1).unwrap();
+  match first.set_status(MemberStatus::Alive) {
+    Err(DiscoError::VersionConflict(_)) => (),
+    other => panic!("expected a version conflict, got {:?}", other),
+  }
+  // A member ZNode deleted out from under the Serverset is dropped on the next pass.
+  zk.delete(&second.znode(), -1).unwrap();
+  let report = serverset.refresh().unwrap();
+  assert_eq!(report.removed, vec!["member_0000000001"]);
+  drop(second);
+  first.deregister().unwrap();
+  assert!(zk.get_children("/discovery/test", false).unwrap().is_empty());
+  serverset.refresh().unwrap();
+  assert!(serverset.members().is_empty());
+  zk.set_connected(false);
+  assert!(serverset.refresh().is_err());
+  assert!(!serverset.is_connected());
+}
+
+// Loses its connection as soon as a Serverset's children have been listed, if armed, so
+// that the members' reads fail.
+struct ListThenDisconnectZk {
+  zk: MockZk,
+  armed: AtomicBool,
+}
+
+impl ZkClient for ListThenDisconnectZk {
+  fn exists(&self, path: &str, watch: bool) -> Result<Stat, ZkError> {
+    self.zk.exists(path, watch)
+  }
+
+  fn get_data(&self, path: &str, watch: bool) -> Result<(Vec<u8>, Stat), ZkError> {
+    self.zk.get_data(path, watch)
+  }
+
+  fn get_children(&self, path: &str, watch: bool) -> Result<Vec<String>, ZkError> {
+    let children = self.zk.get_children(path, watch);
+    if self.armed.load(Ordering::SeqCst) {
+      self.zk.set_connected(false);
+    }
+    children
+  }
+
+  fn create(&self, path: &str, data: Vec<u8>, acl: Vec<Acl>, mode: CreateMode)
+      -> Result<String, ZkError> {
+    self.zk.create(path, data, acl, mode)
+  }
+
+  fn set_data(&self, path: &str, data: Vec<u8>, version: i32) -> Result<Stat, ZkError> {
+    self.zk.set_data(path, data, version)
+  }
+
+  fn delete(&self, path: &str, version: i32) -> Result<(), ZkError> {
+    self.zk.delete(path, version)
+  }
+}
+
+#[test]
+fn members_that_cannot_be_checked_for_are_reported_as_failed() {
+  let client = Arc::new(ListThenDisconnectZk{
+    zk: MockZk::new(),
+    armed: AtomicBool::new(false),
+  });
+  let serverset = Serverset::with_client(client.clone(), local_config()).unwrap();
+  let handle = serverset.register(member("10.0.0.1", 9090, MemberStatus::Alive)).unwrap();
+  serverset.refresh().unwrap();
+  client.armed.store(true, Ordering::SeqCst);
+  // The existence check fails with ConnectionLoss rather than NoNode, so the member is
+  // neither skipped as missing nor removed.
+  let report = serverset.refresh().unwrap();
+  assert_eq!(report.failed.len(), 1);
+  match report.failed[0] {
+    (ref member_znode, DiscoError::Zk(ZkError::ConnectionLoss)) =>
+        assert_eq!(member_znode, "member_0000000000"),
+    ref other => panic!("expected a lost connection, got {:?}", other),
+  }
+  assert!(serverset.members().contains_key("member_0000000000"));
+  client.armed.store(false, Ordering::SeqCst);
+  client.zk.set_connected(true);
+  // The member's version was forgotten, so it is read again in full.
+  let report = serverset.refresh().unwrap();
+  assert!(report.failed.is_empty());
+  drop(handle);
+}
+
+// Blocks every read of a member's data while stalled, as a session that has stopped
+// answering would, counting the reads begun. Each read also takes at least read_delay.
+struct StalledZk {
+  zk: MockZk,
+  stalled: AtomicBool,
+  reads: AtomicUsize,
+  read_delay: Duration,
+}
+
+impl ZkClient for StalledZk {
+  fn exists(&self, path: &str, watch: bool) -> Result<Stat, ZkError> {
+    self.zk.exists(path, watch)
+  }
+
+  fn get_data(&self, path: &str, watch: bool) -> Result<(Vec<u8>, Stat), ZkError> {
+    self.reads.fetch_add(1, Ordering::SeqCst);
+    thread::sleep(self.read_delay);
+    while self.stalled.load(Ordering::SeqCst) {
+      thread::sleep(Duration::from_millis(10));
+    }
+    self.zk.get_data(path, watch)
+  }
+
+  fn get_children(&self, path: &str, watch: bool) -> Result<Vec<String>, ZkError> {
+    self.zk.get_children(path, watch)
+  }
+
+  fn create(&self, path: &str, data: Vec<u8>, acl: Vec<Acl>, mode: CreateMode)
+      -> Result<String, ZkError> {
+    self.zk.create(path, data, acl, mode)
+  }
+
+  fn set_data(&self, path: &str, data: Vec<u8>, version: i32) -> Result<Stat, ZkError> {
+    self.zk.set_data(path, data, version)
+  }
+
+  fn delete(&self, path: &str, version: i32) -> Result<(), ZkError> {
+    self.zk.delete(path, version)
+  }
+}
+
+#[test]
+fn stalled_member_fetches_time_out_without_piling_up() {
+  let client = Arc::new(StalledZk{
+    zk: MockZk::new(),
+    stalled: AtomicBool::new(true),
+    reads: AtomicUsize::new(0),
+    read_delay: Duration::from_millis(0),
+  });
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_timeout_secs(1)
+    .zookeeper_fetch_concurrency(2)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Serverset::with_client(client.clone(), config).unwrap();
+  let handles: Vec<_> = (1..4)
+    .map(|i| serverset.register(member(&format!("10.0.0.{}", i), 9090,
+        MemberStatus::Alive)).unwrap())
+    .collect();
+  // Both fetches in flight are given up on after a session timeout, along with the
+  // member queued behind them, as no thread is left to fetch it.
+  let started = Instant::now();
+  let report = serverset.refresh().unwrap();
+  assert!(started.elapsed() < Duration::from_millis(1500), "{:?}", started.elapsed());
+  assert_eq!(report.failed.len(), 3);
+  for &(_, ref reason) in report.failed.iter() {
+    match *reason {
+      DiscoError::FetchTimeout(_) => (),
+      ref other => panic!("expected a fetch timeout, got {:?}", other),
+    }
+  }
+  // Both fetch threads are still stuck, so the next pass starts no more of them.
+  let report = serverset.refresh().unwrap();
+  assert_eq!(report.failed.len(), 3);
+  assert_eq!(client.reads.load(Ordering::SeqCst), 2);
+  // Once the stuck fetches finish, their threads exit and passes succeed again.
+  client.stalled.store(false, Ordering::SeqCst);
+  let started = Instant::now();
+  while serverset.len() < 3 {
+    assert!(started.elapsed() < Duration::from_secs(5), "have {} members",
+        serverset.len());
+    thread::sleep(Duration::from_millis(10));
+    serverset.refresh().unwrap();
+  }
+  drop(handles);
+}
+
+#[test]
+fn members_queued_behind_slow_fetches_are_still_admitted() {
+  let client = Arc::new(StalledZk{
+    zk: MockZk::new(),
+    stalled: AtomicBool::new(false),
+    reads: AtomicUsize::new(0),
+    read_delay: Duration::from_millis(300),
+  });
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_timeout_secs(1)
+    .zookeeper_fetch_concurrency(2)
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Serverset::with_client(client.clone(), config).unwrap();
+  let handles: Vec<_> = (1..9)
+    .map(|i| serverset.register(member(&format!("10.0.0.{}", i), 9090,
+        MemberStatus::Alive)).unwrap())
+    .collect();
+  // The pass outlasts the session timeout, but no one fetch does.
+  let started = Instant::now();
+  let report = serverset.refresh().unwrap();
+  assert!(started.elapsed() > Duration::from_secs(1), "{:?}", started.elapsed());
+  assert!(report.failed.is_empty(), "{:?}", report.failed);
+  assert_eq!(serverset.len(), 8);
+  drop(handles);
+}
+
+#[test]
+fn serversets_read_and_register_members_under_a_chroot() {
+  let zk = MockZk::new();
+  for path in &["/apps", "/apps/prod"] {
+    zk.create(path, Vec::new(), Vec::new(), CreateMode::Persistent).unwrap();
+  }
+  let config = DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_chroot("/apps/prod")
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  let serverset = Serverset::with_client(Arc::new(zk.chrooted("/apps/prod")), config)
+    .unwrap();
+  let handle = serverset.register(member("10.0.0.1", 9090, MemberStatus::Alive)).unwrap();
+  // The ZNodes are created under the chroot, but reported relative to it.
+  assert_eq!(handle.znode(), "/discovery/test/member_0000000000");
+  assert!(zk.exists("/apps/prod/discovery/test/member_0000000000", false).is_ok());
+  assert!(zk.exists("/discovery", false).is_err());
+  serverset.refresh().unwrap();
+  assert!(serverset.members().contains_key("member_0000000000"));
+  handle.deregister().unwrap();
+  assert!(zk.get_children("/apps/prod/discovery/test", false).unwrap().is_empty());
+}
+
+#[test]
+fn serverset_reports_disconnected_until_session_established() {
+  // No ensemble is listening, so the lazily-connecting client never reaches one.
+  let serverset = Serverset::new(local_config()).unwrap();
+  assert!(!serverset.is_connected());
+}
+
+#[test]
+fn validation_needs_a_reachable_serverset() {
+  // ZooKeeper cannot be reached, so the Serverset ZNode cannot even be checked for.
+  match discotech::validate_with_client(&*unreachable_zk(), &local_config()) {
+    Err(ref reason) if reason.is_retryable() => (),
+    other => panic!("expected a retryable error, got {:?}", other),
+  }
+  let zk = MockZk::new();
+  for path in &["/discovery", "/discovery/test"] {
+    zk.create(path, Vec::new(), Vec::new(), CreateMode::Persistent).unwrap();
+  }
+  let member_data = serde_json::to_vec(&member("10.0.0.1", 9090, MemberStatus::Alive))
+    .unwrap();
+  zk.create("/discovery/test/member_0000000001", member_data, Vec::new(),
+      CreateMode::Persistent).unwrap();
+  zk.create("/discovery/test/member_0000000002", b"not json".to_vec(), Vec::new(),
+      CreateMode::Persistent).unwrap();
+  let report = discotech::validate_with_client(&zk, &local_config()).unwrap();
+  assert_eq!((report.total, report.parsed, report.failed), (2, 1, 1));
+  assert_eq!(report.errors[0].0, "member_0000000002");
+  let report = ValidationReport{
+    total: 3,
+    parsed: 2,
+    failed: 1,
+    errors: vec![("member_0000000003".to_string(),
+        DiscoError::MissingZnode("/discovery/test/member_0000000003".to_string()))],
+  };
+  assert!(!report.is_valid());
+  assert_eq!(report.to_string(), "3 members, 2 parsed, 1 failed\n  member_0000000003: \
+      Could not find ZNode: /discovery/test/member_0000000003");
+}
+
+#[test]
+fn acl_denials_surface_as_no_auth() {
+  match DiscoError::from_zk("/discovery/test", ZkError::NoAuth) {
+    DiscoError::NoAuth(ref znode) => assert_eq!(znode, "/discovery/test"),
+    other => panic!("Expected NoAuth, got {:?}", other),
+  }
+  assert!(!DiscoError::from_zk("/discovery/test", ZkError::NoAuth).is_retryable());
+  match DiscoError::from_zk("/discovery/test", ZkError::NoNode) {
+    DiscoError::Zk(ZkError::NoNode) => (),
+    other => panic!("Expected NoNode, got {:?}", other),
+  }
+}
+
+#[test]
+fn member_status_round_trips_unknown_values() {
+  let member: ServersetMember = serde_json::from_str(r#"{"serviceEndpoint":
+      {"host": "10.0.0.1", "port": 9090}, "additionalEndpoints": {},
+      "status": "HEALTHY"}"#).unwrap();
+  assert_eq!(member.status, MemberStatus::Unknown("HEALTHY".to_string()));
+  assert!(serde_json::to_string(&member).unwrap().contains(r#""status":"HEALTHY""#));
+  assert_eq!(MemberStatus::from("STOPPING"), MemberStatus::Stopping);
+}
+
+#[test]
+fn member_decodes_aurora_shard() {
+  let with_shard: ServersetMember = serde_json::from_str(r#"{"status": "ALIVE",
+      "additionalEndpoints": {"aurora": {"host": "10.0.0.1", "port": 31044},
+      "http": {"host": "10.0.0.1", "port": 31044}},
+      "serviceEndpoint": {"host": "10.0.0.1", "port": 31044}, "shard": 3}"#).unwrap();
+  assert_eq!(with_shard.shard, Some(3));
+  assert_eq!(with_shard.additionalEndpoints["aurora"].port, 31044);
+  let without_shard: ServersetMember = serde_json::from_str(r#"{"status": "ALIVE",
+      "additionalEndpoints": {"http": {"host": "10.0.0.1", "port": 31044}},
+      "serviceEndpoint": {"host": "10.0.0.1", "port": 31044}}"#).unwrap();
+  assert_eq!(without_shard.shard, None);
+}
+
+#[test]
+fn member_json_round_trips_finagle_format() {
+  let finagle_json = concat!(r#"{"serviceEndpoint":{"host":"10.0.0.1","port":9090},"#,
+      r#""additionalEndpoints":{"http-admin":{"host":"10.0.0.1","port":9990}},"#,
+      r#""status":"ALIVE"}"#);
+  let member: ServersetMember = serde_json::from_str(finagle_json).unwrap();
+  assert_eq!(member.serviceEndpoint.port, 9090);
+  assert_eq!(member.additionalEndpoints["http-admin"].port, 9990);
+  assert_eq!(member.status, MemberStatus::Alive);
+  assert_eq!(serde_json::to_string(&member).unwrap(), finagle_json);
+}
+
+#[test]
+fn member_decoding_tolerates_real_world_shapes() {
+  let finagle = ServersetMember::from_json(include_str!("fixtures/finagle_member.json"),
+      &MemberStatus::Alive).unwrap();
+  assert_eq!(finagle.additionalEndpoints.len(), 1);
+  let aurora = ServersetMember::from_json(include_str!("fixtures/aurora_member.json"),
+      &MemberStatus::Alive).unwrap();
+  assert_eq!(aurora.shard, Some(0));
+  // Extra fields are kept aside, and absent additionalEndpoints and status are defaulted.
+  let curator = ServersetMember::from_json(include_str!("fixtures/curator_member.json"),
+      &MemberStatus::Starting).unwrap();
+  assert_eq!(curator.serviceEndpoint.port, 8080);
+  assert!(curator.additionalEndpoints.is_empty());
+  assert_eq!(curator.status, MemberStatus::Starting);
+  assert_eq!(curator.extra_fields["serviceType"], "DYNAMIC");
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct BuildInfo {
+  datacenter: String,
+  build_sha: String,
+  capabilities: Vec<String>,
+}
+
+#[test]
+fn extra_member_fields_decode_into_custom_types() {
+  let member = ServersetMember::from_json(include_str!("fixtures/extended_member.json"),
+      &MemberStatus::Alive).unwrap();
+  assert_eq!(member.extra_fields_as::<BuildInfo>().unwrap(), BuildInfo{
+    datacenter: "us-east-1".to_string(),
+    build_sha: "3f2a9c1".to_string(),
+    capabilities: vec!["search".to_string(), "autocomplete".to_string()],
+  });
+  // The extra fields survive being written back out.
+  let written = serde_json::to_string(&member).unwrap();
+  let reread = ServersetMember::from_json(&written, &MemberStatus::Alive).unwrap();
+  assert_eq!(reread, member);
+}
+
+// A member as announced with the extended_member fixture's extra fields, typed.
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct BuildMember {
+  serviceEndpoint: ServiceEndpoint,
+  status: MemberStatus,
+  datacenter: String,
+  build_sha: String,
+  capabilities: Vec<String>,
+}
+
+impl HasStatus for BuildMember {
+  fn status(&self) -> &MemberStatus {
+    &self.status
+  }
+
+  fn service_endpoint(&self) -> Option<&ServiceEndpoint> {
+    Some(&self.serviceEndpoint)
+  }
+}
+
+#[test]
+fn serversets_decode_members_into_custom_types() {
+  let zk = Arc::new(MockZk::new());
+  for path in &["/discovery", "/discovery/test"] {
+    zk.create(path, Vec::new(), Vec::new(), CreateMode::Persistent).unwrap();
+  }
+  let member_data = include_bytes!("fixtures/extended_member.json").to_vec();
+  zk.create("/discovery/test/member_0000000000", member_data, Vec::new(),
+      CreateMode::Persistent).unwrap();
+  let serverset: Serverset<BuildMember> = Serverset::with_client_codec(zk.clone(),
+      local_config(), Arc::new(TypedJsonCodec::new())).unwrap();
+  let events = serverset.events();
+  let report = serverset.refresh().unwrap();
+  assert_eq!(report.added, vec!["member_0000000000"]);
+  let expected = BuildMember{
+    serviceEndpoint: ServiceEndpoint::new("10.0.0.4", 9090).unwrap(),
+    status: MemberStatus::Alive,
+    datacenter: "us-east-1".to_string(),
+    build_sha: "3f2a9c1".to_string(),
+    capabilities: vec!["search".to_string(), "autocomplete".to_string()],
+  };
+  assert_eq!(serverset.members()["member_0000000000"], expected);
+  match events.try_recv() {
+    Ok(ServersetEvent::MemberAdded(ref member_znode, ref member)) =>
+        assert_eq!((member_znode.as_str(), member), ("member_0000000000", &expected)),
+    other => panic!("expected the member to be added, got {:?}", other),
+  }
+  // Members are registered in the custom type, and the status filter still applies.
+  let draining = BuildMember{
+    status: MemberStatus::Stopping,
+    ..expected.clone()
+  };
+  let _handle = serverset.register(draining.clone()).unwrap();
+  serverset.refresh().unwrap();
+  assert_eq!(serverset.len(), 1);
+  assert_eq!(serverset.members_by_status("STOPPING"), vec![draining]);
+  serverset.wait_until_ready(1, Duration::from_millis(0)).unwrap();
+}
+
+// A site-specific format storing members as {"ip": ..., "port": ...}.
+struct FlatCodec;
+
+impl MemberCodec for FlatCodec {
+  fn decode(&self, bytes: &[u8]) -> Result<ServersetMember, CodecError> {
+    let flat: serde_json::Value = try!(serde_json::from_slice(bytes));
+    match (flat["ip"].as_str(), flat["port"].as_u64()) {
+      (Some(ip), Some(port)) => Ok(member(ip, port as u16, MemberStatus::Alive)),
+      _ => Err(CodecError::Malformed("expected ip and port".to_string())),
+    }
+  }
+
+  fn encode(&self, member: &ServersetMember) -> Result<Vec<u8>, CodecError> {
+    Ok(format!(r#"{{"ip":"{}","port":{}}}"#, member.serviceEndpoint.host,
+        member.serviceEndpoint.port).into_bytes())
+  }
+}
+
+#[test]
+fn serversets_accept_custom_codecs() {
+  let codec = FlatCodec;
+  let decoded = codec.decode(br#"{"ip": "10.0.0.1", "port": 9090}"#).unwrap();
+  assert_eq!(decoded, member("10.0.0.1", 9090, MemberStatus::Alive));
+  assert_eq!(codec.decode(&codec.encode(&decoded).unwrap()).unwrap(), decoded);
+  match DiscoError::from(codec.decode(br#"{"ip": "10.0.0.1"}"#).unwrap_err()) {
+    DiscoError::Codec(CodecError::Malformed(_)) => (),
+    other => panic!("Expected a malformed member, got {:?}", other),
+  }
+  assert!(Serverset::with_codec(local_config(), Arc::new(FlatCodec)).is_ok());
+}
+
+#[test]
+fn host_port_codec_round_trips_members() {
+  let codec = HostPortCodec;
+  let decoded = codec.decode(b"10.0.0.1:9090\n").unwrap();
+  assert_eq!(decoded.serviceEndpoint, ServiceEndpoint{host: "10.0.0.1".to_string(),
+      port: 9090});
+  assert_eq!(decoded.status, MemberStatus::Alive);
+  let ipv6 = member("::1", 9090, MemberStatus::Alive);
+  let encoded = codec.encode(&ipv6).unwrap();
+  assert_eq!(encoded, b"[::1]:9090".to_vec());
+  assert_eq!(codec.decode(&encoded).unwrap().serviceEndpoint.host, "::1");
+  assert!(codec.decode(b"10.0.0.1").is_err());
+  assert!(codec.decode(b"10.0.0.1:http").is_err());
+}
+
+// The fixtures follow the TBinaryProtocol layout of fixtures/service_instance.thrift.
+#[cfg(feature = "thrift")]
+#[test]
+fn thrift_codec_decodes_service_instances() {
+  let codec = ThriftMemberCodec::new(MemberStatus::Alive);
+  let member = codec.decode(include_bytes!("fixtures/thrift_member.bin")).unwrap();
+  assert_eq!(member.serviceEndpoint, ServiceEndpoint{host: "10.0.0.1".to_string(),
+      port: 9090});
+  assert_eq!(member.additionalEndpoints["http-admin"].port, 9990);
+  assert_eq!(member.status, MemberStatus::Alive);
+  assert_eq!(member.shard, Some(3));
+  assert_eq!(codec.decode(&codec.encode(&member).unwrap()).unwrap(), member);
+  // Fields unknown to this IDL are skipped.
+  let newer = codec.decode(include_bytes!("fixtures/thrift_member_extra_fields.bin"))
+      .unwrap();
+  assert_eq!(newer.serviceEndpoint.port, 8080);
+  assert_eq!(newer.status, MemberStatus::Stopping);
+  assert_eq!(newer.shard, None);
+  let truncated = include_bytes!("fixtures/thrift_member.bin");
+  assert!(codec.decode(&truncated[..40]).is_err());
+}
+
+#[test]
+fn shard_id_parses_member_znode_names() {
+  assert_eq!(shard_id("member_0000000012"), Some(12));
+  assert_eq!(shard_id("member_0000000000"), Some(0));
+  assert_eq!(shard_id("member_"), None);
+  assert_eq!(shard_id("member_00000000x2"), None);
+  assert_eq!(shard_id("instance_0000000012"), None);
+}
+
+#[test]
+fn config_builder_requires_host_and_znode() {
+  assert!(DiscoConfig::builder().zookeeper_host("localhost").build().is_err());
+  assert!(DiscoConfig::builder().serverset_znode("/discovery/test").build().is_err());
+  let config = local_config();
+  assert_eq!(config.zookeeper_port, 2181);
+  assert_eq!(config.zookeeper_poll_ms, 5000);
+  assert_eq!(config.zookeeper_timeout_secs, 10);
+}
+
+#[test]
+fn config_builder_validates_fields() {
+  let builder = || DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test");
+  assert!(DiscoConfig::builder().zookeeper_host("localhost")
+      .serverset_znode("discovery/test").build().is_err());
+  assert!(builder().zookeeper_port(0).build().is_err());
+  assert!(builder().zookeeper_poll_ms(0).build().is_err());
+  assert!(builder().zookeeper_poll_ms(1).build().is_ok());
+  assert!(builder().zookeeper_timeout_secs(0).build().is_err());
+  assert!(builder().zookeeper_fallback_poll_ms(0).build().is_err());
+  assert!(builder().zookeeper_fetch_concurrency(0).build().is_err());
+  for znode in &["/discovery/test/", "/discovery//test", "/discovery/my test", "/"] {
+    let error = builder().serverset_znode(znode).build().unwrap_err().to_string();
+    assert!(error.contains("serverset_znode"), "{}", error);
+  }
+
+  // Configs assembled without the builder are checked when a Serverset is created.
+  let mut config = local_config();
+  config.serverset_znode = "/discovery/test/".to_string();
+  match Serverset::new(config) {
+    Err(DiscoError::Config(ConfigError::InvalidValue("serverset_znode", ref reason))) =>
+        assert_eq!(reason, "must not end with /"),
+    other => panic!("expected an invalid serverset_znode, got {:?}", other.err()),
+  }
+}
+
+#[test]
+fn join_znode_uses_a_single_separator() {
+  assert_eq!(join_znode("/discovery/test", "member_0000000001"),
+      "/discovery/test/member_0000000001");
+  assert_eq!(join_znode("/", "discovery"), "/discovery");
+  assert_eq!(join_znode("/discovery/", "/member_"), "/discovery/member_");
+  assert_eq!(join_znode("/a/", "b"), "/a/b");
+  assert_eq!(join_znode("/a", "b"), "/a/b");
+  assert_eq!(join_znode("a/b/", "c"), "/a/b/c");
+  assert_eq!(join_znode("/a//b", "c//d/"), "/a/b/c/d");
+  assert_eq!(join_znode("", "b"), "/b");
+  assert_eq!(join_znode("/a", ""), "/a");
+  assert_eq!(join_znode("", ""), "/");
+}
+
+#[test]
+fn parse_servers_handles_whitespace_and_missing_ports() {
+  let servers = parse_servers(" zk1:2182, zk2 ,zk3:2181 ").unwrap();
+  assert_eq!(servers, vec![
+    ZkServer{host: "zk1".to_string(), port: 2182},
+    ZkServer{host: "zk2".to_string(), port: 2181},
+    ZkServer{host: "zk3".to_string(), port: 2181},
+  ]);
+  assert!(parse_servers("").is_err());
+  assert!(parse_servers("zk1:2181,,zk2:2181").is_err());
+  assert!(parse_servers("zk1:").is_err());
+  assert!(parse_servers("zk1:port").is_err());
+  assert!(parse_servers("zk1:2181/apps").is_err());
+}
+
+#[test]
+fn config_servers_fall_back_to_single_host() {
+  assert_eq!(local_config().servers().unwrap(),
+      vec![ZkServer{host: "localhost".to_string(), port: 2181}]);
+  let config: DiscoConfig = serde_json::from_str(r#"{
+      "zookeeper_servers": "zk1:2181,zk2:2181", "zookeeper_poll_ms": 1000,
+      "zookeeper_timeout_secs": 15, "serverset_znode": "/discovery/test"}"#).unwrap();
+  assert_eq!(config.servers().unwrap().len(), 2);
+  let config = DiscoConfig::builder()
+    .zookeeper_servers(ZkServers::List(vec![ZkServer{host: "zk1".to_string(),
+        port: 2181}]))
+    .serverset_znode("/discovery/test")
+    .build()
+    .unwrap();
+  assert_eq!(config.servers().unwrap()[0].host, "zk1");
+}
+
+#[test]
+fn config_validates_chroot() {
+  let with_chroot = |chroot: &str| DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .zookeeper_chroot(chroot)
+    .serverset_znode("/discovery/test")
+    .build();
+  assert_eq!(with_chroot("/apps/prod").unwrap().chroot().unwrap(), "/apps/prod");
+  assert_eq!(with_chroot("").unwrap().chroot().unwrap(), "");
+  assert_eq!(local_config().chroot().unwrap(), "");
+  assert!(with_chroot("apps/prod").is_err());
+  assert!(with_chroot("/apps/prod/").is_err());
+  assert!(with_chroot("/").is_err());
+}
+
+#[test]
+fn creator_all_registration_acl_requires_auth() {
+  let builder = || DiscoConfig::builder()
+    .zookeeper_host("localhost")
+    .serverset_znode("/discovery/test")
+    .registration_acl(RegistrationAcl::CreatorAll);
+  assert!(builder().build().is_err());
+  let config = builder().zk_auth("digest", "user:password").build().unwrap();
+  assert_eq!(config.registration_acl().unwrap(), RegistrationAcl::CreatorAll);
+  assert_eq!(local_config().registration_acl().unwrap(), RegistrationAcl::Open);
+  let config: DiscoConfig = serde_json::from_str(r#"{"zookeeper_host": "localhost",
+      "zookeeper_poll_ms": 1000, "zookeeper_timeout_secs": 15,
+      "serverset_znode": "/discovery/test", "zookeeper_auth_scheme": "digest",
+      "zookeeper_auth_credential": "user:password",
+      "registration_acl": "creator_all"}"#).unwrap();
+  assert_eq!(config.zk_auth_scheme, Some("digest".to_string()));
+  assert_eq!(config.registration_acl().unwrap(), RegistrationAcl::CreatorAll);
+}
+
+#[test]
+fn half_set_zk_auth_is_rejected_before_connecting() {
+  let mut config = local_config();
+  config.zk_auth_scheme = Some("digest".to_string());
+  match config.validate() {
+    Err(ConfigError::MissingField("zk_auth_credential")) => (),
+    other => panic!("expected a missing credential, got {:?}", other),
+  }
+  config.zk_auth_scheme = None;
+  config.zk_auth_credential = Some("user:password".to_string());
+  match config.validate() {
+    Err(ConfigError::MissingField("zk_auth_scheme")) => (),
+    other => panic!("expected a missing scheme, got {:?}", other),
+  }
+
+  let path = env::temp_dir().join("discotech_half_set_auth.json");
+  File::create(&path).unwrap().write_all(br#"{"zookeeper_host": "localhost",
+      "zookeeper_poll_ms": 1000, "zookeeper_timeout_secs": 15,
+      "serverset_znode": "/discovery/test", "zk_auth_scheme": "digest"}"#).unwrap();
+  let error = read_config(path.to_str().unwrap().to_string()).unwrap_err().to_string();
+  assert!(error.contains("zk_auth_credential"), "unexpected error {}", error);
+}
+
+#[test]
+fn unparseable_config_files_are_reported_rather_than_panicking() {
+  let path = env::temp_dir().join("discotech_unparseable.json");
+  File::create(&path).unwrap().write_all(b"{\"zookeeper_host\": ").unwrap();
+  let error = read_config(path.to_str().unwrap().to_string()).unwrap_err();
+  assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+  assert!(error.to_string().contains("discotech_unparseable.json"));
+  File::create(&path).unwrap().write_all(br#"{"zookeeper_host": 2181}"#).unwrap();
+  let error = read_config(path.to_str().unwrap().to_string()).unwrap_err();
+  assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn configs_asking_for_tls_are_rejected() {
+  let path = env::temp_dir().join("discotech_tls.json");
+  File::create(&path).unwrap().write_all(br#"{"zookeeper_host": "localhost",
+      "zookeeper_poll_ms": 1000, "zookeeper_timeout_secs": 15,
+      "serverset_znode": "/discovery/test", "zk_tls": true}"#).unwrap();
+  let error = read_config(path.to_str().unwrap().to_string()).unwrap_err().to_string();
+  assert!(error.contains("TLS is not supported"), "unexpected error {}", error);
+
+  env::set_var("DISCOTLSTEST_ZK_HOSTS", "zk1");
+  env::set_var("DISCOTLSTEST_SERVERSET_ZNODE", "/services/foo/prod");
+  assert!(DiscoConfig::from_env_prefixed("DISCOTLSTEST_").is_ok());
+  env::set_var("DISCOTLSTEST_ZK_TLS", "true");
+  match DiscoConfig::from_env_prefixed("DISCOTLSTEST_") {
+    Err(ConfigError::InvalidValue("zk_tls", _)) => (),
+    other => panic!("expected TLS to be rejected, got {:?}", other),
+  }
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn toml_configs_asking_for_tls_are_rejected() {
+  let path = env::temp_dir().join("discotech_tls.toml");
+  // Top-level keys must come before any table.
+  let written = format!("zk_tls_ca_path = \"/etc/zk/ca.pem\"\n{}",
+      toml::Value::try_from(&local_config()).unwrap());
+  File::create(&path).unwrap().write_all(written.as_bytes()).unwrap();
+  match DiscoConfig::from_toml_file(&path) {
+    Err(ConfigError::InvalidValue("zk_tls_ca_path", ref reason)) =>
+        assert!(reason.contains("TLS is not supported")),
+    other => panic!("expected TLS to be rejected, got {:?}", other),
+  }
+}
+
+#[test]
+fn config_reads_prefixed_environment_variables() {
+  // Each test uses its own prefix, as tests share the process environment.
+  env::set_var("DISCOENVTEST_ZK_HOSTS", "zk1, zk2:2182");
+  env::set_var("DISCOENVTEST_ZK_PORT", "2183");
+  env::set_var("DISCOENVTEST_SERVERSET_ZNODE", "/services/foo/prod");
+  env::set_var("DISCOENVTEST_POLL_MS", "250");
+  env::set_var("DISCOENVTEST_STATUS_FILTER", "ALIVE, STARTING");
+  env::set_var("DISCOENVTEST_MEMBER_READ_RETRIES", "0");
+  env::set_var("DISCOENVTEST_MEMBER_PAYLOAD_LOG_BYTES", "64");
+  env::set_var("DISCOENVTEST_BACKOFF_MAX_MS", "5000");
+  let config = DiscoConfig::from_env_prefixed("DISCOENVTEST_").unwrap();
+  assert_eq!(config.servers().unwrap(), vec![
+    ZkServer{host: "zk1".to_string(), port: 2183},
+    ZkServer{host: "zk2".to_string(), port: 2182},
+  ]);
+  assert_eq!(config.serverset_znode, "/services/foo/prod");
+  assert_eq!(config.zookeeper_poll_ms, 250);
+  assert_eq!(config.zookeeper_timeout_secs, 10);
+  assert_eq!(config.member_read_retries, Some(0));
+  assert_eq!(config.member_payload_log_bytes, Some(64));
+  assert_eq!(config.backoff_policy().max, Duration::from_secs(5));
+  assert_eq!(config.status_filter,
+      Some(vec!["ALIVE".to_string(), "STARTING".to_string()]));
+
+  env::set_var("DISCOENVTEST_POLL_MS", "soon");
+  let error = DiscoConfig::from_env_prefixed("DISCOENVTEST_").unwrap_err();
+  assert!(error.to_string().contains("DISCOENVTEST_POLL_MS"));
+  let error = DiscoConfig::from_env_prefixed("DISCOENVUNSET_").unwrap_err();
+  assert!(error.to_string().contains("DISCOENVUNSET_ZK_HOSTS"));
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn config_round_trips_through_toml_files() {
+  let fixture = Path::new("test/fixtures/disco.toml");
+  let config = DiscoConfig::from_toml_file(fixture).unwrap();
+  assert_eq!(config.servers().unwrap(), vec![
+    ZkServer{host: "zk1".to_string(), port: 2181},
+    ZkServer{host: "zk2".to_string(), port: 2182},
+  ]);
+  assert_eq!(config.chroot().unwrap(), "/apps/prod");
+  assert_eq!(config.registration_acl().unwrap(), RegistrationAcl::CreatorAll);
+
+  let path = env::temp_dir().join("discotech_round_trip.toml");
+  let written = toml::Value::try_from(&config).unwrap().to_string();
+  File::create(&path).unwrap().write_all(written.as_bytes()).unwrap();
+  assert_eq!(DiscoConfig::from_toml_file(&path).unwrap(), config);
+
+  // Unknown keys are rejected, naming both the file and the key.
+  let misspelt = written.replace("zookeeper_poll_ms", "zookeeper_pol_ms");
+  File::create(&path).unwrap().write_all(misspelt.as_bytes()).unwrap();
+  let error = DiscoConfig::from_toml_file(&path).unwrap_err().to_string();
+  assert!(error.contains("discotech_round_trip.toml"));
+  assert!(error.contains("zookeeper_pol_ms"));
+
+  // Aliases are accepted as the keys they stand for.
+  let aliased = written.replace("zk_auth_scheme", "zookeeper_auth_scheme");
+  File::create(&path).unwrap().write_all(aliased.as_bytes()).unwrap();
+  assert_eq!(DiscoConfig::from_toml_file(&path).unwrap(), config);
+}
+
+#[test]
+fn config_accepts_statuses_under_either_name() {
+  let config: DiscoConfig = serde_json::from_str(r#"{"zookeeper_host": "localhost",
+      "zookeeper_port": 2181, "zookeeper_poll_ms": 1000, "zookeeper_timeout_secs": 15,
+      "serverset_znode": "/discovery/test",
+      "accepted_statuses": ["HEALTHY", "STARTING"]}"#).unwrap();
+  assert_eq!(config.status_filter,
+      Some(vec!["HEALTHY".to_string(), "STARTING".to_string()]));
+}
+
+// Runs the disco binary, returning its exit code.
+fn disco(args: &[&str]) -> i32 {
+  let status = Command::new(env!("CARGO_BIN_EXE_disco")).args(args).status().unwrap();
+  status.code().unwrap()
+}
+
+#[test]
+fn disco_exits_with_a_code_for_each_failure() {
+  assert_eq!(disco(&[]), 1);
+  assert_eq!(disco(&["list", "--znode", "/discovery/test"]), 1);
+  assert_eq!(disco(&["list", "--zk", "localhost:2181", "--znode", "/discovery/test",
+      "--timeout-secs", "soon"]), 1);
+  assert_eq!(disco(&["frobnicate", "--zk", "localhost:2181", "--znode",
+      "/discovery/test"]), 1);
+  // Nothing is listening, which is given up on at the timeout even if the client would
+  // keep trying to connect.
+  let started = Instant::now();
+  assert_eq!(disco(&["list", "--zk", "127.0.0.1:1", "--znode", "/discovery/test",
+      "--timeout-secs", "1"]), 2);
+  assert_eq!(disco(&["announce", "--zk", "127.0.0.1:1", "--znode", "/discovery/test",
+      "--host", "10.0.0.1", "--port", "9090", "--timeout-secs", "1"]), 2);
+  assert!(started.elapsed() < Duration::from_secs(10));
+}